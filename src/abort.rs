@@ -0,0 +1,191 @@
+//! Early abort of runs against a grid that is broken, instead of running all remaining tests
+//! into the same failure.
+//!
+//! The failure rate is tracked over a rolling window of recently finished tests. Once it exceeds
+//! the threshold the run is aborted like an interrupted one: no further tests are launched and
+//! the tests in flight quit their sessions.
+
+use crate::threshold::parse_failure_rate;
+use anyhow::{bail, Context, Result};
+use humantime::format_duration;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tests that have to finish within the window before the failure rate is meaningful
+const MIN_TESTS: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AbortRule {
+    /// Failure rate in percent above which the run is aborted
+    pub failure_rate: f64,
+    pub window: Duration,
+}
+
+impl AbortRule {
+    /// Reads the threshold from `ABORT_FAILURE_RATE` and the window from `ABORT_WINDOW`
+    /// (default 1m), returns `None` if no threshold is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let failure_rate = match std::env::var("ABORT_FAILURE_RATE") {
+            Ok(value) => parse_failure_rate(&value).context("Invalid ABORT_FAILURE_RATE")?,
+            Err(_) => return Ok(None),
+        };
+        let window =
+            humantime::parse_duration(&std::env::var("ABORT_WINDOW").unwrap_or("1m".into()))
+                .context("Invalid ABORT_WINDOW")?;
+
+        Ok(Some(Self {
+            failure_rate,
+            window,
+        }))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.window.is_zero() {
+            bail!("The window of the failure rate that aborts the run needs a duration");
+        }
+
+        Ok(())
+    }
+}
+
+struct State {
+    /// Finish time and success of the tests within the window, oldest first
+    finished: VecDeque<(Instant, bool)>,
+    tripped: bool,
+}
+
+/// Rolling failure rate of a run, aborting it once it spikes
+pub struct FailureSpike {
+    rule: AbortRule,
+    state: Mutex<State>,
+}
+
+impl FailureSpike {
+    pub fn new(rule: AbortRule) -> Self {
+        Self {
+            rule,
+            state: Mutex::new(State {
+                finished: VecDeque::new(),
+                tripped: false,
+            }),
+        }
+    }
+
+    /// Records a finished test, returns true once the failure rate first exceeds the threshold
+    pub fn record(&self, success: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.tripped {
+            return false;
+        }
+
+        let now = Instant::now();
+        state.finished.push_back((now, success));
+        while let Some((finished, _)) = state.finished.front() {
+            if now.duration_since(*finished) <= self.rule.window {
+                break;
+            }
+            state.finished.pop_front();
+        }
+
+        let total = state.finished.len();
+        if total < MIN_TESTS {
+            return false;
+        }
+
+        let failed = state
+            .finished
+            .iter()
+            .filter(|(_, success)| !success)
+            .count();
+        let rate = failed as f64 * 100.0 / total as f64;
+        if rate <= self.rule.failure_rate {
+            return false;
+        }
+
+        state.tripped = true;
+        log::error!(
+            "{} of the last {} tests failed within {} ({:.1}%, threshold {}%), aborting the run.",
+            failed,
+            total,
+            format_duration(self.rule.window),
+            rate,
+            self.rule.failure_rate
+        );
+
+        true
+    }
+
+    /// Whether the run was aborted
+    pub fn tripped(&self) -> bool {
+        self.state.lock().unwrap().tripped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spike(failure_rate: f64, window: Duration) -> FailureSpike {
+        FailureSpike::new(AbortRule {
+            failure_rate,
+            window,
+        })
+    }
+
+    #[test]
+    fn waits_for_enough_tests() {
+        let spike = spike(50.0, Duration::from_secs(60));
+        for _ in 0..MIN_TESTS - 1 {
+            assert!(!spike.record(false));
+        }
+        assert!(!spike.tripped());
+
+        assert!(spike.record(false));
+        assert!(spike.tripped());
+    }
+
+    #[test]
+    fn trips_only_above_the_threshold() {
+        let spike = spike(50.0, Duration::from_secs(60));
+        for index in 0..MIN_TESTS {
+            assert!(!spike.record(index % 2 == 0));
+        }
+        assert!(!spike.tripped());
+
+        // 6 of 11 tests failed
+        assert!(spike.record(false));
+    }
+
+    #[test]
+    fn trips_once() {
+        let spike = spike(0.0, Duration::from_secs(60));
+        let trips = (0..3 * MIN_TESTS).filter(|_| spike.record(false)).count();
+        assert_eq!(trips, 1);
+        assert!(spike.tripped());
+    }
+
+    #[test]
+    fn forgets_tests_outside_of_the_window() {
+        let spike = spike(50.0, Duration::from_millis(50));
+        for _ in 0..MIN_TESTS - 1 {
+            spike.record(false);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        // The failures left the window, so the run has to fill it again
+        assert!(!spike.record(false));
+        assert!(!spike.tripped());
+    }
+
+    #[test]
+    fn rejects_an_empty_window() {
+        let rule = AbortRule {
+            failure_rate: 10.0,
+            window: Duration::ZERO,
+        };
+        assert!(rule.validate().is_err());
+    }
+}
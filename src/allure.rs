@@ -0,0 +1,233 @@
+//! Results in the format of Allure, written into an `allure-results` directory.
+//!
+//! Every test becomes one `<uuid>-result.json` file, with the variables it captured attached
+//! as JSON so they show up next to the result in the Allure report. Screenshots, page sources
+//! and console logs saved for the test are attached as well. Results are grouped into suites by
+//! browser version and scenario, the grid `report` prints.
+
+use crate::variables::Variables;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TestResult<'a> {
+    uuid: &'a str,
+    history_id: &'a str,
+    name: &'a str,
+    full_name: &'a str,
+    status: &'a str,
+    status_details: StatusDetails<'a>,
+    stage: &'a str,
+    start: u128,
+    stop: u128,
+    labels: Vec<NameValue<'a>>,
+    parameters: Vec<NameValue<'a>>,
+    attachments: Vec<Attachment<'a>>,
+}
+
+#[derive(Serialize)]
+struct StatusDetails<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    /// Passed on a re-run after failing at first
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    flaky: bool,
+}
+
+#[derive(Serialize)]
+struct NameValue<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(Serialize)]
+struct Attachment<'a> {
+    name: &'a str,
+    source: &'a str,
+    #[serde(rename = "type")]
+    mime_type: &'a str,
+}
+
+/// Files saved for a test, attached as `(variable with the path, name, extension, MIME type)`
+const ARTIFACTS: [(&str, &str, &str, &str); 3] = [
+    ("screenshot", "screenshot", "png", "image/png"),
+    ("page_source", "page source", "html", "text/html"),
+    ("console_log", "console", "log", "text/plain"),
+];
+
+/// Outcome of a test in Allure terms
+pub enum Status {
+    Passed,
+    /// The application under test misbehaved
+    Failed,
+    /// The test could not be executed properly, e.g. because no session was available
+    Broken,
+}
+
+pub struct AllureResults {
+    directory: PathBuf,
+    /// Prefix making file names unique across runs writing into the same directory
+    run_id: String,
+    browser: String,
+}
+
+impl AllureResults {
+    pub fn create(directory: &str, browser: &str) -> Result<Self> {
+        std::fs::create_dir_all(directory).with_context(|| {
+            format!("Failed to create Allure results directory '{}'", directory)
+        })?;
+
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            run_id: format!("{:x}", started.as_nanos()),
+            browser: browser.to_owned(),
+        })
+    }
+
+    /// Writes the result of a finished test of the given browser and scenario and returns the
+    /// files written for it, problems are logged but never fail the run
+    pub fn write(
+        &self,
+        test: &str,
+        cell: (&str, &str),
+        status: Status,
+        error: Option<&str>,
+        duration: Duration,
+        variables: &Variables,
+    ) -> Vec<String> {
+        let mut written = Vec::new();
+        if let Err(e) =
+            self.write_result(test, cell, status, error, duration, variables, &mut written)
+        {
+            log::warn!("Failed to write Allure result of test #{}: {}", test, e);
+        }
+
+        written
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_result(
+        &self,
+        test: &str,
+        (browser, scenario): (&str, &str),
+        status: Status,
+        error: Option<&str>,
+        duration: Duration,
+        variables: &Variables,
+        written: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let uuid = format!("{}-{}", self.run_id, test.replace('.', "-"));
+        let name = format!("Test #{}", test);
+        let stop = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+        let attachment = format!("{}-variables-attachment.json", uuid);
+        let values = variables.iter().collect::<BTreeMap<_, _>>();
+        let path = self.directory.join(&attachment);
+        serde_json::to_writer_pretty(File::create(&path)?, &values)?;
+        written.push(path);
+
+        // Allure only resolves attachments within the results directory
+        let mut artifacts = Vec::new();
+        for (variable, name, extension, mime_type) in ARTIFACTS.iter() {
+            if let Ok(path) = variables.get(variable) {
+                let source = format!("{}-{}-attachment.{}", uuid, variable, extension);
+                let copy = self.directory.join(&source);
+                std::fs::copy(path, &copy)
+                    .with_context(|| format!("Failed to attach '{}'", path))?;
+                written.push(copy);
+                artifacts.push((*name, source, *mime_type));
+            }
+        }
+        let mut attachments = vec![Attachment {
+            name: "variables",
+            source: &attachment,
+            mime_type: "application/json",
+        }];
+        attachments.extend(
+            artifacts
+                .iter()
+                .map(|(name, source, mime_type)| Attachment {
+                    name,
+                    source,
+                    mime_type,
+                }),
+        );
+
+        let mut parameters = vec![NameValue {
+            name: "browser",
+            value: &self.browser,
+        }];
+        for variable in ["platform", "browser_version"].iter() {
+            if let Ok(value) = variables.get(variable) {
+                parameters.push(NameValue {
+                    name: variable,
+                    value,
+                });
+            }
+        }
+        parameters.push(NameValue {
+            name: "scenario",
+            value: scenario,
+        });
+
+        let result = TestResult {
+            uuid: &uuid,
+            history_id: &name,
+            name: &name,
+            full_name: &name,
+            status: match status {
+                Status::Passed => "passed",
+                Status::Failed => "failed",
+                Status::Broken => "broken",
+            },
+            status_details: StatusDetails {
+                message: error,
+                flaky: matches!(status, Status::Passed)
+                    && variables
+                        .get("attempts")
+                        .map_or(false, |attempts| attempts != "1"),
+            },
+            stage: "finished",
+            start: stop.saturating_sub(duration.as_millis()),
+            stop,
+            labels: vec![
+                NameValue {
+                    name: "parentSuite",
+                    value: "ParallelSeleniumTest",
+                },
+                NameValue {
+                    name: "suite",
+                    value: browser,
+                },
+                NameValue {
+                    name: "subSuite",
+                    value: scenario,
+                },
+                NameValue {
+                    name: "framework",
+                    value: "thirtyfour",
+                },
+            ],
+            parameters,
+            attachments,
+        };
+
+        let path = self.directory.join(format!("{}-result.json", uuid));
+        serde_json::to_writer_pretty(File::create(&path)?, &result)?;
+        written.push(path);
+
+        Ok(())
+    }
+}
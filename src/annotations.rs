@@ -0,0 +1,103 @@
+//! Test results in the formats CI servers pick up from the build output.
+//!
+//! Annotations are printed to stdout while the log goes to stderr, so they are not interleaved
+//! with log lines.
+
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+pub enum Annotations {
+    /// `::error` and `::notice` workflow commands of GitHub Actions
+    GitHub,
+    /// TeamCity service messages
+    TeamCity,
+}
+
+impl Annotations {
+    /// Reads the format from `ANNOTATIONS` (`github` or `teamcity`)
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("ANNOTATIONS").as_deref() {
+            Ok("github") => Ok(Some(Annotations::GitHub)),
+            Ok("teamcity") => Ok(Some(Annotations::TeamCity)),
+            Ok(other) => bail!("Unknown annotation format '{}'", other),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Reports a finished test, `error` is set for failed ones together with the failing step
+    pub fn test_finished(
+        &self,
+        test: &str,
+        error: Option<&str>,
+        step: Option<&str>,
+        duration: Duration,
+    ) {
+        let name = match step {
+            Some(step) => format!("Test #{} ({})", test, step),
+            None => format!("Test #{}", test),
+        };
+
+        match self {
+            Annotations::GitHub => {
+                if let Some(error) = error {
+                    println!(
+                        "::error title={}::{}",
+                        escape_github_property(&name),
+                        escape_github(error)
+                    );
+                }
+            }
+            Annotations::TeamCity => {
+                let test = escape_teamcity(&format!("Test #{}", test));
+                println!("##teamcity[testStarted name='{}' flowId='{}']", test, test);
+                if let Some(error) = error {
+                    println!(
+                        "##teamcity[testFailed name='{}' message='{}' details='{}' flowId='{}']",
+                        test,
+                        escape_teamcity(&format!("Failed in step {}", step.unwrap_or("-"))),
+                        escape_teamcity(error),
+                        test
+                    );
+                }
+                println!(
+                    "##teamcity[testFinished name='{}' duration='{}' flowId='{}']",
+                    test,
+                    duration.as_millis(),
+                    test
+                );
+            }
+        }
+    }
+
+    pub fn run_finished(&self, total: u64, failed: u64) {
+        let summary = format!("{} / {} tests succeeded", total - failed, total);
+
+        match self {
+            Annotations::GitHub if failed > 0 => println!("::error::{}", escape_github(&summary)),
+            Annotations::GitHub => println!("::notice::{}", escape_github(&summary)),
+            Annotations::TeamCity => println!(
+                "##teamcity[buildStatisticValue key='failedTests' value='{}']",
+                failed
+            ),
+        }
+    }
+}
+
+fn escape_github(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_github_property(text: &str) -> String {
+    escape_github(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn escape_teamcity(text: &str) -> String {
+    text.replace('|', "||")
+        .replace('\'', "|'")
+        .replace('\n', "|n")
+        .replace('\r', "|r")
+        .replace('[', "|[")
+        .replace(']', "|]")
+}
@@ -0,0 +1,232 @@
+//! Checks for text read from a page that go beyond exact equality.
+
+use crate::diff::describe_mismatch;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::fmt;
+use thirtyfour::{By, WebDriver, WebElement};
+
+/// Condition a piece of text read from the page has to fulfil
+pub enum Expectation {
+    Equals(String),
+    Contains(String),
+    StartsWith(String),
+    Matches(Regex),
+    /// Numeric value within `tolerance` of `value`
+    Number {
+        value: f64,
+        tolerance: f64,
+    },
+}
+
+impl Expectation {
+    pub fn equals(text: impl Into<String>) -> Self {
+        Self::Equals(text.into())
+    }
+
+    pub fn contains(text: impl Into<String>) -> Self {
+        Self::Contains(text.into())
+    }
+
+    pub fn starts_with(text: impl Into<String>) -> Self {
+        Self::StartsWith(text.into())
+    }
+
+    pub fn matches(pattern: &str) -> Result<Self> {
+        let regex =
+            Regex::new(pattern).with_context(|| format!("Invalid pattern '{}'", pattern))?;
+        Ok(Self::Matches(regex))
+    }
+
+    pub fn number(value: f64, tolerance: f64) -> Self {
+        Self::Number { value, tolerance }
+    }
+
+    pub fn check(&self, actual: &str) -> Result<()> {
+        let fulfilled = match self {
+            Expectation::Equals(expected) => {
+                if actual != expected {
+                    bail!("{}", describe_mismatch(actual, expected));
+                }
+                true
+            }
+            Expectation::Contains(needle) => actual.contains(needle.as_str()),
+            Expectation::StartsWith(prefix) => actual.starts_with(prefix.as_str()),
+            Expectation::Matches(regex) => regex.is_match(actual),
+            Expectation::Number { value, tolerance } => {
+                let number = actual
+                    .trim()
+                    .parse::<f64>()
+                    .with_context(|| format!("'{}' is not a number", actual))?;
+                (number - value).abs() <= *tolerance
+            }
+        };
+
+        if !fulfilled {
+            bail!("'{}' does not {}", actual, self);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expectation::Equals(expected) => write!(f, "equal '{}'", expected),
+            Expectation::Contains(needle) => write!(f, "contain '{}'", needle),
+            Expectation::StartsWith(prefix) => write!(f, "start with '{}'", prefix),
+            Expectation::Matches(regex) => write!(f, "match /{}/", regex),
+            Expectation::Number { value, tolerance } => {
+                write!(f, "equal {} ± {}", value, tolerance)
+            }
+        }
+    }
+}
+
+/// State of an element that can be asserted on without executing scripts
+#[derive(Debug, Clone, Copy)]
+pub enum State {
+    Enabled,
+    Disabled,
+    Selected,
+    Unselected,
+    Displayed,
+    Hidden,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            State::Enabled => "enabled",
+            State::Disabled => "disabled",
+            State::Selected => "selected",
+            State::Unselected => "unselected",
+            State::Displayed => "displayed",
+            State::Hidden => "hidden",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Checks that an element is in the given state
+pub async fn state(element: &WebElement<'_>, expected: State) -> Result<()> {
+    let fulfilled = match expected {
+        State::Enabled => element.is_enabled().await?,
+        State::Disabled => !element.is_enabled().await?,
+        State::Selected => element.is_selected().await?,
+        State::Unselected => !element.is_selected().await?,
+        State::Displayed => element.is_displayed().await?,
+        State::Hidden => !element.is_displayed().await?,
+    };
+
+    if !fulfilled {
+        bail!("Element is not {}", expected);
+    }
+
+    Ok(())
+}
+
+/// Checks how many elements a selector matches
+pub async fn count(driver: &WebDriver, by: By<'_>, expected: usize) -> Result<()> {
+    let actual = driver.find_elements(by).await?.len();
+
+    if actual != expected {
+        bail!("Expected {} matching elements, found {}", expected, actual);
+    }
+
+    Ok(())
+}
+
+/// Checks the visible text of an element
+pub async fn text(element: &WebElement<'_>, expected: &Expectation) -> Result<()> {
+    let text = element.text().await?;
+    expected.check(&text).context("Unexpected element text")
+}
+
+/// Checks an attribute of an element, failing if it is not present at all
+pub async fn attribute(element: &WebElement<'_>, name: &str, expected: &Expectation) -> Result<()> {
+    let value = element
+        .get_attribute(name)
+        .await?
+        .with_context(|| format!("Attribute '{}' is not set", name))?;
+
+    expected
+        .check(&value)
+        .with_context(|| format!("Unexpected value of attribute '{}'", name))
+}
+
+/// Checks the computed value of a CSS property of an element
+pub async fn css_property(
+    element: &WebElement<'_>,
+    name: &str,
+    expected: &Expectation,
+) -> Result<()> {
+    let value = element.get_css_property(name).await?;
+
+    expected
+        .check(&value)
+        .with_context(|| format!("Unexpected value of CSS property '{}'", name))
+}
+
+/// Checks that the current page fits its viewport: nothing overflows it horizontally, and the key
+/// element is displayed within it
+pub async fn layout(driver: &WebDriver, key: &WebElement<'_>) -> Result<()> {
+    let script = r#"
+        const root = document.documentElement;
+        return { width: root.clientWidth, overflow: root.scrollWidth - root.clientWidth };
+    "#;
+
+    let result = driver.execute_script(script).await?;
+    let width = result.value()["width"].as_f64().unwrap_or_default();
+    let overflow = result.value()["overflow"].as_f64().unwrap_or_default();
+    if overflow > 0.0 {
+        bail!(
+            "The page overflows its {} px wide viewport horizontally by {} px",
+            width,
+            overflow
+        );
+    }
+
+    state(key, State::Displayed)
+        .await
+        .context("Key element of the layout")?;
+    let rect = key.rect().await?;
+    if rect.x < 0.0 || rect.x + rect.width > width {
+        bail!(
+            "Key element spans {} to {} px, outside of the {} px wide viewport",
+            rect.x,
+            rect.x + rect.width,
+            width
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that no subresource of the current page (scripts, stylesheets, images, …) failed to load.
+///
+/// Relies on the `responseStatus` reported by the Resource Timing API. Browsers that do not
+/// report it, and cross-origin resources without `Timing-Allow-Origin`, can not be checked.
+pub async fn subresources(driver: &WebDriver) -> Result<()> {
+    let script = r#"
+        return performance.getEntriesByType('resource')
+            .filter(entry => entry.responseStatus >= 400)
+            .map(entry => entry.responseStatus + ' ' + entry.name);
+    "#;
+
+    let result = driver.execute_script(script).await?;
+    let failed: Vec<String> = serde_json::from_value(result.value().clone())
+        .context("Unexpected result of the resource timing query")?;
+
+    if !failed.is_empty() {
+        bail!(
+            "{} subresources failed to load: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+
+    Ok(())
+}
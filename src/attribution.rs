@@ -0,0 +1,116 @@
+//! Attribution of the session creation latency to the client, the network and the grid.
+//!
+//! The traffic proxy sends every New Session request over a connection of its own and times its
+//! phases: resolving the host name of the grid, opening the TCP connection, the TLS handshake,
+//! waiting for the first byte of the response (the grid creating the session plus one round
+//! trip) and transferring the response. The runner attributes whatever remains of the session
+//! creation it measured to the client, i.e. the WebDriver client and the proxy queueing and
+//! handling the request.
+
+use crate::metrics::Metrics;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const CLIENT: &str = "session-create-client";
+pub const DNS: &str = "session-create-dns";
+pub const CONNECT: &str = "session-create-connect";
+pub const TLS: &str = "session-create-tls";
+pub const FIRST_BYTE: &str = "session-create-first-byte";
+pub const TRANSFER: &str = "session-create-transfer";
+
+/// Age after which a recorded session is assumed to belong to a creation the client gave up on,
+/// the runner otherwise picks it up as soon as the response reaches it
+const ABANDONED: Duration = Duration::from_secs(60);
+
+/// Durations of the phases of one New Session request
+pub struct Phases {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// Missing if the grid is reached without TLS
+    pub tls: Option<Duration>,
+    pub first_byte: Duration,
+    pub transfer: Duration,
+}
+
+impl Phases {
+    fn total(&self) -> Duration {
+        self.dns + self.connect + self.tls.unwrap_or_default() + self.first_byte + self.transfer
+    }
+}
+
+pub struct SessionTiming {
+    metrics: Arc<Metrics>,
+    /// Time the proxy spent on the request of every session the runner did not pick up yet, and
+    /// when it was recorded
+    handled: Mutex<HashMap<String, (Duration, Instant)>>,
+}
+
+impl SessionTiming {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            handled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the phases of a New Session request, `session_id` is missing if the grid did not
+    /// create a session
+    pub fn record(&self, session_id: Option<&str>, phases: &Phases) {
+        self.metrics.record(DNS, phases.dns);
+        self.metrics.record(CONNECT, phases.connect);
+        if let Some(tls) = phases.tls {
+            self.metrics.record(TLS, tls);
+        }
+        self.metrics.record(FIRST_BYTE, phases.first_byte);
+        self.metrics.record(TRANSFER, phases.transfer);
+
+        let mut handled = self.handled.lock().unwrap();
+        handled.retain(|_, (_, recorded)| recorded.elapsed() < ABANDONED);
+        if let Some(session_id) = session_id {
+            handled.insert(session_id.to_owned(), (phases.total(), Instant::now()));
+        }
+    }
+
+    /// Attributes the part of the session creation the proxy did not see to the client
+    pub fn attribute(&self, session_id: &str, total: Duration) {
+        let handled = self.handled.lock().unwrap().remove(session_id);
+        if let Some((handled, _)) = handled {
+            self.metrics.record(CLIENT, total.saturating_sub(handled));
+        }
+    }
+
+    /// Logs the share of every phase in the mean session creation
+    pub fn log_summary(&self) {
+        let phases = [
+            ("client", CLIENT),
+            ("DNS", DNS),
+            ("connect", CONNECT),
+            ("TLS", TLS),
+            ("first byte", FIRST_BYTE),
+            ("transfer", TRANSFER),
+        ]
+        .iter()
+        .filter_map(|(label, metric)| {
+            let histogram = self.metrics.snapshot(metric)?;
+            Some((*label, histogram.mean()))
+        })
+        .collect::<Vec<_>>();
+
+        let total = phases.iter().map(|(_, mean)| mean).sum::<f64>();
+        if total <= 0.0 {
+            return;
+        }
+
+        log::info!(
+            "Session creation by phase (share of the mean): {}",
+            phases
+                .iter()
+                .map(|(label, mean)| format!("{} {:.1}%", label, mean * 100.0 / total))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Square-wave load for observing how quickly a grid's autoscaler reacts.
+//!
+//! Each cycle holds the full session count in flight for the first half of the period and only
+//! a few sessions for the second half. Session creation latency around the transitions shows
+//! when the grid added capacity after a rising edge and when it released it after a falling one:
+//! sessions created during the low phase suddenly take much longer once idle nodes are gone.
+
+use crate::metrics;
+use anyhow::{Context, Result};
+use humantime::format_duration;
+use std::{sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SquareWave {
+    /// Length of one high and one low phase together
+    pub period: Duration,
+    pub cycles: u32,
+    /// Sessions kept in flight during the low phases
+    pub low: u64,
+}
+
+impl SquareWave {
+    /// Reads the period from `SQUARE_WAVE`, the number of cycles from `SQUARE_WAVE_CYCLES` and
+    /// the low level from `SQUARE_WAVE_LOW`, returns `None` if no period is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let period = match std::env::var("SQUARE_WAVE") {
+            Ok(value) => humantime::parse_duration(&value).context("Invalid SQUARE_WAVE")?,
+            Err(_) => return Ok(None),
+        };
+        let cycles = std::env::var("SQUARE_WAVE_CYCLES")
+            .unwrap_or("3".into())
+            .parse::<u32>()
+            .context("Invalid SQUARE_WAVE_CYCLES")?;
+        let low = std::env::var("SQUARE_WAVE_LOW")
+            .unwrap_or("1".into())
+            .parse::<u64>()
+            .context("Invalid SQUARE_WAVE_LOW")?;
+
+        Ok(Some(Self {
+            period,
+            cycles,
+            low,
+        }))
+    }
+
+    pub fn total(&self) -> Duration {
+        self.period * self.cycles
+    }
+
+    fn half(&self) -> Duration {
+        self.period / 2
+    }
+
+    /// Number of sessions that should be in flight after the given time
+    pub fn level(&self, elapsed: Duration, high: u64) -> u64 {
+        if elapsed >= self.total() {
+            0
+        } else if elapsed.as_nanos() % self.period.as_nanos() < self.half().as_nanos() {
+            high
+        } else {
+            self.low
+        }
+    }
+
+    /// Time of the first transition after the given time
+    pub fn next_transition(&self, elapsed: Duration) -> Duration {
+        self.transitions()
+            .map(|(at, _)| at)
+            .find(|at| *at > elapsed)
+            .unwrap_or_else(|| self.total())
+    }
+
+    /// Times of all transitions, `true` marking rising edges
+    pub fn transitions(&self) -> impl Iterator<Item = (Duration, bool)> + '_ {
+        (0..self.cycles * 2).map(move |edge| (self.half() * edge, edge % 2 == 0))
+    }
+}
+
+/// Session creations of a square-wave run, as offsets from the start of the run
+pub struct Reactions {
+    wave: SquareWave,
+    high: u64,
+    creations: Mutex<Vec<(Duration, Duration)>>,
+}
+
+impl Reactions {
+    pub fn new(wave: SquareWave, high: u64) -> Self {
+        Self {
+            wave,
+            high,
+            creations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a session creation that started at the given offset
+    pub fn record(&self, started: Duration, latency: Duration) {
+        self.creations.lock().unwrap().push((started, latency));
+    }
+
+    /// Logs the reaction times of the grid for every transition
+    pub fn log_summary(&self) {
+        let creations = self.creations.lock().unwrap();
+
+        for (edge, rising) in self.wave.transitions() {
+            let end = self.wave.next_transition(edge);
+            let phase = creations
+                .iter()
+                .filter(|(started, _)| *started >= edge && *started < end)
+                .collect::<Vec<_>>();
+            let median = median_latency(&phase);
+
+            let reaction = if rising {
+                // Capacity is there once the additional sessions all got created
+                let mut created = phase
+                    .iter()
+                    .map(|(started, latency)| *started + *latency - edge)
+                    .collect::<Vec<_>>();
+                created.sort();
+                let additional = self.high.saturating_sub(self.wave.low) as usize;
+                additional
+                    .checked_sub(1)
+                    .and_then(|index| created.get(index))
+                    .map(|reached| format!("scale-up after {}", format_ms(*reached)))
+            } else {
+                // Creations in the low phase slow down once the grid released its idle nodes
+                let previous = creations
+                    .iter()
+                    .filter(|(started, _)| *started >= edge - self.wave.half() && *started < edge)
+                    .collect::<Vec<_>>();
+                median_latency(&previous).and_then(|baseline| {
+                    phase
+                        .iter()
+                        .find(|(_, latency)| *latency > baseline * 2)
+                        .map(|(started, _)| {
+                            format!("scale-down after {}", format_ms(*started - edge))
+                        })
+                })
+            };
+
+            log::info!(
+                "{} edge at {}: {} sessions created, median creation {}, {}.",
+                if rising { "Rising" } else { "Falling" },
+                format_duration(edge),
+                phase.len(),
+                median.map(format_ms).unwrap_or_else(|| "-".to_owned()),
+                reaction.unwrap_or_else(|| "no reaction observed".to_owned())
+            );
+        }
+    }
+}
+
+fn median_latency(creations: &[&(Duration, Duration)]) -> Option<Duration> {
+    let mut latencies = creations
+        .iter()
+        .map(|(_, latency)| *latency)
+        .collect::<Vec<_>>();
+    latencies.sort();
+    latencies.get(latencies.len() / 2).copied()
+}
+
+fn format_ms(duration: Duration) -> String {
+    metrics::format_ms(duration.as_millis() as u64)
+}
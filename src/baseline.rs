@@ -0,0 +1,116 @@
+//! Store of baseline runs, so later runs are compared with them without passing both event logs.
+//!
+//! Tagging a run copies its event log into the store under each of the given labels, e.g. a
+//! branch, an environment or a browser. Runs and reports with one of those labels then include
+//! the delta to the stored baseline, as reported by `compare`.
+
+use crate::{compare, report};
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Significance level of the comparisons with a baseline
+const ALPHA: f64 = 0.05;
+
+pub struct Store {
+    directory: PathBuf,
+}
+
+impl Store {
+    pub fn open(directory: &str) -> Self {
+        Self {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    fn path(&self, label: &str) -> Result<PathBuf> {
+        let valid = !label.is_empty()
+            && !label.starts_with('.')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            bail!(
+                "Baseline labels may only consist of letters, digits, '-', '_' and '.', got '{}'",
+                label
+            );
+        }
+
+        Ok(self.directory.join(format!("{}.ndjson", label)))
+    }
+
+    /// Stores the event log as the baseline of every label, replacing their previous baselines
+    pub fn tag(&self, event_log: &str, labels: &[String]) -> Result<()> {
+        let finished = report::read(event_log)?
+            .iter()
+            .any(|record| record["event"] == "session_finished");
+        if !finished {
+            bail!(
+                "The event log '{}' does not contain any finished tests",
+                event_log
+            );
+        }
+
+        std::fs::create_dir_all(&self.directory).with_context(|| {
+            format!(
+                "Failed to create baseline directory '{}'",
+                self.directory.display()
+            )
+        })?;
+        for label in labels {
+            std::fs::copy(event_log, self.path(label)?)
+                .with_context(|| format!("Failed to store the baseline of '{}'", label))?;
+            log::info!("Stored '{}' as the baseline of '{}'", event_log, label);
+        }
+
+        Ok(())
+    }
+
+    /// Logs the delta of the run to the baseline of every label that has one
+    pub fn compare(&self, event_log: &str, labels: &[String]) -> Result<()> {
+        for label in labels {
+            let path = self.path(label)?;
+            if !path.exists() {
+                log::info!(
+                    "No baseline of '{}' stored yet, tag a run with `baseline --label {}`",
+                    label,
+                    label
+                );
+                continue;
+            }
+
+            log::info!("Delta to the baseline of '{}':", label);
+            compare::compare(&path.to_string_lossy(), event_log, ALPHA)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Comparison of a run with its baselines once it finished
+pub struct Comparison {
+    store: Store,
+    labels: Vec<String>,
+    event_log: String,
+}
+
+impl Comparison {
+    /// Comparison of the run writing the given event log, which has to be a file
+    pub fn new(store: Store, labels: Vec<String>, event_log: Option<String>) -> Result<Self> {
+        let event_log = match event_log {
+            Some(path) if path != "-" => path,
+            _ => {
+                bail!("Comparing the run with a baseline needs its event log in a file (--events)")
+            }
+        };
+
+        Ok(Self {
+            store,
+            labels,
+            event_log,
+        })
+    }
+
+    pub fn log(&self) -> Result<()> {
+        self.store.compare(&self.event_log, &self.labels)
+    }
+}
@@ -0,0 +1,294 @@
+//! Circuit breaker that stops sending new sessions to a grid endpoint that keeps failing to
+//! create them.
+//!
+//! After a number of consecutive failed session creations the breaker opens, and new sessions
+//! wait instead of adding load to the struggling endpoint. Once the cooldown passed it lets a
+//! single session through as a probe (half-open). If the probe gets its session the breaker closes
+//! again, otherwise it reopens for another cooldown.
+
+use anyhow::{Context, Result};
+use humantime::format_duration;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Interval at which waiting sessions check whether they may proceed
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Sessions are created as usual
+    Closed,
+    /// Sessions wait for the cooldown to pass
+    Open,
+    /// A probe session is testing whether the endpoint recovered
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+struct State {
+    circuit: CircuitState,
+    /// Consecutive failed session creations while closed
+    failures: u32,
+    opened: Instant,
+    /// Whether the probe of the half-open breaker is under way
+    probing: bool,
+    /// Number of times the breaker opened
+    trips: u64,
+}
+
+pub struct CircuitBreaker {
+    /// Redacted endpoint the breaker guards, for the log
+    endpoint: String,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    /// Reads the number of consecutive failures that open the breaker from `CIRCUIT_BREAKER` and
+    /// the cooldown from `CIRCUIT_BREAKER_COOLDOWN` (default 30s), returns `None` if the
+    /// breaker is not enabled
+    pub fn from_env(endpoint: &str) -> Result<Option<Self>> {
+        let threshold = match std::env::var("CIRCUIT_BREAKER") {
+            Ok(value) => value.parse::<u32>().context("Invalid CIRCUIT_BREAKER")?,
+            Err(_) => return Ok(None),
+        };
+        let cooldown = humantime::parse_duration(
+            &std::env::var("CIRCUIT_BREAKER_COOLDOWN").unwrap_or("30s".into()),
+        )
+        .context("Invalid CIRCUIT_BREAKER_COOLDOWN")?;
+
+        if threshold == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            endpoint: endpoint.to_owned(),
+            threshold,
+            cooldown,
+            state: Mutex::new(State {
+                circuit: CircuitState::Closed,
+                failures: 0,
+                opened: Instant::now(),
+                probing: false,
+                trips: 0,
+            }),
+        }))
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state.lock().unwrap().circuit
+    }
+
+    /// Waits until a session may be created, returns whether it is the probe of the half-open
+    /// breaker
+    pub async fn admit(&self) -> bool {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                // Read once, the cooldown could otherwise expire between the check and the wait
+                let open_for = state.opened.elapsed();
+                match state.circuit {
+                    CircuitState::Closed => return false,
+                    CircuitState::Open if open_for >= self.cooldown => {
+                        self.transition(&mut state, CircuitState::HalfOpen);
+                        state.probing = true;
+                        return true;
+                    }
+                    CircuitState::Open => self.cooldown - open_for,
+                    CircuitState::HalfOpen if !state.probing => {
+                        state.probing = true;
+                        return true;
+                    }
+                    CircuitState::HalfOpen => POLL_INTERVAL,
+                }
+            };
+
+            sleep(wait.min(POLL_INTERVAL)).await;
+        }
+    }
+
+    /// Records the outcome of a session creation admitted by [`CircuitBreaker::admit`]
+    pub fn record(&self, probe: bool, created: bool) {
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            CircuitState::Closed if created => state.failures = 0,
+            CircuitState::Closed => {
+                state.failures += 1;
+                if state.failures >= self.threshold {
+                    self.transition(&mut state, CircuitState::Open);
+                }
+            }
+            // Sessions admitted before the breaker opened do not tell anything about recovery
+            CircuitState::HalfOpen if probe => {
+                state.probing = false;
+                if created {
+                    self.transition(&mut state, CircuitState::Closed);
+                } else {
+                    self.transition(&mut state, CircuitState::Open);
+                }
+            }
+            CircuitState::HalfOpen | CircuitState::Open => {}
+        }
+    }
+
+    fn transition(&self, state: &mut State, circuit: CircuitState) {
+        match circuit {
+            CircuitState::Open if state.circuit == CircuitState::HalfOpen => {
+                state.opened = Instant::now();
+                log::warn!(
+                    "Circuit breaker of '{}' reopened as the probe session failed, holding new sessions back for {}",
+                    self.endpoint,
+                    format_duration(self.cooldown)
+                );
+            }
+            CircuitState::Open => {
+                state.opened = Instant::now();
+                state.trips += 1;
+                log::warn!(
+                    "Circuit breaker of '{}' opened after {} failed session creations, holding new sessions back for {}",
+                    self.endpoint,
+                    state.failures,
+                    format_duration(self.cooldown)
+                );
+            }
+            CircuitState::HalfOpen => log::info!(
+                "Circuit breaker of '{}' is half-open, probing with a single session",
+                self.endpoint
+            ),
+            CircuitState::Closed => log::info!(
+                "Circuit breaker of '{}' closed as the probe session succeeded",
+                self.endpoint
+            ),
+        }
+
+        state.circuit = circuit;
+        state.failures = 0;
+    }
+
+    pub fn log_summary(&self) {
+        let trips = self.state.lock().unwrap().trips;
+        if trips > 0 {
+            log::info!(
+                "The circuit breaker opened {} times and held sessions back.",
+                trips
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    fn breaker(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            endpoint: "http://grid".into(),
+            threshold,
+            cooldown,
+            state: Mutex::new(State {
+                circuit: CircuitState::Closed,
+                failures: 0,
+                opened: Instant::now(),
+                probing: false,
+                trips: 0,
+            }),
+        }
+    }
+
+    fn trips(breaker: &CircuitBreaker) -> u64 {
+        breaker.state.lock().unwrap().trips
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(3, Duration::from_secs(30));
+        breaker.record(false, false);
+        breaker.record(false, false);
+        breaker.record(false, true);
+        breaker.record(false, false);
+        breaker.record(false, false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record(false, false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(trips(&breaker), 1);
+    }
+
+    #[test]
+    fn ignores_sessions_admitted_before_it_opened() {
+        let breaker = breaker(1, Duration::from_secs(30));
+        breaker.record(false, false);
+        breaker.record(false, true);
+        breaker.record(false, false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(trips(&breaker), 1);
+    }
+
+    #[tokio::test]
+    async fn holds_sessions_back_while_open() {
+        let breaker = breaker(1, Duration::from_secs(30));
+        assert!(!breaker.admit().await);
+        breaker.record(false, false);
+
+        assert!(timeout(Duration::from_millis(250), breaker.admit())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn admits_a_single_probe_after_the_cooldown() {
+        let breaker = breaker(1, Duration::from_millis(50));
+        breaker.record(false, false);
+
+        assert!(breaker.admit().await);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(timeout(Duration::from_millis(250), breaker.admit())
+            .await
+            .is_err());
+
+        // Sessions admitted before the breaker opened do not end the probe
+        breaker.record(false, true);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+
+    #[tokio::test]
+    async fn closes_when_the_probe_succeeds() {
+        let breaker = breaker(1, Duration::from_millis(50));
+        breaker.record(false, false);
+
+        assert!(breaker.admit().await);
+        breaker.record(true, true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(!breaker.admit().await);
+        assert_eq!(trips(&breaker), 1);
+    }
+
+    #[tokio::test]
+    async fn reopens_when_the_probe_fails() {
+        let breaker = breaker(1, Duration::from_millis(50));
+        breaker.record(false, false);
+
+        assert!(breaker.admit().await);
+        breaker.record(true, false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        // Reopening continues the trip instead of starting another
+        assert_eq!(trips(&breaker), 1);
+
+        assert!(breaker.admit().await);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+}
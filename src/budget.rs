@@ -0,0 +1,129 @@
+//! Error budget of a run with a planned duration, derived from a success rate objective.
+//!
+//! The budget is the share of tests allowed to fail (0.5% for an SLO of 99.5%). It burns at a
+//! rate of 1 if failures occur exactly as often as allowed, anything above exhausts the budget
+//! before the planned end of the run: at a burn rate of 2, half way through.
+
+use crate::flag::env_flag;
+use anyhow::{bail, Context, Result};
+use humantime::format_duration;
+use std::{sync::Mutex, time::Duration};
+
+/// Tests that have to finish before the burn rate is meaningful
+const MIN_TESTS: u64 = 20;
+
+#[derive(Default)]
+struct State {
+    passed: u64,
+    failed: u64,
+    /// Whether the budget is currently projected to run out
+    burning: bool,
+    aborted: bool,
+}
+
+pub struct ErrorBudget {
+    /// Share of tests allowed to fail
+    allowed: f64,
+    planned: Duration,
+    abort: bool,
+    state: Mutex<State>,
+}
+
+impl ErrorBudget {
+    /// Budget for a success rate objective given in percent
+    pub fn new(slo: f64, planned: Duration, abort: bool) -> Result<Self> {
+        if !(slo > 0.0 && slo < 100.0) {
+            bail!(
+                "The SLO has to be a percentage between 0 and 100, got {}",
+                slo
+            );
+        }
+
+        Ok(Self {
+            allowed: 1.0 - slo / 100.0,
+            planned,
+            abort,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Reads the objective from `SLO` and whether to abort from `SLO_ABORT`, returns `None`
+    /// if no objective is set
+    pub fn from_env(planned: Duration) -> Result<Option<Self>> {
+        let slo = match std::env::var("SLO") {
+            Ok(value) => value.parse::<f64>().context("Invalid SLO")?,
+            Err(_) => return Ok(None),
+        };
+        let abort = env_flag("SLO_ABORT")?;
+
+        Self::new(slo, planned, abort).map(Some)
+    }
+
+    /// Records a test that finished the given time after the run started
+    pub fn record(&self, elapsed: Duration, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        if success {
+            state.passed += 1;
+        } else {
+            state.failed += 1;
+        }
+
+        let total = state.passed + state.failed;
+        if total < MIN_TESTS || state.aborted {
+            return;
+        }
+
+        let burn_rate = self.burn_rate(&state);
+        if burn_rate > 1.0 && !state.burning {
+            state.burning = true;
+            let exhausted = self.planned.div_f64(burn_rate);
+            log::warn!(
+                "Error budget burning at {:.1}x, it {} after {} of the planned {} ({} / {} tests failed).",
+                burn_rate,
+                if exhausted <= elapsed { "was exhausted" } else { "will be exhausted" },
+                format_duration(Duration::from_secs(exhausted.as_secs())),
+                format_duration(self.planned),
+                state.failed,
+                total
+            );
+
+            if self.abort {
+                state.aborted = true;
+                log::error!("Aborting the run, no new tests will be started.");
+            }
+        } else if burn_rate <= 1.0 && state.burning {
+            state.burning = false;
+            log::info!(
+                "Error budget burn rate recovered to {:.1}x ({} / {} tests failed).",
+                burn_rate,
+                state.failed,
+                total
+            );
+        }
+    }
+
+    /// Whether the run should stop launching tests
+    pub fn aborted(&self) -> bool {
+        self.state.lock().unwrap().aborted
+    }
+
+    pub fn log_summary(&self) {
+        let state = self.state.lock().unwrap();
+        let total = state.passed + state.failed;
+        if total == 0 {
+            return;
+        }
+
+        log::info!(
+            "Error budget: {} / {} tests failed, {:.0}% of the budget used.",
+            state.failed,
+            total,
+            self.burn_rate(&state) * 100.0
+        );
+    }
+
+    fn burn_rate(&self, state: &State) -> f64 {
+        let total = (state.passed + state.failed).max(1);
+        state.failed as f64 / total as f64 / self.allowed
+    }
+}
@@ -0,0 +1,326 @@
+//! Search for the maximum concurrency at which a grid still meets its objectives.
+//!
+//! Every round keeps a number of virtual users busy for a short probe duration and checks the
+//! success rate (and optionally the p95 test duration) of the tests it ran. The number of users
+//! is binary-searched between one and the session count, so the capacity is found in about
+//! `log2(count)` rounds. Since a round only sees a sample of tests, the success rates are
+//! reported with their 95% confidence interval.
+
+use anyhow::{bail, Context, Result};
+use humantime::format_duration;
+use std::time::Duration;
+
+/// z-score of the 95% confidence intervals
+const Z: f64 = 1.96;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CapacitySearch {
+    /// Duration of the round at every concurrency level
+    pub probe: Duration,
+    /// Success rate objective in percent every round has to meet
+    pub slo: f64,
+    /// Upper bound of the p95 test duration every round has to meet
+    pub max_p95: Option<Duration>,
+}
+
+impl CapacitySearch {
+    /// Reads the probe duration from `FIND_CAPACITY`, the objective from `SLO` (default 99%)
+    /// and the duration bound from `MAX_P95`, returns `None` if no probe duration is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let probe = match std::env::var("FIND_CAPACITY") {
+            Ok(value) => humantime::parse_duration(&value).context("Invalid FIND_CAPACITY")?,
+            Err(_) => return Ok(None),
+        };
+        let slo = std::env::var("SLO")
+            .unwrap_or("99".into())
+            .parse::<f64>()
+            .context("Invalid SLO")?;
+        let max_p95 = match std::env::var("MAX_P95") {
+            Ok(value) => Some(humantime::parse_duration(&value).context("Invalid MAX_P95")?),
+            Err(_) => None,
+        };
+
+        Ok(Some(Self {
+            probe,
+            slo,
+            max_p95,
+        }))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.probe.is_zero() {
+            bail!("The probe rounds of the capacity search need a duration");
+        }
+        if !(self.slo > 0.0 && self.slo <= 100.0) {
+            bail!(
+                "The SLO has to be a percentage between 0 and 100, got {}",
+                self.slo
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of the round at one concurrency level
+pub struct Round {
+    pub users: u64,
+    pub passed: u64,
+    pub failed: u64,
+    /// p95 duration of the passed tests in milliseconds
+    pub p95: Option<u64>,
+}
+
+impl Round {
+    fn total(&self) -> u64 {
+        self.passed + self.failed
+    }
+
+    fn success_rate(&self) -> f64 {
+        self.passed as f64 / self.total().max(1) as f64
+    }
+
+    /// Wilson score interval of the success rate
+    fn confidence_interval(&self) -> (f64, f64) {
+        let n = self.total() as f64;
+        if n == 0.0 {
+            return (0.0, 1.0);
+        }
+
+        let p = self.success_rate();
+        let denominator = 1.0 + Z * Z / n;
+        let center = (p + Z * Z / (2.0 * n)) / denominator;
+        let margin = Z / denominator * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt();
+
+        ((center - margin).max(0.0), (center + margin).min(1.0))
+    }
+}
+
+/// Bounds of a capacity search and the rounds it ran so far
+pub struct Search {
+    config: CapacitySearch,
+    /// Highest level known to meet the objectives
+    passing: u64,
+    /// Lowest level known to miss them
+    failing: u64,
+    rounds: Vec<Round>,
+}
+
+impl Search {
+    pub fn new(config: CapacitySearch, count: u64) -> Self {
+        Self {
+            config,
+            passing: 0,
+            failing: count + 1,
+            rounds: Vec::new(),
+        }
+    }
+
+    /// Concurrency level of the next round, `None` once the capacity is known. The first round
+    /// probes the session count in case it is not even reached.
+    pub fn next(&self) -> Option<u64> {
+        if self.failing - self.passing <= 1 {
+            None
+        } else if self.rounds.is_empty() {
+            Some(self.failing - 1)
+        } else {
+            Some(self.passing + (self.failing - self.passing) / 2)
+        }
+    }
+
+    /// Whether a round meets the objectives
+    fn meets(&self, round: &Round) -> bool {
+        let p95_met = match (self.config.max_p95, round.p95) {
+            (Some(max), Some(p95)) => Duration::from_millis(p95) <= max,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        round.total() > 0 && round.success_rate() * 100.0 >= self.config.slo && p95_met
+    }
+
+    /// Narrows the bounds by the outcome of a round and logs it
+    pub fn record(&mut self, round: Round) {
+        let met = self.meets(&round);
+        let (low, high) = round.confidence_interval();
+        log::info!(
+            "{} sessions: {} / {} succeeded ({:.1}%, 95% CI {:.1}% - {:.1}%), p95 {}, {} the objectives",
+            round.users,
+            round.passed,
+            round.total(),
+            round.success_rate() * 100.0,
+            low * 100.0,
+            high * 100.0,
+            round
+                .p95
+                .map(|p95| format_duration(Duration::from_millis(p95)).to_string())
+                .unwrap_or_else(|| "-".into()),
+            if met { "meets" } else { "misses" }
+        );
+
+        if met {
+            self.passing = self.passing.max(round.users);
+        } else {
+            self.failing = self.failing.min(round.users);
+        }
+        self.rounds.push(round);
+    }
+
+    /// Logs the discovered capacity and returns it
+    pub fn log_summary(&self, count: u64) -> u64 {
+        let mut objectives = format!("{}% success", self.config.slo);
+        if let Some(max_p95) = self.config.max_p95 {
+            objectives.push_str(&format!(" and p95 below {}", format_duration(max_p95)));
+        }
+
+        log::info!(
+            "Capacity search finished after {} rounds of {} ({}):",
+            self.rounds.len(),
+            format_duration(self.config.probe),
+            objectives
+        );
+
+        if self.passing == 0 {
+            log::info!("  Not even a single session meets the objectives");
+            return 0;
+        }
+
+        let at = |users: u64| self.rounds.iter().find(|round| round.users == users);
+        if self.passing == count {
+            log::info!(
+                "  Capacity is at least {} sessions, raise the session count to search further",
+                count
+            );
+        } else {
+            log::info!("  Capacity: {} sessions", self.passing);
+        }
+
+        for (label, round) in [
+            ("last passing", at(self.passing)),
+            ("first failing", at(self.failing)),
+        ] {
+            if let Some(round) = round {
+                let (low, high) = round.confidence_interval();
+                log::info!(
+                    "  {} sessions ({}): success rate {:.1}% - {:.1}% with 95% confidence",
+                    round.users,
+                    label,
+                    low * 100.0,
+                    high * 100.0
+                );
+            }
+        }
+
+        // Levels whose success rate is above the objective even at the low end of the interval
+        let confident = self
+            .rounds
+            .iter()
+            .filter(|round| self.meets(round))
+            .filter(|round| round.confidence_interval().0 * 100.0 >= self.config.slo)
+            .map(|round| round.users)
+            .max();
+        match confident {
+            Some(users) => log::info!(
+                "  Meets the objectives with 95% confidence up to {} sessions",
+                users
+            ),
+            None => log::info!(
+                "  No level ran enough tests to meet the objectives with 95% confidence, use longer probes"
+            ),
+        }
+
+        self.passing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_p95: Option<Duration>) -> CapacitySearch {
+        CapacitySearch {
+            probe: Duration::from_secs(60),
+            slo: 95.0,
+            max_p95,
+        }
+    }
+
+    fn round(users: u64, passed: u64, failed: u64, p95: Option<u64>) -> Round {
+        Round {
+            users,
+            passed,
+            failed,
+            p95,
+        }
+    }
+
+    /// Runs the search against a grid that meets the objectives up to `capacity` sessions
+    fn search(count: u64, capacity: u64) -> (u64, Vec<u64>) {
+        let mut search = Search::new(config(None), count);
+        let mut levels = Vec::new();
+        while let Some(users) = search.next() {
+            levels.push(users);
+            let failed = if users <= capacity { 0 } else { 10 };
+            search.record(round(users, 100 - failed, failed, None));
+        }
+        (search.log_summary(count), levels)
+    }
+
+    #[test]
+    fn first_round_probes_the_session_count() {
+        let search = Search::new(config(None), 16);
+        assert_eq!(search.next(), Some(16));
+    }
+
+    #[test]
+    fn finds_the_capacity_by_bisection() {
+        let (capacity, levels) = search(16, 11);
+        assert_eq!(capacity, 11);
+        assert_eq!(levels, vec![16, 8, 12, 10, 11]);
+    }
+
+    #[test]
+    fn stops_after_the_first_round_if_the_count_is_met() {
+        assert_eq!(search(16, 20), (16, vec![16]));
+    }
+
+    #[test]
+    fn reports_zero_if_no_level_meets_the_objectives() {
+        let (capacity, levels) = search(4, 0);
+        assert_eq!(capacity, 0);
+        assert_eq!(levels, vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn single_session_is_searched_once() {
+        assert_eq!(search(1, 1), (1, vec![1]));
+        assert_eq!(search(1, 0), (0, vec![1]));
+    }
+
+    #[test]
+    fn rounds_without_tests_miss_the_objectives() {
+        let search = Search::new(config(None), 4);
+        assert!(!search.meets(&round(4, 0, 0, None)));
+    }
+
+    #[test]
+    fn p95_bound_is_part_of_the_objectives() {
+        let search = Search::new(config(Some(Duration::from_secs(2))), 4);
+        assert!(search.meets(&round(4, 100, 0, Some(2000))));
+        assert!(!search.meets(&round(4, 100, 0, Some(2001))));
+        assert!(!search.meets(&round(4, 100, 0, None)));
+    }
+
+    #[test]
+    fn confidence_interval_is_the_wilson_score_interval() {
+        let (low, high) = round(1, 90, 10, None).confidence_interval();
+        assert!((low - 0.825_633).abs() < 1e-6, "{}", low);
+        assert!((high - 0.944_771).abs() < 1e-6, "{}", high);
+
+        let (low, high) = round(1, 10, 0, None).confidence_interval();
+        assert!((low - 0.722_460).abs() < 1e-6, "{}", low);
+        assert_eq!(high, 1.0);
+
+        assert_eq!(round(1, 0, 0, None).confidence_interval(), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,56 @@
+//! Removal of sessions an aborted run left behind, which would otherwise occupy slots of the grid
+//! until they time out.
+
+use crate::report;
+use anyhow::{Context, Result};
+use basic_test::Redactor;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Grids may take a while to shut a browser down, but a grid that stops answering must not
+/// keep the cleanup from finishing
+const DELETE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Deletes the sessions of all tests the event log shows as started but never finished
+pub async fn delete_orphans(endpoint: &str, event_log: &str) -> Result<()> {
+    let records = report::read(event_log)?;
+    let sessions = report::orphaned_sessions(&records);
+
+    if sessions.is_empty() {
+        log::info!("No sessions were left behind.");
+        return Ok(());
+    }
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(DELETE_TIMEOUT)
+        .build()
+        .context("Failed to build grid client")?;
+    let redactor = Redactor::new(endpoint);
+    let endpoint = endpoint.trim_end_matches('/');
+    let mut deleted = 0;
+
+    for session in sessions.iter() {
+        let url = format!("{}/session/{}", endpoint, session);
+        match client
+            .delete(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+        {
+            Ok(_) => {
+                log::info!("Deleted session {}", session);
+                deleted += 1;
+            }
+            Err(e) => log::warn!(
+                "Failed to delete session {}: {}",
+                session,
+                redactor.redact(&e.to_string())
+            ),
+        }
+    }
+
+    log::info!("Deleted {} / {} sessions.", deleted, sessions.len());
+
+    Ok(())
+}
@@ -0,0 +1,402 @@
+//! Comparison of a candidate run with a baseline run, telling real regressions from noise.
+//!
+//! Test durations are compared with a Mann-Whitney U test, which does not assume any particular
+//! distribution of the durations, and the difference of the medians is reported with a bootstrap
+//! confidence interval. Success rates are compared with a two-proportion z-test. A difference only
+//! counts as a regression (or improvement) if it is significant at the chosen level.
+
+use crate::report;
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+/// Resamples of the bootstrap confidence interval
+const RESAMPLES: usize = 2000;
+
+/// Outcomes of the tests of one run
+struct Sample {
+    passed: u64,
+    failed: u64,
+    /// Durations of the passed tests in milliseconds, sorted
+    durations: Vec<u64>,
+}
+
+impl Sample {
+    fn read(path: &str) -> Result<Self> {
+        let records = report::read(path)?;
+        let finished = records
+            .iter()
+            .filter(|r| r["event"] == "session_finished")
+            .collect::<Vec<&Value>>();
+        if finished.is_empty() {
+            bail!(
+                "The event log '{}' does not contain any finished tests",
+                path
+            );
+        }
+
+        let mut durations = finished
+            .iter()
+            .filter(|r| r["success"] == true)
+            .filter_map(|r| r["duration_ms"].as_u64())
+            .collect::<Vec<_>>();
+        durations.sort_unstable();
+        let passed = finished.iter().filter(|r| r["success"] == true).count() as u64;
+
+        Ok(Self {
+            passed,
+            failed: finished.len() as u64 - passed,
+            durations,
+        })
+    }
+
+    fn total(&self) -> u64 {
+        self.passed + self.failed
+    }
+
+    fn success_rate(&self) -> f64 {
+        self.passed as f64 / self.total() as f64
+    }
+}
+
+/// Logs the differences between the runs, returns whether the candidate did not regress
+/// significantly at level `alpha`
+pub fn compare(baseline: &str, candidate: &str, alpha: f64) -> Result<bool> {
+    if !(alpha > 0.0 && alpha < 1.0) {
+        bail!(
+            "The significance level has to be between 0 and 1, got {}",
+            alpha
+        );
+    }
+
+    let (before, after) = (Sample::read(baseline)?, Sample::read(candidate)?);
+    let confidence = (1.0 - alpha) * 100.0;
+    let mut regressed = false;
+
+    log::info!(
+        "Comparing {} baseline tests with {} candidate tests at a significance level of {}",
+        before.total(),
+        after.total(),
+        alpha
+    );
+
+    // Success rates
+    let (difference, p) = proportion_test(&before, &after);
+    let margin = z_for(alpha)
+        * (before.success_rate() * (1.0 - before.success_rate()) / before.total() as f64
+            + after.success_rate() * (1.0 - after.success_rate()) / after.total() as f64)
+            .sqrt();
+    let verdict = verdict(difference, p, alpha);
+    log::info!(
+        "Success rate: {:.2}% -> {:.2}% ({:+.2} points, {:.0}% CI {:+.2} to {:+.2}, p = {:.4}), {}",
+        before.success_rate() * 100.0,
+        after.success_rate() * 100.0,
+        difference * 100.0,
+        confidence,
+        (difference - margin) * 100.0,
+        (difference + margin) * 100.0,
+        p,
+        verdict
+    );
+    regressed |= p < alpha && difference < 0.0;
+
+    // Durations of the passed tests
+    if before.durations.is_empty() || after.durations.is_empty() {
+        log::info!("Durations: not enough passed tests to compare");
+        return Ok(!regressed);
+    }
+
+    let (median_before, median_after) = (median(&before.durations), median(&after.durations));
+    let p = mann_whitney(&before.durations, &after.durations);
+    let (low, high) = bootstrap_median_difference(&before.durations, &after.durations, alpha);
+    // Longer durations are worse, so the sign is flipped for the verdict
+    let verdict = verdict(median_before - median_after, p, alpha);
+    log::info!(
+        "Median duration: {:.0} ms -> {:.0} ms ({:+.0} ms, {:.0}% CI {:+.0} to {:+.0} ms, p = {:.4}), {}",
+        median_before,
+        median_after,
+        median_after - median_before,
+        confidence,
+        low,
+        high,
+        p,
+        verdict
+    );
+    regressed |= p < alpha && median_after > median_before;
+
+    Ok(!regressed)
+}
+
+/// Verdict of a difference where positive values are improvements
+fn verdict(difference: f64, p: f64, alpha: f64) -> &'static str {
+    if p >= alpha {
+        "no significant difference"
+    } else if difference < 0.0 {
+        "significant regression"
+    } else {
+        "significant improvement"
+    }
+}
+
+/// Difference of the success rates and two-sided p-value of a pooled two-proportion z-test
+fn proportion_test(before: &Sample, after: &Sample) -> (f64, f64) {
+    let difference = after.success_rate() - before.success_rate();
+    let (n1, n2) = (before.total() as f64, after.total() as f64);
+    let pooled = (before.passed + after.passed) as f64 / (n1 + n2);
+    let error = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+
+    // Identical rates of 0% or 100% leave nothing to test
+    if error == 0.0 {
+        return (difference, 1.0);
+    }
+
+    (difference, two_sided_p(difference / error))
+}
+
+/// Two-sided p-value of a Mann-Whitney U test with tie correction, using the normal
+/// approximation
+fn mann_whitney(first: &[u64], second: &[u64]) -> f64 {
+    let (n1, n2) = (first.len() as f64, second.len() as f64);
+    let mut values = first
+        .iter()
+        .map(|value| (*value, true))
+        .chain(second.iter().map(|value| (*value, false)))
+        .collect::<Vec<_>>();
+    values.sort_unstable_by_key(|(value, _)| *value);
+
+    // Ties get the mean of the ranks they span
+    let mut rank_sum = 0.0;
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < values.len() {
+        let end = start
+            + values[start..]
+                .iter()
+                .take_while(|(value, _)| *value == values[start].0)
+                .count();
+        let rank = (start + end + 1) as f64 / 2.0;
+        let tied = (end - start) as f64;
+        ties += tied * tied * tied - tied;
+        rank_sum += rank
+            * values[start..end]
+                .iter()
+                .filter(|(_, first)| *first)
+                .count() as f64;
+        start = end;
+    }
+
+    let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+
+    two_sided_p((u - n1 * n2 / 2.0) / variance.sqrt())
+}
+
+/// Percentile bootstrap interval of the difference of the medians (second minus first)
+fn bootstrap_median_difference(first: &[u64], second: &[u64], alpha: f64) -> (f64, f64) {
+    // Deterministic, so comparing the same runs twice gives the same interval
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+
+    let mut differences = (0..RESAMPLES)
+        .map(|_| {
+            let mut resample = |values: &[u64]| {
+                let mut resampled = (0..values.len())
+                    .map(|_| values[next(values.len())])
+                    .collect::<Vec<_>>();
+                resampled.sort_unstable();
+                median(&resampled)
+            };
+            let before = resample(first);
+            resample(second) - before
+        })
+        .collect::<Vec<_>>();
+    differences.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let index = |q: f64| differences[((RESAMPLES - 1) as f64 * q).round() as usize];
+    (index(alpha / 2.0), index(1.0 - alpha / 2.0))
+}
+
+fn median(sorted: &[u64]) -> f64 {
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) as f64 / 2.0
+    } else {
+        sorted[middle] as f64
+    }
+}
+
+/// Two-sided p-value of a standard normal test statistic
+fn two_sided_p(z: f64) -> f64 {
+    erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0)
+}
+
+/// Critical value of a two-sided interval at level `alpha`, found by bisection
+fn z_for(alpha: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 10.0);
+    for _ in 0..60 {
+        let middle = (low + high) / 2.0;
+        if two_sided_p(middle) > alpha {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Complementary error function with a fractional error below 1.2e-7 (Numerical Recipes)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let result = t * polynomial.exp();
+
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(passed: u64, failed: u64) -> Sample {
+        Sample {
+            passed,
+            failed,
+            durations: Vec::new(),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {} to be within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn erfc_matches_reference_values() {
+        assert_close(erfc(0.0), 1.0, 2e-7);
+        assert_close(erfc(0.5), 0.479_500_122, 1e-7);
+        assert_close(erfc(1.0), 0.157_299_207, 1e-7);
+        assert_close(erfc(-1.0), 1.842_700_793, 1e-7);
+        assert_close(erfc(3.0), 0.000_022_090_5, 1e-8);
+    }
+
+    #[test]
+    fn critical_values_invert_the_p_value() {
+        assert_close(two_sided_p(0.0), 1.0, 1e-7);
+        assert_close(two_sided_p(1.0), 0.317_310_508, 1e-6);
+        assert_close(two_sided_p(-1.959_964), 0.05, 1e-6);
+        assert_close(z_for(0.05), 1.959_964, 1e-5);
+        assert_close(z_for(0.01), 2.575_829, 1e-5);
+    }
+
+    #[test]
+    fn proportion_test_matches_reference_values() {
+        let (difference, p) = proportion_test(&sample(90, 10), &sample(80, 20));
+        assert_close(difference, -0.1, 1e-12);
+        assert_close(p, 0.047_670, 1e-5);
+    }
+
+    #[test]
+    fn proportion_test_of_identical_extreme_rates_is_not_significant() {
+        assert_eq!(proportion_test(&sample(10, 0), &sample(20, 0)), (0.0, 1.0));
+        assert_eq!(proportion_test(&sample(0, 10), &sample(0, 20)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn mann_whitney_of_separated_samples() {
+        assert_close(
+            mann_whitney(&[1, 2, 3, 4, 5], &[6, 7, 8, 9, 10]),
+            0.009_023,
+            1e-5,
+        );
+        // The test is symmetric in its samples
+        assert_close(
+            mann_whitney(&[6, 7, 8, 9, 10], &[1, 2, 3, 4, 5]),
+            0.009_023,
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn mann_whitney_corrects_for_ties() {
+        assert_close(mann_whitney(&[1, 2, 2, 3], &[2, 3, 4, 5]), 0.101_591, 1e-5);
+    }
+
+    #[test]
+    fn mann_whitney_of_only_ties_is_not_significant() {
+        assert_eq!(mann_whitney(&[5, 5, 5], &[5, 5]), 1.0);
+        assert_eq!(mann_whitney(&[5], &[5]), 1.0);
+    }
+
+    #[test]
+    fn mann_whitney_of_single_values() {
+        assert_close(mann_whitney(&[1], &[2]), 0.317_311, 1e-5);
+    }
+
+    #[test]
+    fn mann_whitney_of_identical_samples() {
+        assert_close(mann_whitney(&[1, 2, 3], &[1, 2, 3]), 1.0, 1e-7);
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        assert_eq!(median(&[7]), 7.0);
+        assert_eq!(median(&[1, 2, 9]), 2.0);
+        assert_eq!(median(&[1, 2, 4, 9]), 3.0);
+    }
+
+    #[test]
+    fn bootstrap_of_constant_samples_is_exact() {
+        assert_eq!(
+            bootstrap_median_difference(&[100, 100, 100], &[100, 100], 0.05),
+            (0.0, 0.0)
+        );
+        assert_eq!(
+            bootstrap_median_difference(&[100], &[150], 0.05),
+            (50.0, 50.0)
+        );
+    }
+
+    #[test]
+    fn bootstrap_interval_covers_the_shift() {
+        let first = (100..120).collect::<Vec<u64>>();
+        let second = first.iter().map(|value| value + 50).collect::<Vec<_>>();
+        let (low, high) = bootstrap_median_difference(&first, &second, 0.05);
+        assert!(low <= 50.0 && 50.0 <= high, "({}, {})", low, high);
+        assert!(low > 0.0, "({}, {})", low, high);
+    }
+
+    #[test]
+    fn bootstrap_is_deterministic() {
+        let first = [120, 80, 95, 130, 101];
+        let second = [140, 90, 99, 150, 111];
+        assert_eq!(
+            bootstrap_median_difference(&first, &second, 0.1),
+            bootstrap_median_difference(&first, &second, 0.1)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Run configuration read from a TOML file, so larger runs can be reviewed and reproduced.
+//!
+//! Besides the settings that are also available as flags, the `env` table sets any of the
+//! environment variables a run is configured with:
+//!
+//! ```toml
+//! endpoint = "http://localhost:4444/"
+//! count = 200
+//! browser = "chrome"
+//!
+//! [env]
+//! ITERATIONS = "20"
+//! SUMMARY_INTERVAL = "1m"
+//! ```
+//!
+//! Presets are built-in configurations of common kinds of runs, which a configuration file
+//! refines: its values and `env` entries replace those of the preset.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{collections::BTreeMap, net::IpAddr, time::Duration};
+use toml::{value::Table, Value};
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RunConfig {
+    pub endpoint: Option<String>,
+    pub count: Option<u64>,
+    pub browser: Option<String>,
+    /// Timeout per session in seconds
+    pub timeout: Option<u64>,
+    /// Timeouts of the session creation and of every further command, e.g. `5m`
+    pub session_timeout: Option<String>,
+    pub command_timeout: Option<String>,
+    /// Bound of every step, navigation and element lookup, e.g. `30s`
+    pub step_timeout: Option<String>,
+    pub navigation_timeout: Option<String>,
+    pub lookup_timeout: Option<String>,
+    /// Cleanup of sessions before they are quit, `cookies` and `blank`
+    #[serde(default)]
+    pub teardown: Vec<String>,
+    /// JavaScript files executed in the browser before the session is quit
+    #[serde(default)]
+    pub teardown_scripts: Vec<String>,
+    #[serde(default)]
+    pub skip_quit: bool,
+    /// URL receiving a POST for every quit session
+    pub after_quit: Option<String>,
+    pub max_concurrent: Option<usize>,
+    /// Sessions launched per second, independent of how quickly they finish
+    pub rate: Option<f64>,
+    /// File of arrival timestamps to replay, see `--replay`
+    pub replay: Option<String>,
+    /// Period of the square wave, see `--square-wave`
+    pub square_wave: Option<String>,
+    pub cycles: Option<u32>,
+    pub low: Option<u64>,
+    /// Probe duration of the capacity search, see `--find-capacity`
+    pub find_capacity: Option<String>,
+    /// Upper bound of the p95 test duration during a capacity search, e.g. `30s`
+    pub max_p95: Option<String>,
+    /// Share of failed tests within the window that aborts the run, e.g. `50%`
+    pub abort_failure_rate: Option<String>,
+    /// Rolling window of the failure rate that aborts the run, e.g. `2m`
+    pub abort_window: Option<String>,
+    /// Success rate objective in percent
+    pub slo: Option<f64>,
+    #[serde(default)]
+    pub abort_on_budget: bool,
+    pub metrics_port: Option<u16>,
+    /// OpenTelemetry collector receiving the traces
+    pub otlp: Option<String>,
+    #[serde(default)]
+    pub tui: bool,
+    /// Collects the browser console of every test
+    #[serde(default)]
+    pub console_log: bool,
+    /// Bounds of the random viewport of every session, e.g. `360x640..1920x1080`
+    pub viewport_jitter: Option<String>,
+    /// Device pixel ratios of the jittered viewports
+    #[serde(default)]
+    pub pixel_ratios: Vec<f64>,
+    /// Seed of the jittered viewports
+    pub viewport_seed: Option<u64>,
+    /// Directory of scripted journeys
+    pub scenario_dir: Option<String>,
+    /// Globs of the journeys loaded from the directory, all if empty
+    #[serde(default)]
+    pub scenario_include: Vec<String>,
+    /// Globs of the journeys skipped
+    #[serde(default)]
+    pub scenario_exclude: Vec<String>,
+    /// Age after which pooled sessions are recreated, e.g. `15m`
+    pub max_session_age: Option<String>,
+    /// Failed tests the run tolerates before it counts as failed
+    pub max_failures: Option<u64>,
+    /// Share of failed tests the run tolerates, e.g. `2%`
+    pub max_failure_rate: Option<String>,
+    /// Re-runs of failed tests
+    pub retries: Option<u32>,
+    /// Retries of failed session creations
+    pub session_retries: Option<u32>,
+    /// Connections to the grid opened before the run starts
+    pub warm_connections: Option<usize>,
+    /// Local addresses the WebDriver requests are sent from in turn
+    #[serde(default)]
+    pub local_addresses: Vec<IpAddr>,
+    /// Times the phases of every session creation
+    #[serde(default)]
+    pub session_timing: bool,
+    /// Time tests get to quit their sessions after Ctrl+C, e.g. `1m`
+    pub grace_period: Option<String>,
+    /// Path of the event log, `-` for stdout
+    pub events: Option<String>,
+    /// Path of the JUnit XML report
+    pub junit: Option<String>,
+    /// Labels whose stored baselines the run is compared with
+    #[serde(default)]
+    pub baselines: Vec<String>,
+    /// Directory of the stored baselines
+    pub baseline_dir: Option<String>,
+    /// Directory receiving screenshots and page sources of failed tests
+    pub screenshots: Option<String>,
+    /// Stages of the load profile, as durations like `5m`
+    pub ramp_up: Option<String>,
+    pub steady: Option<String>,
+    pub ramp_down: Option<String>,
+    /// Soak test duration, a load profile consisting of the steady state only
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Built-in configuration of a common kind of run
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Preset {
+    /// A handful of short sessions checking that the grid works at all
+    Smoke,
+    /// Search of the highest concurrency meeting a 99% success rate
+    Capacity,
+    /// An hour of steady load against a 99.5% success rate objective
+    Soak,
+}
+
+impl Preset {
+    fn source(self) -> &'static str {
+        match self {
+            Preset::Smoke => include_str!("presets/smoke.toml"),
+            Preset::Capacity => include_str!("presets/capacity.toml"),
+            Preset::Soak => include_str!("presets/soak.toml"),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Preset::Smoke => "smoke",
+            Preset::Capacity => "capacity",
+            Preset::Soak => "soak",
+        }
+    }
+}
+
+impl RunConfig {
+    /// Reads the configuration file on top of the preset, either of which may be missing
+    pub fn load(path: Option<&str>, preset: Option<Preset>) -> Result<Self> {
+        let mut table = match preset {
+            Some(preset) => {
+                log::info!("Using the {} preset", preset.name());
+                toml::from_str(preset.source()).expect("Presets are valid TOML")
+            }
+            None => Table::new(),
+        };
+
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Value::Table(table).try_into()?),
+        };
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration '{}'", path))?;
+        let file: Table = toml::from_str(&content)
+            .with_context(|| format!("Invalid configuration '{}'", path))?;
+
+        for (key, value) in file {
+            match (table.get_mut(&key), value) {
+                (Some(Value::Table(env)), Value::Table(overrides)) if key == "env" => {
+                    env.extend(overrides)
+                }
+                (_, value) => {
+                    table.insert(key, value);
+                }
+            }
+        }
+
+        Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Invalid configuration '{}'", path))
+    }
+
+    /// Sets the variables of the `env` table, variables set in the environment take precedence
+    pub fn export_env(&self) {
+        for (key, value) in self.env.iter() {
+            if std::env::var_os(key).is_none() {
+                std::env::set_var(key, value);
+            }
+        }
+    }
+}
+
+/// Parses one of the durations of the file
+pub fn parse_duration(value: Option<&str>) -> Result<Option<Duration>> {
+    match value {
+        Some(value) => Ok(Some(
+            humantime::parse_duration(value)
+                .with_context(|| format!("Invalid duration '{}'", value))?,
+        )),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,129 @@
+//! Collection of the browser console of every test, whose messages are otherwise invisible to
+//! the runner.
+//!
+//! Chrome keeps its console messages for the legacy log endpoint, given the `goog:loggingPrefs`
+//! capability at session creation. Other browsers get a hook on the `console` functions instead,
+//! which is installed along with the page error listeners whenever a step begins. It misses
+//! messages logged while a page is still loading, before the hook could be installed.
+
+use crate::flag::env_flag;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thirtyfour::{prelude::*, Capabilities};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Off,
+    /// Reads the messages from the log endpoint of Chrome
+    Endpoint,
+    /// Records the messages through a hook on the `console` functions
+    Hook,
+}
+
+#[derive(Deserialize)]
+pub struct Message {
+    pub level: String,
+    pub message: String,
+}
+
+impl Message {
+    pub fn is_error(&self) -> bool {
+        matches!(self.level.as_str(), "SEVERE" | "error")
+    }
+}
+
+impl Console {
+    /// Collects the console if `enabled`, defaults to `CONSOLE_LOG`, using the log endpoint for
+    /// Chrome and the hook for every other browser
+    pub fn from_env(enabled: Option<bool>, browser: &str) -> Result<Self> {
+        let enabled = match enabled {
+            Some(enabled) => enabled,
+            None => env_flag("CONSOLE_LOG")?,
+        };
+
+        Ok(match (enabled, browser) {
+            (false, _) => Console::Off,
+            (true, "chrome") => Console::Endpoint,
+            (true, _) => Console::Hook,
+        })
+    }
+
+    /// Asks Chrome to keep the console messages for the log endpoint
+    pub fn apply(&self, caps: &mut impl Capabilities) -> Result<()> {
+        if *self == Console::Endpoint {
+            caps.add("goog:loggingPrefs", json!({ "browser": "ALL" }))?;
+        }
+
+        Ok(())
+    }
+
+    /// Installs the hook on the current page unless it already is
+    pub async fn arm(&self, driver: &WebDriver) {
+        if *self != Console::Hook {
+            return;
+        }
+
+        let script = r#"
+            if (!window.__consoleMessages) {
+                window.__consoleMessages = [];
+                for (const level of ['log', 'info', 'warn', 'error', 'debug']) {
+                    const original = console[level];
+                    console[level] = (...args) => {
+                        window.__consoleMessages.push({ level, message: args.map(String).join(' ') });
+                        original.apply(console, args);
+                    };
+                }
+            }
+        "#;
+
+        if let Err(e) = driver.execute_script(script).await {
+            log::debug!("Failed to install the console hook: {}", e);
+        }
+    }
+
+    /// Returns and forgets the messages logged since the last collection
+    pub async fn collect(
+        &self,
+        driver: &WebDriver,
+        endpoint: &str,
+        client: &reqwest::Client,
+    ) -> Result<Vec<Message>> {
+        match self {
+            Console::Off => Ok(Vec::new()),
+            Console::Endpoint => {
+                let session = format!(
+                    "{}/session/{}",
+                    endpoint.trim_end_matches('/'),
+                    driver.session_id()
+                );
+                // Selenium 4 grids only route the log command under its vendor prefix
+                for path in ["se/log", "log"] {
+                    let response = client
+                        .post(format!("{}/{}", session, path))
+                        .json(&json!({ "type": "browser" }))
+                        .send()
+                        .await?;
+                    if response.status().is_success() {
+                        let body = response.json::<Value>().await?;
+                        return Ok(serde_json::from_value(body["value"].clone())?);
+                    }
+                }
+
+                bail!("The grid does not provide the log endpoint")
+            }
+            Console::Hook => {
+                let script = r#"
+                    const messages = window.__consoleMessages || [];
+                    if (window.__consoleMessages) {
+                        window.__consoleMessages = [];
+                    }
+                    return messages;
+                "#;
+
+                let result = driver.execute_script(script).await?;
+                Ok(serde_json::from_value(result.value().clone())?)
+            }
+        }
+    }
+}
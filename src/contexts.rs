@@ -0,0 +1,89 @@
+//! Switching between windows and frames by the URL they show.
+//!
+//! Window handles and frame indices depend on the order in which the application opened them,
+//! which varies between sessions of dynamic applications. Matching the URL instead picks the
+//! same browsing context in every session.
+
+use crate::assertions::Expectation;
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use thirtyfour::prelude::*;
+
+/// Frames nested deeper than this are not searched
+const MAX_FRAME_DEPTH: usize = 4;
+
+/// Switches to the first window whose URL fulfils the expectation, staying in the current
+/// window if none does
+pub async fn switch_to_window(driver: &WebDriver, url: &Expectation) -> Result<()> {
+    let original = driver.current_window_handle().await?;
+    let mut seen = Vec::new();
+
+    for handle in driver.window_handles().await? {
+        driver.switch_to().window(&handle).await?;
+        let location = driver.current_url().await?;
+        if url.check(&location).is_ok() {
+            return Ok(());
+        }
+        seen.push(location);
+    }
+
+    driver.switch_to().window(&original).await?;
+    bail!(
+        "No window has a URL that does {} (found {})",
+        url,
+        seen.join(", ")
+    );
+}
+
+/// Switches to the first frame (searched breadth-first from the top of the page) whose URL
+/// fulfils the expectation, returning to the top of the page if none does
+pub async fn switch_to_frame(driver: &WebDriver, url: &Expectation) -> Result<()> {
+    let mut queue = VecDeque::from(vec![Vec::new()]);
+    let mut seen = Vec::new();
+
+    while let Some(path) = queue.pop_front() {
+        enter(driver, &path).await?;
+
+        if !path.is_empty() {
+            let location = frame_url(driver).await?;
+            if url.check(&location).is_ok() {
+                return Ok(());
+            }
+            seen.push(location);
+        }
+
+        if path.len() < MAX_FRAME_DEPTH {
+            let frames = driver.find_elements(By::Css("iframe, frame")).await?;
+            for index in 0..frames.len() as u16 {
+                let mut child = path.clone();
+                child.push(index);
+                queue.push_back(child);
+            }
+        }
+    }
+
+    driver.switch_to().default_content().await?;
+    bail!(
+        "No frame has a URL that does {} (found {})",
+        url,
+        seen.join(", ")
+    );
+}
+
+/// Enters the frame at the given path of indices, starting from the top of the page
+async fn enter(driver: &WebDriver, path: &[u16]) -> Result<()> {
+    driver.switch_to().default_content().await?;
+    for index in path {
+        driver.switch_to().frame_number(*index).await?;
+    }
+
+    Ok(())
+}
+
+/// URL of the current frame, which `current_url` does not report as it refers to the window
+async fn frame_url(driver: &WebDriver) -> Result<String> {
+    let result = driver
+        .execute_script("return window.location.href;")
+        .await?;
+    Ok(result.value().as_str().unwrap_or_default().to_owned())
+}
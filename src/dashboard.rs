@@ -0,0 +1,242 @@
+//! Live terminal dashboard replacing the scrolling log of long runs.
+//!
+//! The dashboard takes over the terminal on the alternate screen and redraws itself twice per
+//! second with the sessions in flight, the outcomes so far, a sparkline of the mean test duration
+//! per second and the most recent failures. Logging is muted while it is shown, the summary at
+//! the end of the run is logged as usual once the dashboard stopped.
+
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use humantime::format_duration;
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Sparkline},
+    Terminal,
+};
+use std::{
+    collections::VecDeque,
+    io::Stdout,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Failures kept for display
+const RECENT_FAILURES: usize = 50;
+const REFRESH: Duration = Duration::from_millis(500);
+
+/// Session counters at the time of a redraw
+pub struct Counters {
+    pub started: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Failures caused by the grid rather than the application
+    pub infrastructure_failures: u64,
+    /// State of the circuit breaker, if there is one
+    pub circuit: Option<&'static str>,
+}
+
+struct State {
+    started: Instant,
+    /// Sum of the durations and number of tests finished in every second of the run
+    latencies: Mutex<Vec<(u64, u64)>>,
+    /// Test name and error of the most recent failures, newest first
+    failures: Mutex<VecDeque<(String, String)>>,
+}
+
+pub struct Dashboard {
+    state: Arc<State>,
+    stopped: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(State {
+                started: Instant::now(),
+                latencies: Mutex::new(Vec::new()),
+                failures: Mutex::new(VecDeque::new()),
+            }),
+            stopped: Arc::new(AtomicBool::new(false)),
+            handle: Mutex::new(None),
+        }
+    }
+
+    /// Takes over the terminal until stopped, `counters` is called on every redraw. Quitting
+    /// calls `interrupt`, which returns whether the run was interrupted before.
+    pub fn start(
+        &self,
+        counters: impl Fn() -> Counters + Send + 'static,
+        interrupt: impl Fn() -> bool + Send + 'static,
+    ) -> Result<()> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+        terminal::enable_raw_mode()?;
+        execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+        let level = log::max_level();
+        log::set_max_level(log::LevelFilter::Off);
+
+        let handle = {
+            let state = self.state.clone();
+            let stopped = self.stopped.clone();
+            std::thread::spawn(move || {
+                let result = refresh(&mut terminal, &state, &stopped, counters, interrupt);
+                restore(&mut terminal, level);
+                if let Err(e) = result {
+                    log::error!("Dashboard stopped unexpectedly: {}", e);
+                }
+            })
+        };
+
+        *self.handle.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    /// Adds a finished test to the sparkline and, if it failed, to the recent failures
+    pub fn record(&self, test: &str, duration: Duration, error: Option<&str>) {
+        let second = self.state.started.elapsed().as_secs() as usize;
+        let mut latencies = self.state.latencies.lock().unwrap();
+        if latencies.len() <= second {
+            latencies.resize(second + 1, (0, 0));
+        }
+        latencies[second].0 += duration.as_millis() as u64;
+        latencies[second].1 += 1;
+        drop(latencies);
+
+        if let Some(error) = error {
+            let mut failures = self.state.failures.lock().unwrap();
+            failures.push_front((test.to_owned(), error.to_owned()));
+            failures.truncate(RECENT_FAILURES);
+        }
+    }
+
+    /// Hands the terminal back and resumes logging
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.lock().unwrap().take() {
+            handle.join().ok();
+        }
+    }
+}
+
+fn refresh(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    state: &State,
+    stopped: &AtomicBool,
+    counters: impl Fn() -> Counters,
+    interrupt: impl Fn() -> bool,
+) -> Result<()> {
+    while !stopped.load(Ordering::Relaxed) {
+        let counters = counters();
+        let elapsed = Duration::from_secs(state.started.elapsed().as_secs());
+
+        terminal.draw(|frame| {
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Length(8),
+                    Constraint::Min(3),
+                ])
+                .split(frame.size());
+
+            let mut status = format!(
+                "Active: {}   Completed: {}   Failed: {} ({} by the grid)",
+                counters.started.saturating_sub(counters.completed),
+                counters.completed,
+                counters.failed,
+                counters.infrastructure_failures
+            );
+            if let Some(circuit) = counters.circuit {
+                status.push_str(&format!("   Circuit: {}", circuit));
+            }
+            // Sessions are held back while the circuit breaker is not closed
+            let style = match counters.circuit {
+                Some(circuit) if circuit != "closed" => Style::default().fg(Color::Yellow),
+                _ => Style::default(),
+            };
+            let title = format!(" Running for {} (q to stop) ", format_duration(elapsed));
+            frame.render_widget(
+                Paragraph::new(status)
+                    .style(style)
+                    .block(Block::default().title(title).borders(Borders::ALL)),
+                areas[0],
+            );
+
+            // Mean duration per second, as many seconds as fit into the chart
+            let width = areas[1].width.saturating_sub(2) as usize;
+            let latencies = state.latencies.lock().unwrap();
+            let data = latencies[latencies.len().saturating_sub(width)..]
+                .iter()
+                .map(|(sum, count)| if *count > 0 { sum / count } else { 0 })
+                .collect::<Vec<_>>();
+            drop(latencies);
+            let peak = data.iter().max().copied().unwrap_or_default();
+            frame.render_widget(
+                Sparkline::default()
+                    .block(
+                        Block::default()
+                            .title(format!(" Mean test duration, peak {} ms ", peak))
+                            .borders(Borders::ALL),
+                    )
+                    .data(&data)
+                    .style(Style::default().fg(Color::Cyan)),
+                areas[1],
+            );
+
+            let failures = state
+                .failures
+                .lock()
+                .unwrap()
+                .iter()
+                .take(areas[2].height.saturating_sub(2) as usize)
+                .map(|(test, error)| ListItem::new(format!("#{}: {}", test, error)))
+                .collect::<Vec<_>>();
+            frame.render_widget(
+                List::new(failures)
+                    .block(
+                        Block::default()
+                            .title(" Recent failures ")
+                            .borders(Borders::ALL),
+                    )
+                    .style(Style::default().fg(Color::Red)),
+                areas[2],
+            );
+        })?;
+
+        // Raw mode swallows Ctrl+C, so it has to be handled like the signal would: the first one
+        // stops the run gracefully, the second one exits right away
+        if event::poll(REFRESH)? {
+            if let Event::Key(key) = event::read()? {
+                let interrupted =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press
+                    && (key.code == KeyCode::Char('q') || interrupted)
+                {
+                    if interrupt() {
+                        restore(terminal, log::LevelFilter::Off);
+                        std::process::exit(130);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn restore(terminal: &mut Terminal<CrosstermBackend<Stdout>>, level: log::LevelFilter) {
+    terminal::disable_raw_mode().ok();
+    execute!(terminal.backend_mut(), LeaveAlternateScreen).ok();
+    terminal.show_cursor().ok();
+    log::set_max_level(level);
+}
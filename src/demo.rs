@@ -0,0 +1,288 @@
+//! Embedded server hosting the demo application.
+//!
+//! Besides the single demo page it serves a small shop consisting of a product list, detail
+//! pages, an order form and a confirmation page, which the journey scenario navigates through.
+//! Browsers on the grid have to be able to reach the server, which is why it binds to a
+//! configurable address and can be announced under a different URL.
+//!
+//! Every route accepts query parameters injecting faults, so error handling of scenarios can
+//! be exercised deterministically:
+//!
+//! - `delay=2s` holds the response back
+//! - `status=500` answers with the given status code
+//! - `malformed=true` cuts the HTML off in the middle of a tag
+//!
+//! The `/echo` route shows the headers and remote address of the request, so the way browser
+//! traffic reaches the application (proxies, egress addresses, headers) can be verified.
+
+use anyhow::{Context, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use reqwest::Url;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{spawn, time::sleep};
+
+/// Number of products listed by the shop
+pub const PRODUCTS: u64 = 20;
+
+struct DemoApp {
+    /// Single page demo, possibly padded to the configured complexity
+    page: String,
+}
+
+/// Faults requested through the query parameters of a request
+#[derive(Default)]
+struct Faults {
+    delay: Option<Duration>,
+    status: Option<StatusCode>,
+    malformed: bool,
+}
+
+impl Faults {
+    fn from_url(url: &Url) -> Self {
+        let mut faults = Faults::default();
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "delay" => faults.delay = humantime::parse_duration(&value).ok(),
+                "status" => {
+                    faults.status = value
+                        .parse()
+                        .ok()
+                        .and_then(|code| StatusCode::from_u16(code).ok())
+                }
+                "malformed" => faults.malformed = value != "false",
+                _ => {}
+            }
+        }
+
+        faults
+    }
+}
+
+impl DemoApp {
+    async fn handle(&self, request: Request<Body>, remote: SocketAddr) -> Response<Body> {
+        if request.method() != Method::GET {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed".into());
+        }
+
+        let uri = request.uri().to_string();
+        let url = match Url::parse(&format!("http://demo{}", uri)) {
+            Ok(url) => url,
+            Err(_) => return respond(StatusCode::BAD_REQUEST, "Malformed request".into()),
+        };
+
+        let faults = Faults::from_url(&url);
+        if let Some(delay) = faults.delay {
+            sleep(delay).await;
+        }
+
+        if let Some(status) = faults.status {
+            let title = format!("Injected {}", status);
+            return respond(status, document(&title, &format!("<h1>{}</h1>", title)));
+        }
+
+        let (status, body) = match url.path() {
+            "/echo" => (StatusCode::OK, echo(&request, remote)),
+            _ => self.route(&url),
+        };
+        if faults.malformed {
+            return respond(status, malformed(&body));
+        }
+
+        respond(status, body)
+    }
+
+    fn route(&self, url: &Url) -> (StatusCode, String) {
+        let segments = url.path_segments().map_or(Vec::new(), |s| s.collect());
+
+        match segments.as_slice() {
+            [""] => (StatusCode::OK, self.page.clone()),
+            ["items"] => (StatusCode::OK, list()),
+            ["items", id] => match product(id) {
+                Some(id) => (StatusCode::OK, detail(id)),
+                None => not_found(),
+            },
+            ["items", id, "order"] => match product(id) {
+                Some(id) => (StatusCode::OK, order_form(id)),
+                None => not_found(),
+            },
+            ["confirmation"] => {
+                let query = |name: &str| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.into_owned())
+                        .unwrap_or_default()
+                };
+
+                match product(&query("item")) {
+                    Some(id) => (
+                        StatusCode::OK,
+                        confirmation(id, &query("quantity"), &query("customer")),
+                    ),
+                    None => not_found(),
+                }
+            }
+            _ => not_found(),
+        }
+    }
+}
+
+/// First half of a page followed by an unterminated tag
+fn malformed(page: &str) -> String {
+    let mut cut = page.len() / 2;
+    while !page.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    format!("{}<div class=\"broken", &page[..cut])
+}
+
+fn product(id: &str) -> Option<u64> {
+    id.parse().ok().filter(|id| (1..=PRODUCTS).contains(id))
+}
+
+fn product_name(id: u64) -> String {
+    format!("Product {}", id)
+}
+
+fn document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{}</title></head>\n<body>\n{}\n</body>\n</html>\n",
+        title, body
+    )
+}
+
+fn list() -> String {
+    let items = (1..=PRODUCTS)
+        .map(|id| {
+            format!(
+                "<li><a class=\"product\" href=\"/items/{}\">{}</a></li>",
+                id,
+                product_name(id)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    document(
+        "Products",
+        &format!("<h1>Products</h1>\n<ul id=\"products\">\n{}\n</ul>", items),
+    )
+}
+
+fn detail(id: u64) -> String {
+    document(
+        &product_name(id),
+        &format!(
+            "<h1 id=\"name\">{}</h1>\n<p id=\"price\">{}.99 €</p>\n<a id=\"order\" href=\"/items/{}/order\">Order</a>\n<a href=\"/items\">Back</a>",
+            product_name(id),
+            id * 3,
+            id
+        ),
+    )
+}
+
+fn order_form(id: u64) -> String {
+    document(
+        "Order",
+        &format!(
+            "<h1>Order {}</h1>\n<form action=\"/confirmation\" method=\"get\">\n<input type=\"hidden\" name=\"item\" value=\"{}\" />\n<input id=\"quantity\" name=\"quantity\" type=\"number\" min=\"1\" value=\"1\" />\n<input id=\"customer\" name=\"customer\" placeholder=\"Your name\" />\n<button id=\"submit\" type=\"submit\">Place order</button>\n</form>",
+            product_name(id),
+            id
+        ),
+    )
+}
+
+fn confirmation(id: u64, quantity: &str, customer: &str) -> String {
+    document(
+        "Order confirmed",
+        &format!(
+            "<h1>Order confirmed</h1>\n<p id=\"summary\">{} × {} for {}</p>\n<a href=\"/items\">Continue shopping</a>",
+            escape(quantity),
+            product_name(id),
+            escape(customer)
+        ),
+    )
+}
+
+fn echo(request: &Request<Body>, remote: SocketAddr) -> String {
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "<tr><td>{}</td><td id=\"header-{}\">{}</td></tr>",
+                name,
+                name,
+                escape(&String::from_utf8_lossy(value.as_bytes()))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    document(
+        "Echo",
+        &format!(
+            "<h1>Echo</h1>\n<p>Remote address: <span id=\"remote\">{}</span></p>\n<table id=\"headers\">\n{}\n</table>",
+            remote.ip(),
+            headers
+        ),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn not_found() -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        document("Not found", "<h1>Not found</h1>"),
+    )
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))
+        .expect("Failed to build demo response")
+}
+
+/// Starts serving the demo application on `bind`, returns the address it listens on
+pub async fn start(bind: &str, page: String) -> Result<SocketAddr> {
+    let address = bind
+        .parse::<SocketAddr>()
+        .with_context(|| format!("Invalid demo server address '{}'", bind))?;
+    let app = Arc::new(DemoApp { page });
+
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let app = app.clone();
+        let remote = connection.remote_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let app = app.clone();
+                async move { Ok::<_, Infallible>(app.handle(request, remote).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?.serve(make_service);
+    let local_address = server.local_addr();
+
+    spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Demo server stopped unexpectedly: {}", e);
+        }
+    });
+
+    Ok(local_address)
+}
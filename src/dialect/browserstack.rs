@@ -0,0 +1,52 @@
+//! BrowserStack, which takes progress and outcome of a test through its JavaScript executor.
+
+use super::{CapabilityField, GridDialect};
+use crate::priority::Priority;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use thirtyfour::WebDriver;
+
+pub struct BrowserStack;
+
+impl BrowserStack {
+    async fn execute(driver: &WebDriver, action: &str, arguments: Value) {
+        let command = json!({ "action": action, "arguments": arguments });
+        let script = format!("browserstack_executor: {}", command);
+        driver.execute_script(&script).await.ok();
+    }
+}
+
+#[async_trait]
+impl GridDialect for BrowserStack {
+    fn name(&self) -> &'static str {
+        "BrowserStack"
+    }
+
+    fn metadata_capabilities(
+        &self,
+        name: &str,
+        build: &str,
+        _priority: Option<&Priority>,
+    ) -> Vec<CapabilityField> {
+        vec![
+            ("bstack:options", "sessionName", json!(name)),
+            ("bstack:options", "buildName", json!(build)),
+        ]
+    }
+
+    async fn report(&self, driver: &WebDriver, messages: &[String], status: Option<&str>) {
+        for message in messages {
+            let arguments = json!({ "data": message, "level": "info" });
+            Self::execute(driver, "annotate", arguments).await;
+        }
+
+        if let Some(status) = status {
+            let status = if status == "success" {
+                "passed"
+            } else {
+                "failed"
+            };
+            Self::execute(driver, "setSessionStatus", json!({ "status": status })).await;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Vendor specific behaviour of the grids tests can run against.
+//!
+//! Grids differ in how sessions are labelled, how tests report their progress and outcome, and
+//! what they expose about themselves. Each [`GridDialect`] encapsulates one vendor, the one to
+//! use is either configured explicitly or detected from the endpoint.
+
+use crate::priority::Priority;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use thirtyfour::{Capabilities, WebDriver};
+
+mod browserstack;
+mod sauce;
+mod selenium;
+mod webgrid;
+
+pub use browserstack::BrowserStack;
+pub use sauce::SauceLabs;
+pub use selenium::SeleniumGrid;
+pub use webgrid::WebGrid;
+
+/// Capability subkey assignment in the form `(capability, subkey, value)`
+pub type CapabilityField = (&'static str, &'static str, Value);
+
+#[async_trait]
+pub trait GridDialect: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Capabilities labelling a new session with a name, build and priority class
+    fn metadata_capabilities(
+        &self,
+        name: &str,
+        build: &str,
+        priority: Option<&Priority>,
+    ) -> Vec<CapabilityField>;
+
+    /// Attaches runtime metadata to a running session
+    async fn set_metadata(&self, _driver: &WebDriver, _fields: HashMap<String, String>) {}
+
+    /// Delivers buffered progress messages and the test status (`success` or `failure`)
+    async fn report(&self, driver: &WebDriver, messages: &[String], status: Option<&str>);
+
+    /// Page showing the recording or details of a session
+    fn session_url(&self, _session_id: &str) -> Option<String> {
+        None
+    }
+
+    /// Number of session requests waiting for a free slot
+    async fn queue_size(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Picks the dialect by name (`webgrid`, `selenium`, `sauce` or `browserstack`) or detects it
+/// from the endpoint if no name or `auto` is given
+pub async fn select(name: Option<&str>, endpoint: &str) -> Result<Box<dyn GridDialect>> {
+    let dialect: Box<dyn GridDialect> = match name.unwrap_or("auto") {
+        "webgrid" => Box::new(WebGrid::new()),
+        "selenium" => Box::new(SeleniumGrid::new(endpoint)),
+        "sauce" => Box::new(SauceLabs::new(endpoint)),
+        "browserstack" => Box::new(BrowserStack),
+        "auto" => detect(endpoint).await,
+        other => bail!("Unknown grid dialect '{}'", other),
+    };
+
+    log::info!("Using {} grid dialect", dialect.name());
+    Ok(dialect)
+}
+
+async fn detect(endpoint: &str) -> Box<dyn GridDialect> {
+    if endpoint.contains("saucelabs.com") {
+        Box::new(SauceLabs::new(endpoint))
+    } else if endpoint.contains("browserstack.com") {
+        Box::new(BrowserStack)
+    } else if SeleniumGrid::probe(endpoint).await {
+        Box::new(SeleniumGrid::new(endpoint))
+    } else {
+        Box::new(WebGrid::new())
+    }
+}
+
+/// Adds the metadata capabilities of the dialect to a new session
+pub fn apply(
+    dialect: &dyn GridDialect,
+    caps: &mut impl Capabilities,
+    name: &str,
+    build: &str,
+    priority: Option<&Priority>,
+) -> Result<()> {
+    for (key, subkey, value) in dialect.metadata_capabilities(name, build, priority) {
+        caps.add_subkey(key, subkey, value)?;
+    }
+
+    Ok(())
+}
+
+/// Buffers the messages and status of one session until they are flushed, as reporting them
+/// costs a round trip each with most dialects
+pub struct StatusChannel<'a> {
+    dialect: &'a dyn GridDialect,
+    messages: Vec<String>,
+    status: Option<String>,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl<'a> StatusChannel<'a> {
+    pub fn new(dialect: &'a dyn GridDialect, flush_interval: Duration) -> Self {
+        Self {
+            dialect,
+            messages: Vec::new(),
+            status: None,
+            flush_interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Queues a message, sending everything queued so far if the flush interval has passed
+    pub async fn message(&mut self, driver: &WebDriver, message: &str) {
+        self.messages.push(message.to_owned());
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush(driver).await;
+        }
+    }
+
+    /// Sets the status reported with the next flush, replacing any earlier one
+    pub fn set_status(&mut self, status: &str) {
+        self.status = Some(status.to_owned());
+    }
+
+    pub fn has_status(&self) -> bool {
+        self.status.is_some()
+    }
+
+    pub async fn flush(&mut self, driver: &WebDriver) {
+        self.last_flush = Instant::now();
+
+        if self.messages.is_empty() && self.status.is_none() {
+            return;
+        }
+
+        let messages = std::mem::take(&mut self.messages);
+        self.dialect
+            .report(driver, &messages, self.status.take().as_deref())
+            .await;
+    }
+}
@@ -0,0 +1,76 @@
+//! Sauce Labs, which takes progress and outcome of a test through JavaScript executor commands.
+
+use super::{CapabilityField, GridDialect};
+use crate::priority::Priority;
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::json;
+use thirtyfour::WebDriver;
+
+pub struct SauceLabs {
+    /// Host of the web app belonging to the data center of the endpoint
+    app_host: String,
+}
+
+impl SauceLabs {
+    pub fn new(endpoint: &str) -> Self {
+        let host = Url::parse(endpoint)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_default();
+
+        let app_host = match host.strip_prefix("ondemand.") {
+            Some(domain) => format!("app.{}", domain),
+            None => "app.saucelabs.com".into(),
+        };
+
+        Self { app_host }
+    }
+}
+
+#[async_trait]
+impl GridDialect for SauceLabs {
+    fn name(&self) -> &'static str {
+        "Sauce Labs"
+    }
+
+    fn metadata_capabilities(
+        &self,
+        name: &str,
+        build: &str,
+        priority: Option<&Priority>,
+    ) -> Vec<CapabilityField> {
+        let mut fields = vec![
+            ("sauce:options", "name", json!(name)),
+            ("sauce:options", "build", json!(build)),
+        ];
+
+        // Sauce Labs queues jobs with a lower number first
+        if let Some(priority) = priority {
+            fields.push(("sauce:options", "priority", json!(priority.rank)));
+        }
+
+        fields
+    }
+
+    async fn report(&self, driver: &WebDriver, messages: &[String], status: Option<&str>) {
+        for message in messages {
+            let script = format!("sauce:context={}", message);
+            driver.execute_script(&script).await.ok();
+        }
+
+        if let Some(status) = status {
+            let result = if status == "success" {
+                "passed"
+            } else {
+                "failed"
+            };
+            let script = format!("sauce:job-result={}", result);
+            driver.execute_script(&script).await.ok();
+        }
+    }
+
+    fn session_url(&self, session_id: &str) -> Option<String> {
+        Some(format!("https://{}/tests/{}", self.app_host, session_id))
+    }
+}
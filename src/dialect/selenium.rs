@@ -0,0 +1,78 @@
+//! Selenium Grid 4, which has no way to report test outcomes but exposes its queue.
+
+use super::{CapabilityField, GridDialect};
+use crate::priority::Priority;
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::time::Duration;
+use thirtyfour::WebDriver;
+
+pub struct SeleniumGrid {
+    graphql: Option<Url>,
+    client: reqwest::Client,
+}
+
+impl SeleniumGrid {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            graphql: Url::parse(endpoint)
+                .and_then(|url| url.join("/graphql"))
+                .ok(),
+            client: client(),
+        }
+    }
+
+    /// Whether the endpoint answers `/status` the way Selenium Grid 4 does
+    pub async fn probe(endpoint: &str) -> bool {
+        let url = format!("{}/status", endpoint.trim_end_matches('/'));
+
+        let status = match client().get(&url).send().await {
+            Ok(response) => response.json::<Value>().await.ok(),
+            Err(_) => None,
+        };
+
+        status.map_or(false, |status| status["value"]["nodes"].is_array())
+    }
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build grid client")
+}
+
+#[async_trait]
+impl GridDialect for SeleniumGrid {
+    fn name(&self) -> &'static str {
+        "Selenium Grid"
+    }
+
+    fn metadata_capabilities(
+        &self,
+        _name: &str,
+        _build: &str,
+        _priority: Option<&Priority>,
+    ) -> Vec<CapabilityField> {
+        Vec::new()
+    }
+
+    async fn report(&self, _driver: &WebDriver, _messages: &[String], _status: Option<&str>) {}
+
+    async fn queue_size(&self) -> Option<u64> {
+        let query = json!({ "query": "{ grid { sessionQueueSize } }" });
+        let response = self
+            .client
+            .post(self.graphql.clone()?)
+            .json(&query)
+            .send()
+            .await
+            .ok()?
+            .json::<Value>()
+            .await
+            .ok()?;
+
+        response["data"]["grid"]["sessionQueueSize"].as_u64()
+    }
+}
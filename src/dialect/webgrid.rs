@@ -0,0 +1,107 @@
+//! WebGrid, which offers extensions of the WebDriver protocol for session metadata.
+//!
+//! Some deployments lack the metadata endpoint, which is why support is detected with the
+//! first session and the command is skipped for all later ones.
+
+use super::{CapabilityField, GridDialect};
+use crate::priority::Priority;
+use async_trait::async_trait;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use thirtyfour::{Cookie, ExtensionCommand, WebDriver};
+
+struct WebgridMetadataCommand {
+    fields: HashMap<String, String>,
+}
+
+impl ExtensionCommand for WebgridMetadataCommand {
+    fn parameters_json(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.fields.clone()).ok()
+    }
+
+    fn method(&self) -> thirtyfour::RequestMethod {
+        thirtyfour::RequestMethod::Post
+    }
+
+    fn endpoint(&self) -> String {
+        "/webgrid/metadata".into()
+    }
+}
+
+pub struct WebGrid {
+    metadata: AtomicBool,
+}
+
+impl WebGrid {
+    pub fn new() -> Self {
+        Self {
+            metadata: AtomicBool::new(true),
+        }
+    }
+}
+
+#[async_trait]
+impl GridDialect for WebGrid {
+    fn name(&self) -> &'static str {
+        "WebGrid"
+    }
+
+    fn metadata_capabilities(
+        &self,
+        name: &str,
+        build: &str,
+        priority: Option<&Priority>,
+    ) -> Vec<CapabilityField> {
+        let mut metadata = json!({ "name": name, "build": build });
+        if let Some(priority) = priority {
+            metadata["priority"] = json!(priority.label);
+        }
+
+        vec![("webgrid:options", "metadata", metadata)]
+    }
+
+    /// Sends runtime metadata unless the grid is known to lack the endpoint
+    async fn set_metadata(&self, driver: &WebDriver, fields: HashMap<String, String>) {
+        if !self.metadata.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let command = WebgridMetadataCommand { fields };
+        if let Err(e) = driver.extension_command(command).await {
+            if is_unsupported(&format!("{:?}", e)) && self.metadata.swap(false, Ordering::Relaxed) {
+                log::info!("Grid does not support /webgrid/metadata, no longer sending metadata");
+            }
+        }
+    }
+
+    /// Messages and status are picked up from cookies, messages are batched into one
+    async fn report(&self, driver: &WebDriver, messages: &[String], status: Option<&str>) {
+        if !messages.is_empty() {
+            let cookie = Cookie::new("webgrid:message", json!(messages.join("\n")));
+            driver.add_cookie(cookie).await.ok();
+        }
+
+        if let Some(status) = status {
+            let cookie = Cookie::new("webgrid:metadata.session:status", json!(status));
+            driver.add_cookie(cookie).await.ok();
+        }
+    }
+}
+
+/// Whether an error indicates the command is unknown rather than a transient failure
+fn is_unsupported(error: &str) -> bool {
+    let error = error.to_ascii_lowercase();
+
+    [
+        "unknowncommand",
+        "unknown command",
+        "notfound",
+        "not found",
+        "404",
+    ]
+    .iter()
+    .any(|indicator| error.contains(indicator))
+}
@@ -0,0 +1,128 @@
+//! Human readable descriptions of mismatching strings.
+//!
+//! Text read from a page frequently differs only by whitespace or invisible characters,
+//! which a plain `actual != expected` message hides completely.
+
+/// Strings above this many characters (combined) are not diffed to keep the quadratic table small
+const MAX_DIFF_LENGTH: usize = 2000;
+
+enum Edit {
+    Same(char),
+    Removed(char),
+    Added(char),
+}
+
+/// Renders a string with whitespace and invisible characters replaced by visible markers
+pub fn reveal(text: &str) -> String {
+    text.chars().map(reveal_char).collect()
+}
+
+fn reveal_char(c: char) -> String {
+    match c {
+        ' ' => "·".into(),
+        '\t' => "→".into(),
+        '\n' => "↵".into(),
+        '\r' => "␍".into(),
+        '\u{a0}' | '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}' => {
+            format!("\\u{{{:04x}}}", c as u32)
+        }
+        c if c.is_control() => format!("\\u{{{:04x}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// Describes how `actual` differs from `expected`, including a character-level diff
+/// where removed characters are shown as `[-x-]` and added ones as `{+x+}`
+pub fn describe_mismatch(actual: &str, expected: &str) -> String {
+    let mut description = format!(
+        "expected: \"{}\"\n  actual: \"{}\"",
+        reveal(expected),
+        reveal(actual)
+    );
+
+    let expected = expected.chars().collect::<Vec<_>>();
+    let actual = actual.chars().collect::<Vec<_>>();
+
+    if expected.len() + actual.len() <= MAX_DIFF_LENGTH {
+        description.push_str("\n    diff: ");
+        description.push_str(&render(&diff(&expected, &actual)));
+    }
+
+    description
+}
+
+/// Longest common subsequence based diff of two character sequences
+fn diff(expected: &[char], actual: &[char]) -> Vec<Edit> {
+    let (n, m) = (expected.len(), actual.len());
+
+    // lengths[i][j] holds the LCS length of expected[i..] and actual[j..]
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if expected[i] == actual[j] {
+            edits.push(Edit::Same(expected[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            edits.push(Edit::Removed(expected[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(actual[j]));
+            j += 1;
+        }
+    }
+
+    edits.extend(expected[i..].iter().map(|c| Edit::Removed(*c)));
+    edits.extend(actual[j..].iter().map(|c| Edit::Added(*c)));
+
+    edits
+}
+
+fn render(edits: &[Edit]) -> String {
+    let mut output = String::new();
+    let mut index = 0;
+
+    while index < edits.len() {
+        match edits[index] {
+            Edit::Same(c) => {
+                output.push_str(&reveal_char(c));
+                index += 1;
+            }
+            _ => {
+                // Group consecutive changes so the output reads as `[-old-]{+new+}`
+                let mut removed = String::new();
+                let mut added = String::new();
+
+                while let Some(edit) = edits.get(index) {
+                    match edit {
+                        Edit::Removed(c) => removed.push_str(&reveal_char(*c)),
+                        Edit::Added(c) => added.push_str(&reveal_char(*c)),
+                        Edit::Same(_) => break,
+                    }
+                    index += 1;
+                }
+
+                if !removed.is_empty() {
+                    output.push_str(&format!("[-{}-]", removed));
+                }
+
+                if !added.is_empty() {
+                    output.push_str(&format!("{{+{}+}}", added));
+                }
+            }
+        }
+    }
+
+    output
+}
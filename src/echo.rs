@@ -0,0 +1,112 @@
+//! Scenario verifying how browser traffic reaches the application, based on the `/echo` page of
+//! the embedded demo server.
+//!
+//! Expectations are given as `name=pattern` pairs separated by semicolons, where the name is
+//! either a request header or `remote` for the address the request came from, and the pattern
+//! is a regular expression the value has to match.
+
+use crate::{
+    assertions::{self, Expectation},
+    dialect::StatusChannel,
+    metrics::Steps,
+    scenario::{Scenario, Session},
+    variables::Variables,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use thirtyfour::prelude::*;
+
+/// Checks of the request as seen by the `/echo` page of the embedded demo server
+pub struct Echo {
+    pub page: String,
+    pub checks: Vec<Check>,
+}
+
+pub struct Check {
+    name: String,
+    expectation: Expectation,
+}
+
+/// Parses a list like `remote=^10\.; x-forwarded-for=.+`
+pub fn parse_checks(list: &str) -> Result<Vec<Check>> {
+    list.split(';')
+        .map(str::trim)
+        .filter(|check| !check.is_empty())
+        .map(|check| {
+            let (name, pattern) = check
+                .split_once('=')
+                .with_context(|| format!("Expected 'name=pattern', got '{}'", check))?;
+
+            Ok(Check {
+                name: name.trim().to_ascii_lowercase(),
+                expectation: Expectation::matches(pattern.trim())?,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Scenario for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    async fn run(&self, session: &mut Session<'_>) -> Result<()> {
+        run(
+            session.driver,
+            &self.page,
+            &self.checks,
+            session.variables,
+            &mut session.steps,
+            &mut session.status,
+        )
+        .await
+    }
+}
+
+async fn run(
+    driver: &WebDriver,
+    page: &str,
+    checks: &[Check],
+    variables: &mut Variables,
+    steps: &mut Steps<'_>,
+    status: &mut StatusChannel<'_>,
+) -> Result<()> {
+    steps.begin(driver, "echo").await?;
+    status.message(driver, "Visiting echo page").await;
+    driver.get(page).await?;
+
+    // Keep the address for invariants like `constant(remote_address)`
+    let remote = driver.find_element(By::Id("remote")).await?;
+    variables.capture_text("remote_address", &remote).await?;
+
+    steps.begin(driver, "headers").await?;
+    status.message(driver, "Checking request").await;
+    for check in checks {
+        let id = if check.name == "remote" {
+            "remote".to_owned()
+        } else {
+            format!("header-{}", check.name)
+        };
+
+        let element = match driver.find_element(By::Id(&id)).await {
+            Ok(element) => element,
+            Err(_) => {
+                status.set_status("failure");
+                bail!("Request did not carry header '{}'", check.name);
+            }
+        };
+
+        if let Err(e) = assertions::text(&element, &check.expectation).await {
+            status.set_status("failure");
+            bail!("Unexpected value of '{}': {:#}", check.name, e);
+        }
+    }
+
+    steps.finish();
+
+    status.message(driver, "Request looked as expected!").await;
+    status.set_status("success");
+
+    Ok(())
+}
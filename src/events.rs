@@ -0,0 +1,128 @@
+//! Append-only NDJSON log of everything that happens during a run.
+//!
+//! The log holds a single run, an existing file is replaced when the run starts. Each line is a JSON object with a `timestamp` and an `event` discriminator, written and
+//! flushed as the event happens so the log stays usable even if the run is killed. The path `-`
+//! streams the events to stdout instead, the regular log output goes to stderr.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted {
+        endpoint: &'a str,
+        browser: &'a str,
+        scenario: &'a str,
+        count: u64,
+        iterations: u64,
+    },
+    SessionScheduled {
+        test: &'a str,
+    },
+    SessionStarted {
+        test: &'a str,
+        session_id: &'a str,
+    },
+    StepStarted {
+        test: &'a str,
+        name: &'a str,
+    },
+    StepFinished {
+        test: &'a str,
+        name: &'a str,
+        duration_ms: u64,
+    },
+    SessionFinished {
+        test: &'a str,
+        /// Missing if the test did not get a session
+        session_id: Option<&'a str>,
+        success: bool,
+        duration_ms: u64,
+        error: Option<String>,
+        /// Session creation attempts, missing if the test reused a pooled session
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_attempts: Option<u32>,
+        /// Attempts the test made, missing unless failed tests are re-run
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attempts: Option<u32>,
+        /// Version of the browser the session reported, missing if the test did not get one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        browser_version: Option<&'a str>,
+        /// Scenario the test ran instead of the one of the run
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scenario: Option<&'a str>,
+        /// Version of the scripted journeys the test started with
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scenario_version: Option<&'a str>,
+        /// Screenshot and page source saved for the failed test
+        #[serde(skip_serializing_if = "Option::is_none")]
+        screenshot: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        page_source: Option<&'a str>,
+    },
+    RunFinished {
+        total: u64,
+        failed: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+pub struct EventLog {
+    file: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventLog {
+    pub fn create(path: &str) -> Result<Self> {
+        let file: Box<dyn Write + Send> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+                .with_context(|| format!("Failed to open event log '{}'", path))?;
+            Box::new(BufWriter::new(file))
+        };
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends an event, problems writing it are logged but never fail the run
+    pub fn emit(&self, event: Event<'_>) {
+        if let Err(e) = self.write(event) {
+            log::warn!("Failed to write event: {}", e);
+        }
+    }
+
+    fn write(&self, event: Event<'_>) -> Result<()> {
+        let record = Record {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            event,
+        };
+
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Prometheus endpoint publishing the state of a running run on `/metrics`.
+//!
+//! Besides the session counters, every latency metric (session creation, steps, total) is
+//! exported as a histogram labelled with its name, so dashboards can follow long soak runs live.
+
+use crate::metrics::Metrics;
+use anyhow::{Context, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use std::{convert::Infallible, fmt::Write as _, net::SocketAddr, sync::Arc};
+use tokio::spawn;
+
+/// Upper bounds of the histogram buckets in seconds
+const BUCKETS: [f64; 12] = [
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+/// Session counters at the time of a scrape
+pub struct Counters {
+    pub started: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Failures caused by the grid rather than the application
+    pub infrastructure_failures: u64,
+    /// Passed tests that failed at first and passed on a re-run
+    pub flaky: u64,
+    /// Retries of failed session creations
+    pub session_retries: u64,
+}
+
+/// Serves the metrics on the given port of all interfaces, `counters` is called on every scrape
+pub async fn start(
+    port: u16,
+    metrics: Arc<Metrics>,
+    counters: impl Fn() -> Counters + Send + Sync + 'static,
+) -> Result<SocketAddr> {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let exporter = Arc::new((metrics, counters));
+
+    let make_service = make_service_fn(move |_| {
+        let exporter = exporter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let exporter = exporter.clone();
+                async move {
+                    let response = if request.uri().path() == "/metrics" {
+                        let (metrics, counters) = exporter.as_ref();
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(render(metrics, &counters())))
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::from("Not found"))
+                    };
+
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .with_context(|| format!("Failed to bind metrics endpoint to port {}", port))?
+        .serve(make_service);
+    let local_address = server.local_addr();
+
+    spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Metrics endpoint stopped unexpectedly: {}", e);
+        }
+    });
+
+    Ok(local_address)
+}
+
+fn render(metrics: &Metrics, counters: &Counters) -> String {
+    let mut text = String::new();
+
+    let gauges = [(
+        "selenium_sessions_active",
+        "Tests currently in flight",
+        counters.started.saturating_sub(counters.completed),
+    )];
+    for (name, help, value) in gauges.iter() {
+        writeln!(text, "# HELP {} {}", name, help).ok();
+        writeln!(text, "# TYPE {} gauge", name).ok();
+        writeln!(text, "{} {}", name, value).ok();
+    }
+
+    let totals = [
+        (
+            "selenium_sessions_started_total",
+            "Tests started",
+            counters.started,
+        ),
+        (
+            "selenium_sessions_completed_total",
+            "Tests finished, successful or not",
+            counters.completed,
+        ),
+        (
+            "selenium_session_creation_retries_total",
+            "Retries of failed session creations",
+            counters.session_retries,
+        ),
+    ];
+    for (name, help, value) in totals.iter() {
+        writeln!(text, "# HELP {} {}", name, help).ok();
+        writeln!(text, "# TYPE {} counter", name).ok();
+        writeln!(text, "{} {}", name, value).ok();
+    }
+
+    text.push_str("# HELP selenium_failures_total Failed tests by the party that caused them\n");
+    text.push_str("# TYPE selenium_failures_total counter\n");
+    writeln!(
+        text,
+        "selenium_failures_total{{category=\"grid\"}} {}",
+        counters.infrastructure_failures
+    )
+    .ok();
+    writeln!(
+        text,
+        "selenium_failures_total{{category=\"test\"}} {}",
+        counters
+            .failed
+            .saturating_sub(counters.infrastructure_failures)
+    )
+    .ok();
+
+    // Tests that only passed when re-run are hidden instability, not a clean pass
+    text.push_str("# HELP selenium_passes_total Passed tests by the attempt they passed on\n");
+    text.push_str("# TYPE selenium_passes_total counter\n");
+    writeln!(
+        text,
+        "selenium_passes_total{{attempt=\"first\"}} {}",
+        counters
+            .completed
+            .saturating_sub(counters.failed)
+            .saturating_sub(counters.flaky)
+    )
+    .ok();
+    writeln!(
+        text,
+        "selenium_passes_total{{attempt=\"retry\"}} {}",
+        counters.flaky
+    )
+    .ok();
+
+    text.push_str(
+        "# HELP selenium_duration_seconds Durations of session creation, steps and tests\n",
+    );
+    text.push_str("# TYPE selenium_duration_seconds histogram\n");
+    for (metric, histogram) in metrics.snapshot_all() {
+        for bound in BUCKETS.iter() {
+            let count = histogram.count_between(0, (bound * 1000.0) as u64);
+            writeln!(
+                text,
+                "selenium_duration_seconds_bucket{{metric=\"{}\",le=\"{}\"}} {}",
+                metric, bound, count
+            )
+            .ok();
+        }
+        writeln!(
+            text,
+            "selenium_duration_seconds_bucket{{metric=\"{}\",le=\"+Inf\"}} {}",
+            metric,
+            histogram.len()
+        )
+        .ok();
+        writeln!(
+            text,
+            "selenium_duration_seconds_sum{{metric=\"{}\"}} {:.3}",
+            metric,
+            histogram.mean() * histogram.len() as f64 / 1000.0
+        )
+        .ok();
+        writeln!(
+            text,
+            "selenium_duration_seconds_count{{metric=\"{}\"}} {}",
+            metric,
+            histogram.len()
+        )
+        .ok();
+    }
+
+    text
+}
@@ -0,0 +1,24 @@
+//! Switches read from the environment.
+//!
+//! The command line parses the same variables for its flags, which accepts `1`, `yes` or `on` as
+//! well as `true`. Both have to agree, otherwise a value the command line took would fail the run
+//! once the runner reads it again.
+
+use anyhow::{bail, Context, Result};
+
+/// Parses a switch like `true`, `1`, `yes` or `on` and their opposites, ignoring case
+pub fn parse_flag(value: &str) -> Result<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "t" | "1" | "yes" | "y" | "on" => Ok(true),
+        "false" | "f" | "0" | "no" | "n" | "off" | "" => Ok(false),
+        _ => bail!("Expected a switch like true or false, got '{}'", value),
+    }
+}
+
+/// Reads the switch from the variable, `false` if it is not set
+pub(crate) fn env_flag(variable: &str) -> Result<bool> {
+    match std::env::var(variable) {
+        Ok(value) => parse_flag(&value).with_context(|| format!("Invalid {}", variable)),
+        Err(_) => Ok(false),
+    }
+}
@@ -0,0 +1,484 @@
+//! Standalone HTML report of a previous run, for sharing results with people who don't read logs.
+//!
+//! Charts are plain inline SVG, so the file has no dependencies and can be sent around as is.
+
+use crate::report;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Write as _, time::SystemTime};
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 200.0;
+const BUCKETS: u64 = 20;
+/// Height the rows of the session chart shrink to fit into, within bounds
+const GANTT_HEIGHT: f64 = 600.0;
+/// Dimensions of the step timeline of a single test
+const STEPS_WIDTH: f64 = 480.0;
+const STEP_LABEL: f64 = 160.0;
+const STEP_ROW: f64 = 18.0;
+
+/// Renders the run recorded in the event log into a single HTML file at `output`
+pub fn write(event_log: &str, output: &str) -> Result<()> {
+    let records = report::read(event_log)?;
+    let run = records.iter().find(|r| r["event"] == "run_started");
+    let finished = records
+        .iter()
+        .filter(|r| r["event"] == "session_finished")
+        .collect::<Vec<_>>();
+    let passed = finished.iter().filter(|r| r["success"] == true).count();
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Run report</title>\n<style>{}</style>\n</head>\n<body>",
+        STYLE
+    )?;
+
+    writeln!(html, "<h1>Run report</h1>")?;
+    if let Some(run) = run {
+        writeln!(
+            html,
+            "<p>{} {} sessions of the {} scenario against <code>{}</code>, started {}.</p>",
+            run["count"],
+            escape(run["browser"].as_str().unwrap_or_default()),
+            escape(run["scenario"].as_str().unwrap_or("unknown")),
+            escape(run["endpoint"].as_str().unwrap_or_default()),
+            escape(run["timestamp"].as_str().unwrap_or_default())
+        )?;
+    }
+
+    writeln!(html, "<h2>Outcomes</h2>")?;
+    writeln!(
+        html,
+        "<p>{} / {} tests succeeded.</p>",
+        passed,
+        finished.len()
+    )?;
+    html.push_str(&outcome_bar(passed, finished.len() - passed));
+
+    let mut matrix = report::Matrix::default();
+    matrix.add(&records);
+    if !matrix.is_empty() {
+        writeln!(html, "<h2>Browsers and scenarios</h2>")?;
+        html.push_str(&grid(&matrix));
+    }
+
+    let durations = finished
+        .iter()
+        .filter_map(|r| r["duration_ms"].as_u64())
+        .collect::<Vec<_>>();
+    writeln!(html, "<h2>Test durations</h2>")?;
+    html.push_str(&histogram(&durations));
+
+    writeln!(html, "<h2>Session creation</h2>")?;
+    html.push_str(&timeline(&records, run));
+
+    let tests = lifecycles(&records, run);
+    writeln!(html, "<h2>Sessions over time</h2>")?;
+    html.push_str(&gantt(&tests));
+
+    writeln!(html, "<h2>Tests</h2>")?;
+    html.push_str(&table(&records, &finished, &tests));
+
+    writeln!(html, "</body>\n</html>")?;
+
+    std::fs::write(output, html)
+        .with_context(|| format!("Failed to write HTML report '{}'", output))?;
+    log::info!("Wrote HTML report to '{}'", output);
+
+    Ok(())
+}
+
+/// Stacked bar of passed and failed tests
+fn outcome_bar(passed: usize, failed: usize) -> String {
+    let total = (passed + failed).max(1) as f64;
+    let split = WIDTH * passed as f64 / total;
+
+    format!(
+        "<svg width=\"{w}\" height=\"24\"><rect class=\"passed\" width=\"{p:.1}\" height=\"24\"/><rect class=\"failed\" x=\"{p:.1}\" width=\"{f:.1}\" height=\"24\"/></svg>\n",
+        w = WIDTH,
+        p = split,
+        f = WIDTH - split
+    )
+}
+
+/// Pass rate and p95 duration per browser version and scenario
+fn grid(matrix: &report::Matrix) -> String {
+    let scenarios = matrix.scenarios();
+
+    let mut table = String::from("<table>\n<tr><th></th>");
+    for scenario in scenarios.iter() {
+        table.push_str(&format!("<th>{}</th>", escape(scenario)));
+    }
+    table.push_str("</tr>\n");
+    for browser in matrix.browsers() {
+        table.push_str(&format!("<tr><th>{}</th>", escape(browser)));
+        for scenario in scenarios.iter() {
+            table.push_str(&format!(
+                "<td>{}</td>",
+                escape(&matrix.text(browser, scenario))
+            ));
+        }
+        table.push_str("</tr>\n");
+    }
+    table.push_str("</table>\n");
+
+    table
+}
+
+/// Bar chart of the test durations in equally wide buckets
+fn histogram(durations: &[u64]) -> String {
+    let max = match durations.iter().max() {
+        Some(max) => *max,
+        None => return "<p>No tests finished.</p>\n".to_owned(),
+    };
+
+    let width = (max / BUCKETS + 1).max(1);
+    let mut counts = vec![0u64; BUCKETS as usize];
+    for duration in durations {
+        counts[(duration / width).min(BUCKETS - 1) as usize] += 1;
+    }
+
+    let highest = *counts.iter().max().unwrap_or(&1) as f64;
+    let bar = WIDTH / BUCKETS as f64;
+    let mut svg = format!("<svg width=\"{}\" height=\"{}\">", WIDTH, HEIGHT + 20.0);
+    for (index, count) in counts.iter().enumerate() {
+        let height = HEIGHT * *count as f64 / highest;
+        svg.push_str(&format!(
+            "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{:.1}s to {:.1}s: {}</title></rect>",
+            bar * index as f64 + 1.0,
+            HEIGHT - height,
+            bar - 2.0,
+            height,
+            (width * index as u64) as f64 / 1000.0,
+            (width * (index as u64 + 1)) as f64 / 1000.0,
+            count
+        ));
+    }
+    svg.push_str(&format!(
+        "<text x=\"0\" y=\"{y}\">0s</text><text x=\"{w}\" y=\"{y}\" text-anchor=\"end\">{:.1}s</text></svg>\n",
+        (width * BUCKETS) as f64 / 1000.0,
+        y = HEIGHT + 16.0,
+        w = WIDTH
+    ));
+
+    svg
+}
+
+/// Seconds since the start of the run at which the event was recorded
+fn offset(record: &Value, run: Option<&Value>) -> Option<f64> {
+    let timestamp = humantime::parse_rfc3339(record["timestamp"].as_str()?).ok()?;
+    let start = humantime::parse_rfc3339(run?["timestamp"].as_str()?).ok()?;
+    Some(
+        timestamp
+            .duration_since(start)
+            .unwrap_or_default()
+            .as_secs_f64(),
+    )
+}
+
+/// Cumulative number of sessions the grid created over the course of the run
+fn timeline(records: &[Value], run: Option<&Value>) -> String {
+    let mut created = records
+        .iter()
+        .filter(|r| r["event"] == "session_started")
+        .filter_map(|record| offset(record, run))
+        .collect::<Vec<_>>();
+    created.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let end = match created.last() {
+        Some(end) => end.max(1.0),
+        None => return "<p>No sessions were created.</p>\n".to_owned(),
+    };
+    let total = created.len() as f64;
+
+    let mut points = format!("0,{}", HEIGHT);
+    for (index, at) in created.iter().enumerate() {
+        let x = WIDTH * at / end;
+        points.push_str(&format!(
+            " {:.1},{:.1} {:.1},{:.1}",
+            x,
+            HEIGHT * (1.0 - index as f64 / total),
+            x,
+            HEIGHT * (1.0 - (index + 1) as f64 / total)
+        ));
+    }
+
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\"><polyline class=\"line\" points=\"{}\"/><text x=\"0\" y=\"{y}\">0s</text><text x=\"{w}\" y=\"{y}\" text-anchor=\"end\">{:.0}s</text><text x=\"4\" y=\"14\">{} sessions</text></svg>\n",
+        points,
+        end,
+        created.len(),
+        w = WIDTH,
+        h = HEIGHT + 20.0,
+        y = HEIGHT + 16.0
+    )
+}
+
+/// Course of a test, in seconds since the start of the run
+#[derive(Default)]
+struct Lifecycle<'a> {
+    test: &'a str,
+    scheduled: Option<f64>,
+    /// When the test got its session, of its last attempt if it was re-run
+    started: Option<f64>,
+    finished: Option<f64>,
+    success: bool,
+    /// Steps as `(name, start, duration)`, the duration is missing if the step never finished
+    steps: Vec<(&'a str, f64, Option<f64>)>,
+}
+
+/// Lifecycles of all scheduled tests, in the order they were scheduled
+fn lifecycles<'a>(records: &'a [Value], run: Option<&Value>) -> Vec<Lifecycle<'a>> {
+    let mut tests: Vec<Lifecycle<'a>> = Vec::new();
+    let mut positions = HashMap::new();
+
+    for record in records {
+        let test = match record["test"].as_str() {
+            Some(test) => test,
+            None => continue,
+        };
+        let position = *positions.entry(test).or_insert_with(|| {
+            tests.push(Lifecycle {
+                test,
+                ..Lifecycle::default()
+            });
+            tests.len() - 1
+        });
+        let lifecycle = &mut tests[position];
+        let at = offset(record, run);
+
+        match record["event"].as_str() {
+            Some("session_scheduled") => lifecycle.scheduled = lifecycle.scheduled.or(at),
+            Some("session_started") => lifecycle.started = at,
+            Some("session_finished") => {
+                lifecycle.finished = at;
+                lifecycle.success = record["success"] == true;
+            }
+            Some("step_started") => {
+                if let (Some(name), Some(at)) = (record["name"].as_str(), at) {
+                    lifecycle.steps.push((name, at, None));
+                }
+            }
+            Some("step_finished") => {
+                let name = record["name"].as_str();
+                let step = lifecycle
+                    .steps
+                    .iter_mut()
+                    .rev()
+                    .find(|(step, _, duration)| Some(*step) == name && duration.is_none());
+                if let (Some(step), Some(duration)) = (step, record["duration_ms"].as_u64()) {
+                    step.2 = Some(duration as f64 / 1000.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    tests
+}
+
+/// One bar per test over the wall-clock time of the run, split into waiting for the session and
+/// running the scenario on it, which is colored by outcome
+fn gantt(tests: &[Lifecycle]) -> String {
+    let end = tests
+        .iter()
+        .filter_map(|test| test.finished.or(test.started).or(test.scheduled))
+        .fold(0.0, f64::max)
+        .max(1.0);
+    if tests.is_empty() {
+        return "<p>No tests were scheduled.</p>\n".to_owned();
+    }
+
+    let row = (GANTT_HEIGHT / tests.len() as f64).clamp(1.0, 12.0);
+    let height = row * tests.len() as f64;
+    let x = |at: f64| WIDTH * at / end;
+    let gap = if row > 3.0 { 1.0 } else { 0.0 };
+
+    let mut svg = format!("<svg width=\"{}\" height=\"{}\">", WIDTH, height + 20.0);
+    for (index, test) in tests.iter().enumerate() {
+        let y = row * index as f64;
+        let scheduled = test.scheduled.unwrap_or_default();
+        let outcome = match test.finished {
+            Some(_) if test.success => "passed",
+            Some(_) => "failed",
+            None => "unfinished",
+        };
+
+        // Tests that never got a session spent all their time waiting for one
+        let waited = test.started.or(test.finished).unwrap_or(end);
+        svg.push_str(&format!(
+            "<rect class=\"{}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>Test #{}: waited {:.1}s for a session</title></rect>",
+            if test.started.is_some() { "creating" } else { outcome },
+            x(scheduled),
+            y,
+            x(waited - scheduled).max(1.0),
+            row - gap,
+            escape(test.test),
+            waited - scheduled
+        ));
+        if let Some(started) = test.started {
+            let finished = test.finished.unwrap_or(end);
+            svg.push_str(&format!(
+                "<rect class=\"{}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>Test #{}: ran {:.1}s, {}</title></rect>",
+                outcome,
+                x(started),
+                y,
+                x(finished - started).max(1.0),
+                row - gap,
+                escape(test.test),
+                finished - started,
+                outcome
+            ));
+        }
+    }
+    svg.push_str(&format!(
+        "<text x=\"0\" y=\"{y}\">0s</text><text x=\"{w}\" y=\"{y}\" text-anchor=\"end\">{:.0}s</text></svg>\n",
+        end,
+        y = height + 16.0,
+        w = WIDTH
+    ));
+
+    svg
+}
+
+/// Steps of a test on its own time axis, from getting its session until it finished. Steps that
+/// never finished end with the test and are marked as failed.
+fn steps(test: &Lifecycle) -> String {
+    let first = match test.steps.first() {
+        Some((_, start, _)) => *start,
+        None => return String::new(),
+    };
+    let start = test.started.unwrap_or(first).min(first);
+    let end = test
+        .steps
+        .iter()
+        .map(|(_, at, duration)| at + duration.unwrap_or_default())
+        .chain(test.finished)
+        .fold(start, f64::max);
+    let span = (end - start).max(0.001);
+    let area = STEPS_WIDTH - STEP_LABEL;
+
+    let mut svg = format!(
+        "<svg width=\"{}\" height=\"{}\">",
+        STEPS_WIDTH,
+        STEP_ROW * test.steps.len() as f64
+    );
+    for (index, (name, at, duration)) in test.steps.iter().enumerate() {
+        let y = STEP_ROW * index as f64;
+        let length = duration.unwrap_or(end - at);
+        svg.push_str(&format!(
+            "<text x=\"0\" y=\"{:.1}\">{}</text><rect class=\"{}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}: {:.2}s from {:.2}s{}</title></rect>",
+            y + 13.0,
+            escape(name),
+            if duration.is_some() { "bar" } else { "failed" },
+            STEP_LABEL + area * (at - start) / span,
+            y + 2.0,
+            (area * length / span).max(1.0),
+            STEP_ROW - 4.0,
+            escape(name),
+            length,
+            at - start,
+            if duration.is_some() { "" } else { ", never finished" }
+        ));
+    }
+    svg.push_str("</svg>");
+
+    svg
+}
+
+/// Links to the screenshot and page source of a failed test, the screenshot shows on hover
+fn artifacts(record: &Value) -> String {
+    let mut links = String::new();
+    if let Some(screenshot) = record["screenshot"].as_str() {
+        links.push_str(&format!(
+            "<a class=\"artifact\" href=\"{0}\">screenshot<img src=\"{0}\" alt=\"\"></a> ",
+            escape(screenshot)
+        ));
+    }
+    if let Some(page_source) = record["page_source"].as_str() {
+        links.push_str(&format!(
+            "<a class=\"artifact\" href=\"{}\">page source</a>",
+            escape(page_source)
+        ));
+    }
+
+    links
+}
+
+/// One row per finished test with the session it ran on, its steps and its error
+fn table(records: &[Value], finished: &[&Value], tests: &[Lifecycle]) -> String {
+    let sessions = records
+        .iter()
+        .filter(|r| r["event"] == "session_started")
+        .filter_map(|r| Some((r["test"].as_str()?, r["session_id"].as_str()?)))
+        .collect::<HashMap<_, _>>();
+    let tests = tests
+        .iter()
+        .map(|test| (test.test, test))
+        .collect::<HashMap<_, _>>();
+
+    let mut table = String::from(
+        "<table>\n<tr><th>Test</th><th>Session</th><th>Outcome</th><th>Duration</th><th>Error</th></tr>\n",
+    );
+    for record in finished {
+        let test = record["test"].as_str().unwrap_or_default();
+        let success = record["success"] == true;
+        let duration = format!(
+            "{:.1}s",
+            record["duration_ms"].as_u64().unwrap_or_default() as f64 / 1000.0
+        );
+        // Expands into the timeline of the steps of the test
+        let duration = match tests.get(test).map(|test| steps(test)) {
+            Some(steps) if !steps.is_empty() => {
+                format!(
+                    "<details><summary>{}</summary>{}</details>",
+                    duration, steps
+                )
+            }
+            _ => duration,
+        };
+        table.push_str(&format!(
+            "<tr><td>{}</td><td><code>{}</code></td><td class=\"{}\">{}</td><td>{}</td><td>{}{}</td></tr>",
+            escape(test),
+            escape(sessions.get(test).copied().unwrap_or("-")),
+            if success { "passed" } else { "failed" },
+            if success { "passed" } else { "failed" },
+            duration,
+            escape(record["error"].as_str().unwrap_or_default()),
+            artifacts(record)
+        ));
+    }
+    table.push_str("</table>\n");
+
+    table.push_str(&format!(
+        "<p class=\"generated\">Generated {}</p>\n",
+        humantime::format_rfc3339_seconds(SystemTime::now())
+    ));
+    table
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str =
+    "body { font-family: sans-serif; margin: 2em auto; max-width: 960px; color: #222; }
+svg { display: block; margin: 1em 0; font-size: 12px; }
+.passed { fill: #4caf50; color: #2e7d32; }
+.failed { fill: #e53935; color: #c62828; }
+.bar { fill: #5c6bc0; }
+.line { fill: none; stroke: #5c6bc0; stroke-width: 2; }
+.creating { fill: #bdbdbd; }
+.unfinished { fill: #ffb300; }
+details svg { margin: 0.5em 0; }
+.artifact { display: inline-block; margin-top: 0.5em; margin-right: 1em; }
+.artifact img { display: none; position: absolute; max-width: 480px; border: 1px solid #ddd; background: #fff; }
+.artifact:hover img { display: block; }
+table { border-collapse: collapse; width: 100%; font-size: 14px; }
+th, td { border-bottom: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }
+td:last-child { white-space: pre-wrap; }
+.generated { color: #888; font-size: 12px; }";
@@ -0,0 +1,201 @@
+//! Conversion of web server access logs into replay files, so load tests mirror the shape of real
+//! traffic.
+//!
+//! Requests are read in the Common or Combined Log Format of Apache and nginx. Every request whose
+//! path matches one of the mappings becomes an arrival running the mapped scenario, requests that
+//! match none (assets, health checks, ...) are skipped.
+
+use anyhow::{bail, Context, Result};
+use humantime::format_duration;
+use regex::{Captures, Regex};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    time::{Duration, UNIX_EPOCH},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Start of a request line in the Common and Combined Log Format, capturing the timestamp and
+/// path. Digits are spelled out, `\d` would match those of every script.
+const REQUEST: &str = r#"^\S+ \S+ \S+ \[([0-9]{2})/(\w{3})/([0-9]{4}):([0-9]{2}):([0-9]{2}):([0-9]{2}) ([+-])([0-9]{2})([0-9]{2})\] "\S+ (\S+)[^"]*""#;
+
+/// Requests whose path matches `pattern` run `scenario`
+#[derive(Clone)]
+pub struct Mapping {
+    pattern: Regex,
+    scenario: String,
+}
+
+/// Parses a mapping given as `pattern=scenario`
+pub fn parse_mapping(text: &str) -> Result<Mapping> {
+    // Patterns may contain `=` themselves, e.g. to match query parameters
+    let (pattern, scenario) = text
+        .rsplit_once('=')
+        .context("Expected a mapping of the form pattern=scenario")?;
+    if scenario.is_empty() {
+        bail!("Missing scenario of the mapping '{}'", text);
+    }
+
+    Ok(Mapping {
+        pattern: Regex::new(pattern).with_context(|| format!("Invalid pattern '{}'", pattern))?,
+        scenario: scenario.to_owned(),
+    })
+}
+
+/// Writes a replay file with one arrival per mapped request of the access log
+pub fn convert(access_log: &str, mappings: &[Mapping], output: &str) -> Result<()> {
+    let content = std::fs::read_to_string(access_log)
+        .with_context(|| format!("Failed to read access log '{}'", access_log))?;
+    let request = Regex::new(REQUEST).unwrap();
+
+    let mut arrivals = Vec::new();
+    let mut unmatched = 0;
+    let mut malformed = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let (timestamp, path) = match request
+            .captures(line)
+            .and_then(|captures| Some((timestamp(&captures)?, captures.get(10)?.as_str())))
+        {
+            Some(request) => request,
+            None => {
+                malformed += 1;
+                continue;
+            }
+        };
+
+        match mappings
+            .iter()
+            .find(|mapping| mapping.pattern.is_match(path))
+        {
+            Some(mapping) => arrivals.push((timestamp, mapping.scenario.as_str())),
+            None => unmatched += 1,
+        }
+    }
+
+    if arrivals.is_empty() {
+        bail!("None of the requests in '{}' matched a mapping", access_log);
+    }
+
+    arrivals.sort_by_key(|(timestamp, _)| *timestamp);
+    let mut replay = String::new();
+    let mut scenarios = BTreeMap::new();
+    for (timestamp, scenario) in arrivals.iter() {
+        let time = UNIX_EPOCH + Duration::from_secs(*timestamp);
+        replay.push_str(&format!(
+            "{} {}\n",
+            humantime::format_rfc3339_seconds(time),
+            scenario
+        ));
+        *scenarios.entry(*scenario).or_insert(0) += 1;
+    }
+    std::fs::write(output, replay)
+        .with_context(|| format!("Failed to write replay file '{}'", output))?;
+
+    let span = arrivals[arrivals.len() - 1].0 - arrivals[0].0;
+    log::info!(
+        "Converted {} requests over {} into '{}'",
+        arrivals.len(),
+        format_duration(Duration::from_secs(span)),
+        output
+    );
+    for (scenario, count) in scenarios.iter() {
+        log::info!("  {}: {}", scenario, count);
+    }
+    if unmatched > 0 {
+        log::info!("Skipped {} requests that matched no mapping", unmatched);
+    }
+    if malformed > 0 {
+        log::warn!(
+            "Skipped {} lines that are not in a known log format",
+            malformed
+        );
+    }
+
+    Ok(())
+}
+
+/// Seconds since the epoch of a request timestamp like `10/Oct/2000:13:55:36 -0700`
+fn timestamp(captures: &Captures) -> Option<u64> {
+    let number = |index: usize| captures[index].parse::<i64>().ok();
+
+    let month = MONTHS.iter().position(|month| *month == &captures[2])? as i64 + 1;
+    let days = days_from_civil(number(3)?, month, number(1)?);
+    let local = days * 86400 + number(4)? * 3600 + number(5)? * 60 + number(6)?;
+    let offset = (number(8)? * 3600 + number(9)? * 60) * if &captures[7] == "-" { -1 } else { 1 };
+
+    u64::try_from(local - offset).ok()
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timestamp and path of a log line
+    fn parse(line: &str) -> Option<(u64, String)> {
+        let captures = Regex::new(REQUEST).unwrap().captures(line)?;
+        Some((timestamp(&captures)?, captures[10].to_owned()))
+    }
+
+    #[test]
+    fn days_from_civil_matches_reference_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        assert_eq!(days_from_civil(1600, 1, 1), -135140);
+    }
+
+    #[test]
+    fn parses_the_common_log_format() {
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326"#;
+        assert_eq!(parse(line), Some((971211336, "/apache_pb.gif".into())));
+    }
+
+    #[test]
+    fn parses_the_combined_log_format() {
+        let line = r#"10.0.0.1 - - [29/Feb/2024:00:00:00 +0530] "POST /cart?item=42 HTTP/1.1" 302 0 "https://example.com/" "Mozilla/5.0""#;
+        assert_eq!(parse(line), Some((1709145000, "/cart?item=42".into())));
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        assert_eq!(parse("2000-10-10T13:55:36Z GET /"), None);
+        assert_eq!(
+            parse(r#"127.0.0.1 - - [10/Foo/2000:13:55:36 -0700] "GET / HTTP/1.0" 200 1"#),
+            None
+        );
+        assert_eq!(
+            parse(r#"127.0.0.1 - - [١٠/Oct/٢٠٠٠:13:55:36 -0700] "GET / HTTP/1.0" 200 1"#),
+            None
+        );
+        // Timestamps before the epoch can not be replayed
+        assert_eq!(
+            parse(r#"127.0.0.1 - - [01/Jan/1970:00:00:00 +0100] "GET / HTTP/1.0" 200 1"#),
+            None
+        );
+    }
+
+    #[test]
+    fn mappings_split_at_the_last_equals_sign() {
+        let mapping = parse_mapping("^/search\\?q=.*=search").unwrap();
+        assert_eq!(mapping.scenario, "search");
+        assert!(mapping.pattern.is_match("/search?q=shoes"));
+
+        assert!(parse_mapping("^/cart").is_err());
+        assert!(parse_mapping("^/cart=").is_err());
+        assert!(parse_mapping("(=cart").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Conditions over the variables captured by all sessions, evaluated once the run is over.
+//!
+//! Some server-side bugs (e.g. duplicate IDs handed out under concurrency) are invisible
+//! to a single session and only show up when comparing sessions with each other.
+
+use crate::variables::Variables;
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+pub enum Invariant {
+    /// No two sessions captured the same value
+    Unique(String),
+    /// Every session captured the same value
+    Constant(String),
+}
+
+impl Invariant {
+    fn variable(&self) -> &str {
+        match self {
+            Invariant::Unique(name) | Invariant::Constant(name) => name,
+        }
+    }
+
+    /// Evaluates the invariant, ignoring sessions which never captured the variable
+    pub fn check(&self, sessions: &[Variables]) -> Result<()> {
+        let name = self.variable();
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+
+        for variables in sessions {
+            if let Ok(value) = variables.get(name) {
+                *occurrences.entry(value).or_default() += 1;
+            }
+        }
+
+        match self {
+            Invariant::Unique(_) => {
+                let mut duplicates = occurrences
+                    .iter()
+                    .filter(|(_, count)| **count > 1)
+                    .map(|(value, count)| format!("'{}' ({}x)", value, count))
+                    .collect::<Vec<_>>();
+
+                if !duplicates.is_empty() {
+                    duplicates.sort();
+                    bail!("duplicate values {}", duplicates.join(", "));
+                }
+            }
+            Invariant::Constant(_) => {
+                if occurrences.len() > 1 {
+                    let mut values = occurrences.keys().collect::<Vec<_>>();
+                    values.sort();
+                    bail!("{} distinct values {:?}", values.len(), values);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Invariant {
+    type Err = anyhow::Error;
+
+    fn from_str(declaration: &str) -> Result<Self> {
+        let declaration = declaration.trim();
+        let (kind, rest) = declaration
+            .split_once('(')
+            .with_context(|| format!("Expected '<kind>(<variable>)', got '{}'", declaration))?;
+        let name = rest
+            .strip_suffix(')')
+            .with_context(|| format!("Missing closing parenthesis in '{}'", declaration))?
+            .trim()
+            .to_owned();
+
+        match kind.trim() {
+            "unique" => Ok(Invariant::Unique(name)),
+            "constant" => Ok(Invariant::Constant(name)),
+            other => bail!("Unknown invariant '{}'", other),
+        }
+    }
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invariant::Unique(name) => write!(f, "unique({})", name),
+            Invariant::Constant(name) => write!(f, "constant({})", name),
+        }
+    }
+}
+
+/// Parses a comma separated list like `unique(token),constant(build)`
+pub fn parse_list(list: &str) -> Result<Vec<Invariant>> {
+    list.split(',')
+        .filter(|declaration| !declaration.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
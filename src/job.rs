@@ -0,0 +1,186 @@
+//! Entrypoint of container jobs (e.g. Kubernetes Jobs), configured through the environment only.
+//!
+//! Only variables with the `PST_` prefix configure the run. They are passed on without the prefix
+//! to a separate instance of this binary whose environment holds nothing else, so variables the
+//! platform injects (Kubernetes adds `<SERVICE>_PORT` for every service, for example) can not
+//! change the run by accident. Files like configurations, replay files or proxy rules are mounted
+//! into the container and referenced by their path.
+//!
+//! The results go into a directory, usually a mounted volume, and a completion marker is written
+//! next to them once the run finished, so whoever collects them knows when they are complete.
+//! Platforms stop jobs with SIGTERM, which is passed on to the run as an interrupt, so it quits
+//! its sessions and the marker is only written once it exited.
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    process::ExitStatus,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::{Child, Command};
+
+const PREFIX: &str = "PST_";
+
+/// Variables of the container the run still needs
+pub const INHERITED: [&str; 6] = [
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "TZ",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+];
+
+/// Results written into the results directory unless their variable is set, as
+/// `(variable, file name)`
+const RESULTS: [(&str, &str); 5] = [
+    ("EVENT_LOG", "events.ndjson"),
+    ("JUNIT_REPORT", "junit.xml"),
+    ("ALLURE_RESULTS", "allure-results"),
+    ("SCREENSHOT_DIR", "artifacts"),
+    ("MANIFEST", "manifest.json"),
+];
+
+/// Completion marker holding the exit code of the run
+const MARKER: &str = "done.json";
+
+/// Executes the run configured by the prefixed variables, returns its exit code
+pub async fn execute(results: &str) -> Result<i32> {
+    let results = Path::new(results);
+    std::fs::create_dir_all(results)
+        .with_context(|| format!("Failed to create results directory '{}'", results.display()))?;
+
+    // A restarted job must not look finished before it is
+    let marker = results.join(MARKER);
+    if marker.exists() {
+        std::fs::remove_file(&marker)
+            .context("Failed to remove the completion marker of a previous attempt")?;
+    }
+
+    let mut variables = std::env::vars()
+        .filter_map(|(key, value)| Some((key.strip_prefix(PREFIX)?.to_owned(), value)))
+        .filter(|(key, _)| key != "RESULTS")
+        .collect::<BTreeMap<_, _>>();
+    for (variable, file) in RESULTS.iter() {
+        variables
+            .entry(variable.to_string())
+            .or_insert_with(|| results.join(file).to_string_lossy().into_owned());
+    }
+    let config = variables.remove("CONFIG");
+
+    // The run replaces the event log, the one of an interrupted attempt is kept aside
+    if let Some(events) = variables.get("EVENT_LOG").filter(|path| *path != "-") {
+        let events = Path::new(events);
+        if events.exists() {
+            let previous = format!("{}.previous", events.display());
+            std::fs::rename(events, &previous).with_context(|| {
+                format!(
+                    "Failed to keep the event log of a previous attempt as '{}'",
+                    previous
+                )
+            })?;
+        }
+    }
+
+    log::info!(
+        "Starting the run with {} variables, writing its results to '{}'",
+        variables.len(),
+        results.display()
+    );
+
+    let executable = std::env::current_exe().context("Failed to locate own executable")?;
+    let started = Instant::now();
+    let mut command = Command::new(executable);
+    command
+        .arg("run")
+        .args(config.iter().flat_map(|path| ["--config", path.as_str()]))
+        .env_clear()
+        .envs(
+            INHERITED
+                .iter()
+                .filter_map(|key| Some((*key, std::env::var_os(key)?))),
+        )
+        .envs(variables.iter());
+    // Signals reach the run only through the job, a Ctrl+C in a terminal would otherwise
+    // interrupt it twice and make it exit without quitting its sessions
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command.spawn().context("Failed to start the run")?;
+    let status = wait(&mut child)
+        .await
+        .context("Failed to wait for the run")?;
+    // Runs killed by a signal count as interrupted
+    let exit_code = status.code().unwrap_or(130);
+
+    let summary = json!({
+        "exit_code": exit_code,
+        "duration_ms": started.elapsed().as_millis() as u64,
+        "finished_at": SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    });
+    // Renamed into place, so the marker is never seen half written
+    let partial = results.join(format!("{}.partial", MARKER));
+    std::fs::write(&partial, summary.to_string())
+        .with_context(|| format!("Failed to write '{}'", partial.display()))?;
+    std::fs::rename(&partial, &marker)
+        .with_context(|| format!("Failed to write '{}'", marker.display()))?;
+
+    log::info!(
+        "The run exited with code {}, wrote '{}'",
+        exit_code,
+        marker.display()
+    );
+
+    Ok(exit_code)
+}
+
+/// Waits for the run to exit, passing SIGTERM and SIGINT on as SIGINT, which the run handles like
+/// a Ctrl+C: the first one quits the sessions in flight, the second one exits right away
+#[cfg(unix)]
+async fn wait(child: &mut Child) -> Result<ExitStatus> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    loop {
+        let received = tokio::select! {
+            status = child.wait() => return Ok(status?),
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        };
+
+        if let Some(pid) = child.id() {
+            log::warn!("Received {}, interrupting the run", received);
+            // SAFETY: `kill` has no memory safety requirements, the ID belongs to our own child
+            // which has not been reaped yet
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGINT);
+            }
+        }
+    }
+}
+
+/// Waits for the run to exit. The run shares the console and receives Ctrl+C and Ctrl+Break
+/// itself, the job only has to outlive it to write the marker.
+#[cfg(windows)]
+async fn wait(child: &mut Child) -> Result<ExitStatus> {
+    use tokio::signal::windows::{ctrl_break, ctrl_c};
+
+    let mut interrupt = ctrl_c()?;
+    let mut ctrl_break = ctrl_break()?;
+    loop {
+        let received = tokio::select! {
+            status = child.wait() => return Ok(status?),
+            _ = interrupt.recv() => "Ctrl+C",
+            _ = ctrl_break.recv() => "Ctrl+Break",
+        };
+
+        log::warn!("Received {}, waiting for the run to exit", received);
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn wait(child: &mut Child) -> Result<ExitStatus> {
+    Ok(child.wait().await?)
+}
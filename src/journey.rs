@@ -0,0 +1,108 @@
+//! Journey through the shop of the embedded demo server: list, detail, order form and
+//! confirmation, one navigation each.
+
+use crate::{
+    assertions::{self, Expectation},
+    dialect::StatusChannel,
+    metrics::Steps,
+    scenario::{Scenario, Session},
+    variables::Variables,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::time::SystemTime;
+use thirtyfour::prelude::*;
+
+/// Multi-page journey through the shop of the embedded demo server, starting at the given URL
+pub struct Journey {
+    pub entry: String,
+}
+
+#[async_trait]
+impl Scenario for Journey {
+    fn name(&self) -> &str {
+        "journey"
+    }
+
+    async fn run(&self, session: &mut Session<'_>) -> Result<()> {
+        run(
+            session.driver,
+            &self.entry,
+            session.variables,
+            &mut session.steps,
+            &mut session.status,
+        )
+        .await
+    }
+}
+
+async fn run(
+    driver: &WebDriver,
+    entry: &str,
+    variables: &mut Variables,
+    steps: &mut Steps<'_>,
+    status: &mut StatusChannel<'_>,
+) -> Result<()> {
+    // 1. Pick a product from the list, varying between sessions
+    steps.begin(driver, "list").await?;
+    status.message(driver, "Browsing products").await;
+    steps.navigate(driver, entry).await?;
+    assertions::subresources(driver).await?;
+    let products = driver.find_elements(By::ClassName("product")).await?;
+    if products.is_empty() {
+        status.set_status("failure");
+        bail!("Product list is empty");
+    }
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .subsec_nanos() as usize;
+    let product = &products[seed % products.len()];
+    variables.capture_text("product", product).await?;
+    product.click().await?;
+
+    // 2. Check that the detail page belongs to the selected product
+    steps.begin(driver, "detail").await?;
+    status.message(driver, "Checking product details").await;
+    assertions::subresources(driver).await?;
+    let name = steps.find(driver, By::Id("name")).await?;
+    let expected_name = Expectation::equals(variables.interpolate("${product}")?);
+    if let Err(e) = assertions::text(&name, &expected_name).await {
+        status.set_status("failure");
+        bail!("Detail page shows the wrong product: {:#}", e);
+    }
+    steps.find(driver, By::Id("order")).await?.click().await?;
+
+    // 3. Fill in and submit the order form
+    steps.begin(driver, "order").await?;
+    status.message(driver, "Placing order").await;
+    assertions::subresources(driver).await?;
+    let quantity = steps.find(driver, By::Id("quantity")).await?;
+    quantity.clear().await?;
+    quantity.send_keys("2").await?;
+    variables.set("customer", "Journey Tester");
+    steps
+        .find(driver, By::Id("customer"))
+        .await?
+        .send_keys(variables.get("customer")?)
+        .await?;
+    steps.find(driver, By::Id("submit")).await?.click().await?;
+
+    // 4. Check that the confirmation matches what was ordered
+    steps.begin(driver, "confirmation").await?;
+    status.message(driver, "Checking confirmation").await;
+    assertions::subresources(driver).await?;
+    let summary = steps.find(driver, By::Id("summary")).await?;
+    let expected_summary =
+        Expectation::equals(variables.interpolate("2 × ${product} for ${customer}")?);
+    if let Err(e) = assertions::text(&summary, &expected_summary).await {
+        status.set_status("failure");
+        bail!("Order confirmation is wrong: {:#}", e);
+    }
+
+    steps.finish();
+
+    status.message(driver, "Journey completed!").await;
+    status.set_status("success");
+
+    Ok(())
+}
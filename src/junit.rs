@@ -0,0 +1,281 @@
+//! Results in the JUnit XML format understood by most CI servers.
+//!
+//! Every test becomes one `<testcase>` with the grid session it ran on as a property. Its class
+//! is the browser version and scenario, as `<browser> <version>.<scenario>`, so CI servers group
+//! the results into the same grid as `report`. Failed tests get their step breadcrumbs and the
+//! files saved for them as `<system-out>`, the screenshot as an `[[ATTACHMENT|path]]` which
+//! Jenkins and GitLab show inline. The totals of a JUnit report precede its test cases, so the
+//! cases are spooled to `<path>.cases` as the tests finish and the report is assembled from the
+//! spool once the run is done. Only the totals are kept in memory.
+
+use crate::variables::Variables;
+use anyhow::{Context, Result};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Files saved for failed tests, as `(variable with the path, label)`
+const ARTIFACTS: [(&str, &str); 3] = [
+    ("screenshot", "Screenshot"),
+    ("page_source", "Page source"),
+    ("console_log", "Console log"),
+];
+
+/// Test cases written so far, with the totals of the report
+struct Spool {
+    file: BufWriter<File>,
+    tests: usize,
+    failures: usize,
+    time: Duration,
+}
+
+pub struct JunitReport {
+    path: String,
+    spool: Mutex<Spool>,
+}
+
+impl JunitReport {
+    pub fn new(path: &str) -> Result<Self> {
+        let file = File::create(spool_path(path))
+            .with_context(|| format!("Failed to create JUnit report '{}'", path))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            spool: Mutex::new(Spool {
+                file: BufWriter::new(file),
+                tests: 0,
+                failures: 0,
+                time: Duration::ZERO,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Records a finished test of the given browser and scenario, `failure` holds the error of
+    /// failed ones
+    pub fn record(
+        &self,
+        test: &str,
+        (browser, scenario): (&str, &str),
+        duration: Duration,
+        variables: &Variables,
+        failure: Option<&str>,
+    ) {
+        let case = match test_case(test, (browser, scenario), duration, variables, failure) {
+            Ok(case) => case,
+            Err(e) => {
+                log::warn!("Failed to record test #{} in the JUnit report: {}", test, e);
+                return;
+            }
+        };
+
+        let mut spool = self.spool.lock().unwrap();
+        if let Err(e) = spool.file.write_all(case.as_bytes()) {
+            log::warn!("Failed to record test #{} in the JUnit report: {}", test, e);
+            return;
+        }
+        spool.tests += 1;
+        spool.failures += failure.is_some() as usize;
+        spool.time += duration;
+    }
+
+    /// Writes the report file from the spooled tests and removes the spool
+    pub fn write(&self) -> Result<()> {
+        let mut spool = self.spool.lock().unwrap();
+        let spooled = spool_path(&self.path);
+        spool
+            .file
+            .flush()
+            .with_context(|| format!("Failed to write '{}'", spooled))?;
+
+        let write = || -> Result<()> {
+            let mut report = BufWriter::new(File::create(&self.path)?);
+            writeln!(report, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+            writeln!(
+                report,
+                "<testsuite name=\"ParallelSeleniumTest\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+                spool.tests,
+                spool.failures,
+                spool.time.as_secs_f64()
+            )?;
+            std::io::copy(&mut File::open(&spooled)?, &mut report)?;
+            writeln!(report, "</testsuite>")?;
+            report.flush()?;
+            Ok(())
+        };
+        write().with_context(|| format!("Failed to write JUnit report '{}'", self.path))?;
+        std::fs::remove_file(&spooled)
+            .with_context(|| format!("Failed to remove '{}'", spooled))?;
+
+        log::info!(
+            "Wrote JUnit report of {} tests to '{}'",
+            spool.tests,
+            self.path
+        );
+
+        Ok(())
+    }
+}
+
+/// File the test cases are written to until the report is assembled
+fn spool_path(path: &str) -> String {
+    format!("{}.cases", path)
+}
+
+/// `<testcase>` element of a finished test
+fn test_case(
+    test: &str,
+    (browser, scenario): (&str, &str),
+    duration: Duration,
+    variables: &Variables,
+    failure: Option<&str>,
+) -> Result<String> {
+    let mut xml = String::new();
+    writeln!(
+        xml,
+        "  <testcase classname=\"{}.{}\" name=\"Test #{}\" time=\"{:.3}\">",
+        escape(browser),
+        escape(scenario),
+        escape(test),
+        duration.as_secs_f64()
+    )?;
+    if let Ok(session_id) = variables.get("session_id") {
+        writeln!(xml, "    <properties>")?;
+        writeln!(
+            xml,
+            "      <property name=\"session_id\" value=\"{}\"/>",
+            escape(session_id)
+        )?;
+        writeln!(xml, "    </properties>")?;
+    }
+    if let Some(failure) = failure {
+        // The first line is enough for the summary, the whole error goes into the body
+        let message = failure.lines().next().unwrap_or_default();
+        writeln!(
+            xml,
+            "    <failure message=\"{}\">{}</failure>",
+            escape(message),
+            escape(failure)
+        )?;
+        if let Some(output) = output(variables) {
+            writeln!(xml, "    <system-out>{}</system-out>", escape(&output))?;
+        }
+    }
+    writeln!(xml, "  </testcase>")?;
+
+    Ok(xml)
+}
+
+/// Step breadcrumbs and saved files of a failed test, `None` if there is nothing to show
+fn output(variables: &Variables) -> Option<String> {
+    let mut lines = Vec::new();
+    if let Ok(steps) = variables.get("steps") {
+        lines.push(format!("Steps: {}", steps));
+    }
+    if let Ok(step) = variables.get("failed_step") {
+        lines.push(format!("Failed step: {}", step));
+    }
+    for (variable, label) in ARTIFACTS.iter() {
+        if let Ok(path) = variables.get(variable) {
+            lines.push(format!("{}: {}", label, path));
+        }
+    }
+    if let Ok(screenshot) = variables.get("screenshot") {
+        lines.push(format!("[[ATTACHMENT|{}]]", screenshot));
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters are not allowed in XML 1.0, not even escaped
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_tests_list_their_steps_and_files() {
+        let mut variables = Variables::new();
+        variables.set("steps", "list 1s > detail");
+        variables.set("failed_step", "detail");
+        variables.set("screenshot", "artifacts/7-screenshot.png");
+
+        assert_eq!(
+            output(&variables).unwrap(),
+            "Steps: list 1s > detail\n\
+             Failed step: detail\n\
+             Screenshot: artifacts/7-screenshot.png\n\
+             [[ATTACHMENT|artifacts/7-screenshot.png]]"
+        );
+        assert_eq!(output(&Variables::new()), None);
+    }
+
+    #[test]
+    fn assembles_the_report_from_the_spool() {
+        let path = std::env::temp_dir().join(format!("junit-{}.xml", std::process::id()));
+        let path = path.to_str().unwrap();
+        let report = JunitReport::new(path).unwrap();
+
+        let mut variables = Variables::new();
+        variables.set("session_id", "abc");
+        report.record(
+            "1",
+            ("chrome 120", "browse"),
+            Duration::from_secs(2),
+            &variables,
+            None,
+        );
+        report.record(
+            "2",
+            ("chrome 120", "browse"),
+            Duration::from_millis(500),
+            &Variables::new(),
+            Some("Element <a> not found\nat step detail"),
+        );
+        report.write().unwrap();
+
+        let xml = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!std::path::Path::new(&spool_path(path)).exists());
+        assert_eq!(
+            xml,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"ParallelSeleniumTest\" tests=\"2\" failures=\"1\" time=\"2.500\">\n\
+             \x20 <testcase classname=\"chrome 120.browse\" name=\"Test #1\" time=\"2.000\">\n\
+             \x20   <properties>\n\
+             \x20     <property name=\"session_id\" value=\"abc\"/>\n\
+             \x20   </properties>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase classname=\"chrome 120.browse\" name=\"Test #2\" time=\"0.500\">\n\
+             \x20   <failure message=\"Element &lt;a&gt; not found\">Element &lt;a&gt; not found\nat step detail</failure>\n\
+             \x20 </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+}
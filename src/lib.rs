@@ -0,0 +1,82 @@
+//! Runs a scenario on many browser sessions of a Selenium grid in parallel.
+//!
+//! The binary reads its configuration from the environment, embedding applications hand their own
+//! [`Scenario`] to a [`Runner`] instead of forking the built-in ones.
+
+pub use abort::AbortRule;
+pub use autoscaler::SquareWave;
+pub use capacity::CapacitySearch;
+pub use dialect::{GridDialect, StatusChannel};
+pub use flag::parse_flag;
+pub use load::LoadProfile;
+pub use metrics::Steps;
+pub use priority::Priority;
+pub use redact::Redactor;
+pub use runner::{Outcome, Runner};
+pub use scenario::{DemoPage, Scenario, Session};
+pub use schedule::{ArrivalSchedule, ConstantRate, Replay};
+pub use script::ScenarioDir;
+pub use teardown::Teardown;
+pub use threshold::parse_failure_rate;
+pub use timeouts::StepTimeouts;
+pub use viewport::ViewportJitter;
+
+mod abort;
+mod allure;
+mod annotations;
+pub mod assertions;
+mod attribution;
+mod autoscaler;
+mod breaker;
+mod budget;
+mod capacity;
+mod console;
+pub mod contexts;
+#[cfg(feature = "tui")]
+mod dashboard;
+#[cfg(feature = "demo-server")]
+mod demo;
+mod dialect;
+mod diff;
+pub mod echo;
+mod events;
+#[cfg(feature = "prometheus")]
+mod exporter;
+mod flag;
+mod invariants;
+pub mod journey;
+mod junit;
+mod load;
+mod manifest;
+mod metrics;
+mod mix;
+mod monitor;
+mod otlp;
+mod priority;
+mod probe;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod redact;
+mod retry;
+mod runner;
+mod scenario;
+mod schedule;
+mod script;
+mod site;
+mod targeting;
+mod teardown;
+mod threshold;
+mod timeouts;
+pub mod variables;
+mod viewport;
+mod watchdog;
+
+/// Marks errors caused by the grid rather than by the application under test
+#[derive(Debug)]
+struct InfrastructureFailure;
+
+impl std::fmt::Display for InfrastructureFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to create session")
+    }
+}
@@ -0,0 +1,147 @@
+//! Staged load profile for capacity tests: the number of sessions in flight ramps up, holds
+//! steady and ramps down again. A soak test is a profile consisting of the steady state only.
+//!
+//! The load is generated by virtual users which run one test after another. During the ramp-up
+//! they join one by one until all of them are active, during the ramp-down they leave in the same
+//! manner.
+
+use anyhow::{Context, Result};
+use humantime::format_duration;
+use std::{sync::Mutex, time::Duration};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadProfile {
+    pub ramp_up: Duration,
+    pub steady: Duration,
+    pub ramp_down: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    RampUp,
+    Steady,
+    RampDown,
+}
+
+impl Stage {
+    /// Label sorting the stages in their chronological order
+    pub fn label(&self) -> &'static str {
+        match self {
+            Stage::RampUp => "1 ramp-up",
+            Stage::Steady => "2 steady",
+            Stage::RampDown => "3 ramp-down",
+        }
+    }
+}
+
+impl LoadProfile {
+    /// Reads the stage durations from `RAMP_UP`, `STEADY` and `RAMP_DOWN`, returns `None` if
+    /// none of them is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let stage = |variable: &str| -> Result<Option<Duration>> {
+            match std::env::var(variable) {
+                Ok(value) => Ok(Some(
+                    humantime::parse_duration(&value)
+                        .with_context(|| format!("Invalid {}", variable))?,
+                )),
+                Err(_) => Ok(None),
+            }
+        };
+
+        let (ramp_up, steady, ramp_down) =
+            (stage("RAMP_UP")?, stage("STEADY")?, stage("RAMP_DOWN")?);
+        if ramp_up.is_none() && steady.is_none() && ramp_down.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            ramp_up: ramp_up.unwrap_or_default(),
+            steady: steady.unwrap_or_default(),
+            ramp_down: ramp_down.unwrap_or_default(),
+        }))
+    }
+
+    pub fn total(&self) -> Duration {
+        self.ramp_up + self.steady + self.ramp_down
+    }
+
+    /// Stage the profile is in after the given time
+    pub fn stage(&self, elapsed: Duration) -> Stage {
+        if elapsed < self.ramp_up {
+            Stage::RampUp
+        } else if elapsed < self.ramp_up + self.steady {
+            Stage::Steady
+        } else {
+            Stage::RampDown
+        }
+    }
+
+    /// Offsets at which the virtual user with the given index joins and leaves, the first user
+    /// to join is the last one to leave
+    pub fn window(&self, user: u64, users: u64) -> (Duration, Duration) {
+        let join = self.ramp_up.mul_f64(user as f64 / users as f64);
+        let leave = self.ramp_up
+            + self.steady
+            + self.ramp_down.mul_f64((users - user) as f64 / users as f64);
+
+        (join, leave)
+    }
+}
+
+/// Outcomes of a long run bucketed by the window they finished in, to show how the failure rate
+/// developed over time
+pub struct Timeline {
+    window: Duration,
+    buckets: Mutex<Vec<(u64, u64)>>,
+}
+
+impl Timeline {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            buckets: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records a test that finished the given time after the run started
+    pub fn record(&self, elapsed: Duration, success: bool) {
+        let index = (elapsed.as_secs() / self.window.as_secs().max(1)) as usize;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() <= index {
+            buckets.resize(index + 1, (0, 0));
+        }
+
+        let (passed, failed) = &mut buckets[index];
+        if success {
+            *passed += 1;
+        } else {
+            *failed += 1;
+        }
+    }
+
+    /// Logs the outcomes per window, stays silent if the run fit into a single one
+    pub fn log_summary(&self) {
+        let buckets = self.buckets.lock().unwrap();
+        if buckets.len() < 2 {
+            return;
+        }
+
+        for (index, (passed, failed)) in buckets.iter().enumerate() {
+            let total = passed + failed;
+            let rate = if total > 0 {
+                *failed as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            };
+
+            log::info!(
+                "{} to {}: {} / {} succeeded ({:.1}% failed).",
+                format_duration(self.window * index as u32),
+                format_duration(self.window * (index as u32 + 1)),
+                passed,
+                total,
+                rate
+            );
+        }
+    }
+}
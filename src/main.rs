@@ -1,52 +1,571 @@
-use anyhow::{bail, Result};
-use humantime::format_duration;
-use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
-    time::{Duration, Instant},
+use anyhow::{bail, Context, Result};
+use baseline::Comparison;
+use basic_test::{
+    parse_failure_rate, parse_flag, AbortRule, CapacitySearch, LoadProfile, Outcome, Replay,
+    Runner, ScenarioDir, SquareWave, StepTimeouts, Teardown, ViewportJitter,
 };
-use thirtyfour::{prelude::*, Capabilities, ExtensionCommand};
-use tokio::{spawn, time::sleep};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{parse_duration, Preset, RunConfig};
+use std::{net::IpAddr, time::Duration};
+
+mod baseline;
+mod cleanup;
+mod compare;
+mod config;
+mod html;
+mod import;
+mod job;
+mod report;
+mod suite;
+
+/// Runs Selenium tests against a grid in parallel.
+///
+/// Besides the flags below, runs are configured through environment variables (see the README).
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-const DEMO_BODY: &'static str = include_str!("site.html");
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the scenario on many sessions in parallel
+    Run(RunArgs),
+    /// Executes every run described in a suite file
+    Suite {
+        /// WebDriver endpoint of the grid
+        #[arg(long, env = "ENDPOINT")]
+        endpoint: String,
+        /// Suite file with one run per line
+        #[arg(env = "SUITE")]
+        file: String,
+        #[arg(long, env = "SUITE_MODE", value_enum, default_value_t = SuiteMode::Sequential)]
+        mode: SuiteMode,
+        /// Directory keeping the results of passed runs, unchanged runs are skipped
+        #[arg(long, env = "SUITE_CACHE")]
+        cache: Option<String>,
+    },
+    /// Summarizes the event log of a previous run, or compares several runs in a grid
+    Report {
+        /// Event logs written by runs with `EVENT_LOG` set
+        #[arg(required = true)]
+        event_logs: Vec<String>,
+        /// Also renders a standalone HTML report with charts of a single run to this file
+        #[arg(long)]
+        html: Option<String>,
+        /// Compares a single run with the baseline stored for each of these labels
+        #[arg(long = "baseline", env = "BASELINES", value_delimiter = ',')]
+        baselines: Vec<String>,
+        /// Directory of the stored baselines
+        #[arg(long, env = "BASELINE_DIR", default_value = "baselines")]
+        baseline_dir: String,
+    },
+    /// Stores the event log of a run as the baseline later runs with the same labels compare with
+    Baseline {
+        /// Event log of the run
+        event_log: String,
+        /// Labels the run becomes the baseline of, e.g. a branch or an environment
+        #[arg(long = "label", required = true)]
+        labels: Vec<String>,
+        /// Directory of the stored baselines
+        #[arg(long, env = "BASELINE_DIR", default_value = "baselines")]
+        baseline_dir: String,
+    },
+    /// Compares a candidate run with a baseline run and tells whether it regressed significantly
+    Compare {
+        /// Event log of the baseline run
+        baseline: String,
+        /// Event log of the candidate run
+        candidate: String,
+        /// Significance level below which differences are not attributed to noise
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+    },
+    /// Converts a web server access log into a replay file for `run --replay`
+    Import {
+        /// Access log in the Common or Combined Log Format
+        access_log: String,
+        /// Requests whose path matches the pattern run the scenario, e.g. `^/checkout=journey`
+        #[arg(long = "map", required = true, value_parser = import::parse_mapping)]
+        mappings: Vec<import::Mapping>,
+        /// Replay file to write
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Runs as a container job configured through `PST_` variables only, see the README
+    Job {
+        /// Directory receiving the results and the completion marker, usually a mounted volume
+        #[arg(long, env = "PST_RESULTS", default_value = "/results")]
+        results: String,
+    },
+    /// Deletes sessions an aborted run left behind on the grid
+    Cleanup {
+        /// WebDriver endpoint of the grid
+        #[arg(long, env = "ENDPOINT")]
+        endpoint: String,
+        /// Event log written by the aborted run
+        event_log: String,
+    },
+}
 
-struct WebgridMetadataCommand {
-    fields: HashMap<String, String>,
+#[derive(clap::Args)]
+struct RunArgs {
+    /// TOML file with the configuration of the run, flags take precedence over its values
+    #[arg(long)]
+    config: Option<String>,
+    /// Built-in configuration to start from, the configuration file and flags refine it
+    #[arg(long, env = "PRESET", value_enum)]
+    preset: Option<Preset>,
+    /// WebDriver endpoint of the grid
+    #[arg(long, env = "ENDPOINT")]
+    endpoint: Option<String>,
+    /// Number of sessions to run in parallel
+    #[arg(long, env = "FORKS")]
+    count: Option<u64>,
+    /// Browser to request: firefox (default), chrome or safari
+    #[arg(long, env = "BROWSER")]
+    browser: Option<String>,
+    /// Timeout per session in seconds [default: 600]
+    #[arg(long, env = "TIMEOUT")]
+    timeout: Option<u64>,
+    /// Timeout of the session creation, including the time queued on the grid [default: timeout]
+    #[arg(long, env = "SESSION_TIMEOUT", value_parser = humantime::parse_duration)]
+    session_timeout: Option<Duration>,
+    /// Timeout of every further WebDriver command [default: timeout]
+    #[arg(long, env = "COMMAND_TIMEOUT", value_parser = humantime::parse_duration)]
+    command_timeout: Option<Duration>,
+    /// Fails tests whose scenario spends longer than this in a single step
+    #[arg(long, env = "STEP_TIMEOUT", value_parser = humantime::parse_duration)]
+    step_timeout: Option<Duration>,
+    /// Fails tests whose navigation to a URL takes longer than this
+    #[arg(long, env = "NAVIGATION_TIMEOUT", value_parser = humantime::parse_duration)]
+    navigation_timeout: Option<Duration>,
+    /// Fails tests whose element lookup takes longer than this
+    #[arg(long, env = "LOOKUP_TIMEOUT", value_parser = humantime::parse_duration)]
+    lookup_timeout: Option<Duration>,
+    /// Cleans sessions up before they are quit: cookies, blank (comma separated)
+    #[arg(long, env = "TEARDOWN", value_enum, value_delimiter = ',')]
+    teardown: Vec<TeardownStep>,
+    /// JavaScript file executed in the browser before the session is quit, repeatable
+    #[arg(long = "teardown-script")]
+    teardown_scripts: Vec<String>,
+    /// Leaves sessions to the timeout of the grid instead of quitting them
+    #[arg(long, env = "SKIP_QUIT", value_parser = parse_flag)]
+    skip_quit: bool,
+    /// URL receiving a POST for every quit session, `{session_id}` is replaced by its ID
+    #[arg(long, env = "AFTER_QUIT")]
+    after_quit: Option<String>,
+    /// Number of sessions in flight at the same time [default: all]
+    #[arg(long, env = "MAX_CONCURRENT")]
+    max_concurrent: Option<usize>,
+    /// Launches the sessions at this rate per second, independent of how quickly they finish
+    #[arg(long, env = "RATE", conflicts_with_all = ["duration", "ramp_up", "steady", "ramp_down"])]
+    rate: Option<f64>,
+    /// Replays the arrivals recorded in this file, one timestamp per line
+    #[arg(long, env = "REPLAY", conflicts_with_all = ["rate", "duration", "ramp_up", "steady", "ramp_down"])]
+    replay: Option<String>,
+    /// Autoscaler experiment: alternates between the session count and `--low` sessions in
+    /// flight, switching every half of this period
+    #[arg(long, env = "SQUARE_WAVE", value_parser = humantime::parse_duration, conflicts_with_all = ["rate", "replay", "duration", "ramp_up", "steady", "ramp_down"])]
+    square_wave: Option<Duration>,
+    /// Number of periods of the square wave [default: 3]
+    #[arg(long, env = "SQUARE_WAVE_CYCLES", requires = "square_wave")]
+    cycles: Option<u32>,
+    /// Sessions in flight during the low phases of the square wave [default: 1]
+    #[arg(long, env = "SQUARE_WAVE_LOW", requires = "square_wave")]
+    low: Option<u64>,
+    /// Searches the highest session count up to `--count` that meets the SLO, probing each
+    /// level for this duration
+    #[arg(long, env = "FIND_CAPACITY", value_parser = humantime::parse_duration, conflicts_with_all = ["rate", "replay", "square_wave", "duration", "ramp_up", "steady", "ramp_down"])]
+    find_capacity: Option<Duration>,
+    /// Upper bound of the p95 test duration a capacity search level has to meet
+    #[arg(long, env = "MAX_P95", value_parser = humantime::parse_duration, requires = "find_capacity")]
+    max_p95: Option<Duration>,
+    /// Aborts the run once this share of the tests finished within `--abort-window` failed, e.g. 50%
+    #[arg(long, env = "ABORT_FAILURE_RATE", value_parser = parse_failure_rate, conflicts_with = "find_capacity")]
+    abort_failure_rate: Option<f64>,
+    /// Rolling window of the failure rate that aborts the run [default: 1m]
+    #[arg(long, env = "ABORT_WINDOW", value_parser = humantime::parse_duration, requires = "abort_failure_rate")]
+    abort_window: Option<Duration>,
+    /// Success rate objective in percent, tracks the error budget of soak tests and load profiles
+    /// [default for capacity searches: 99]
+    #[arg(long, env = "SLO")]
+    slo: Option<f64>,
+    /// Stops launching tests once the error budget will be exhausted before the planned end
+    #[arg(long, env = "SLO_ABORT", requires = "slo", value_parser = parse_flag)]
+    abort_on_budget: bool,
+    /// Publishes live metrics for Prometheus on `/metrics` of this port
+    #[arg(long, env = "METRICS_PORT")]
+    metrics_port: Option<u16>,
+    /// OpenTelemetry collector receiving a trace per test via OTLP/HTTP, e.g. http://localhost:4318
+    #[arg(long, env = "OTLP_ENDPOINT")]
+    otlp: Option<String>,
+    /// Collects the browser console of every test
+    #[arg(long, env = "CONSOLE_LOG", value_parser = parse_flag)]
+    console_log: bool,
+    /// Gives every session a random viewport within these bounds, e.g. 360x640..1920x1080
+    #[arg(long, env = "VIEWPORT_JITTER")]
+    viewport_jitter: Option<String>,
+    /// Device pixel ratios the jittered viewports pick from, e.g. 1,2
+    #[arg(
+        long,
+        env = "PIXEL_RATIOS",
+        value_delimiter = ',',
+        requires = "viewport_jitter"
+    )]
+    pixel_ratios: Vec<f64>,
+    /// Seed of the jittered viewports, repeats those of the run that logged it [default: random]
+    #[arg(long, env = "VIEWPORT_SEED")]
+    viewport_seed: Option<u64>,
+    /// Shows a live dashboard in the terminal instead of logging every test
+    #[arg(long, env = "TUI", value_parser = parse_flag)]
+    tui: bool,
+    /// Loads every `.journey` file below this directory as a scenario named by its relative path
+    #[arg(long, env = "SCENARIO_DIR")]
+    scenario_dir: Option<String>,
+    /// Only loads the journeys whose name matches one of these globs, e.g. checkout/**
+    #[arg(
+        long,
+        env = "SCENARIO_INCLUDE",
+        value_delimiter = ',',
+        requires = "scenario_dir"
+    )]
+    scenario_include: Vec<String>,
+    /// Skips the journeys whose name matches one of these globs
+    #[arg(
+        long,
+        env = "SCENARIO_EXCLUDE",
+        value_delimiter = ',',
+        requires = "scenario_dir"
+    )]
+    scenario_exclude: Vec<String>,
+    /// Quits and recreates pooled sessions once they reached this age
+    #[arg(long, env = "MAX_SESSION_AGE", value_parser = humantime::parse_duration)]
+    max_session_age: Option<Duration>,
+    /// Number of failed tests the run tolerates before the exit code reports a failure
+    #[arg(long, env = "MAX_FAILURES")]
+    max_failures: Option<u64>,
+    /// Share of failed tests the run tolerates before the exit code reports a failure, e.g. 2%
+    #[arg(long, env = "MAX_FAILURE_RATE", value_parser = parse_failure_rate)]
+    max_failure_rate: Option<f64>,
+    /// Re-runs failed tests on a fresh session up to this many times [default: 0]
+    #[arg(long, env = "RETRIES")]
+    retries: Option<u32>,
+    /// Retries failed session creations this many times with exponential backoff [default: 0]
+    #[arg(long, env = "SESSION_RETRIES")]
+    session_retries: Option<u32>,
+    /// Pre-opens this many connections to the grid before the run starts
+    #[arg(long, env = "WARM_CONNECTIONS")]
+    warm_connections: Option<usize>,
+    /// Sends the WebDriver requests from this local address, repeat to alternate between several
+    #[arg(long = "local-address", env = "LOCAL_ADDRESSES", value_delimiter = ',')]
+    local_addresses: Vec<IpAddr>,
+    /// Times the phases of every session creation to tell client, network and grid apart
+    #[arg(long, env = "SESSION_TIMING", value_parser = parse_flag)]
+    session_timing: bool,
+    /// Time tests in flight get to quit their sessions after Ctrl+C [default: 30s]
+    #[arg(long, env = "GRACE_PERIOD", value_parser = humantime::parse_duration)]
+    grace_period: Option<Duration>,
+    /// Appends every lifecycle event as one JSON object per line to this file, `-` for stdout
+    #[arg(long, env = "EVENT_LOG")]
+    events: Option<String>,
+    /// Writes a JUnit XML report with one test case per session to this file
+    #[arg(long, env = "JUNIT_REPORT")]
+    junit: Option<String>,
+    /// Compares the run with the baseline stored for each of these labels once it finished
+    #[arg(long = "baseline", env = "BASELINES", value_delimiter = ',')]
+    baselines: Vec<String>,
+    /// Directory of the stored baselines [default: baselines]
+    #[arg(long, env = "BASELINE_DIR")]
+    baseline_dir: Option<String>,
+    /// Saves a screenshot and the page source of every failed test to this directory
+    #[arg(long, env = "SCREENSHOT_DIR")]
+    screenshots: Option<String>,
+    /// Soak test: keeps the session count in flight for the given time
+    #[arg(long, env = "DURATION", value_parser = humantime::parse_duration, conflicts_with_all = ["ramp_up", "steady", "ramp_down"])]
+    duration: Option<Duration>,
+    /// Time over which the sessions in flight increase to the session count
+    #[arg(long, env = "RAMP_UP", value_parser = humantime::parse_duration)]
+    ramp_up: Option<Duration>,
+    /// Time for which the session count is held, relaunching sessions as they finish
+    #[arg(long, env = "STEADY", value_parser = humantime::parse_duration)]
+    steady: Option<Duration>,
+    /// Time over which the sessions in flight decrease to zero again
+    #[arg(long, env = "RAMP_DOWN", value_parser = humantime::parse_duration)]
+    ramp_down: Option<Duration>,
 }
 
-impl WebgridMetadataCommand {
-    pub fn new() -> Self {
-        Self {
-            fields: HashMap::new(),
+impl RunArgs {
+    /// Combines the flags with the configuration file, along with the comparison with the
+    /// baselines once the run finished
+    fn into_runner(self) -> Result<(Runner, Option<Comparison>)> {
+        let config = RunConfig::load(self.config.as_deref(), self.preset)?;
+        config.export_env();
+
+        let endpoint = self
+            .endpoint
+            .or(config.endpoint)
+            .context("No endpoint given, pass --endpoint or set it in the configuration")?;
+        let count = self
+            .count
+            .or(config.count)
+            .context("No session count given, pass --count or set it in the configuration")?;
+        if count == 0 {
+            bail!("At least one session is required");
         }
-    }
 
-    pub fn with_field(key: String, value: String) -> Self {
-        let mut instance = Self::new();
-        instance.add(key, value);
-        instance
-    }
+        let mut runner = Runner::new(&endpoint, count);
+        if let Some(browser) = self.browser.or(config.browser) {
+            if !BROWSERS.contains(&browser.to_ascii_lowercase().as_str()) {
+                bail!("Unknown browser '{}'", browser);
+            }
+            runner = runner.browser(&browser);
+        }
+        if let Some(timeout) = self.timeout.or(config.timeout) {
+            runner = runner.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(timeout) = self
+            .session_timeout
+            .or(parse_duration(config.session_timeout.as_deref())?)
+        {
+            runner = runner.session_timeout(timeout);
+        }
+        if let Some(timeout) = self
+            .command_timeout
+            .or(parse_duration(config.command_timeout.as_deref())?)
+        {
+            runner = runner.command_timeout(timeout);
+        }
+        runner = runner.step_timeouts(StepTimeouts {
+            step: self
+                .step_timeout
+                .or(parse_duration(config.step_timeout.as_deref())?),
+            navigation: self
+                .navigation_timeout
+                .or(parse_duration(config.navigation_timeout.as_deref())?),
+            lookup: self
+                .lookup_timeout
+                .or(parse_duration(config.lookup_timeout.as_deref())?),
+        });
+        let mut teardown = Teardown {
+            skip_quit: self.skip_quit || config.skip_quit,
+            after_quit: self.after_quit.or(config.after_quit),
+            ..Teardown::default()
+        };
+        for step in config.teardown.iter() {
+            match step.as_str() {
+                "cookies" => teardown.delete_cookies = true,
+                "blank" => teardown.blank = true,
+                other => bail!("Unknown teardown step '{}' in the configuration", other),
+            }
+        }
+        for step in self.teardown {
+            match step {
+                TeardownStep::Cookies => teardown.delete_cookies = true,
+                TeardownStep::Blank => teardown.blank = true,
+            }
+        }
+        for path in self
+            .teardown_scripts
+            .iter()
+            .chain(config.teardown_scripts.iter())
+        {
+            teardown.scripts.push(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read teardown script '{}'", path))?,
+            );
+        }
+        runner = runner.teardown(teardown);
+        if let Some(max_concurrent) = self.max_concurrent.or(config.max_concurrent) {
+            runner = runner.max_concurrent(max_concurrent);
+        }
+
+        if let Some(rate) = self.rate.or(config.rate) {
+            runner = runner.rate(rate);
+        }
+        if let Some(path) = self.replay.or(config.replay) {
+            runner = runner.schedule(Replay::load(&path)?);
+        }
+        if let Some(period) = self
+            .square_wave
+            .or(parse_duration(config.square_wave.as_deref())?)
+        {
+            runner = runner.square_wave(SquareWave {
+                period,
+                cycles: self.cycles.or(config.cycles).unwrap_or(3),
+                low: self.low.or(config.low).unwrap_or(1),
+            });
+        }
+        if let Some(probe) = self
+            .find_capacity
+            .or(parse_duration(config.find_capacity.as_deref())?)
+        {
+            runner = runner.find_capacity(CapacitySearch {
+                probe,
+                slo: self.slo.or(config.slo).unwrap_or(99.0),
+                max_p95: self.max_p95.or(parse_duration(config.max_p95.as_deref())?),
+            });
+        } else if let Some(slo) = self.slo.or(config.slo) {
+            runner = runner
+                .slo(slo)
+                .abort_on_budget(self.abort_on_budget || config.abort_on_budget);
+        }
+        let abort_failure_rate = match (self.abort_failure_rate, config.abort_failure_rate) {
+            (Some(rate), _) => Some(rate),
+            (None, Some(rate)) => Some(parse_failure_rate(&rate)?),
+            (None, None) => None,
+        };
+        if let Some(failure_rate) = abort_failure_rate {
+            runner = runner.abort_on_failure_rate(AbortRule {
+                failure_rate,
+                window: self
+                    .abort_window
+                    .or(parse_duration(config.abort_window.as_deref())?)
+                    .unwrap_or(Duration::from_secs(60)),
+            });
+        }
+        if let Some(port) = self.metrics_port.or(config.metrics_port) {
+            runner = runner.metrics_port(port);
+        }
+        if let Some(otlp) = self.otlp.or(config.otlp) {
+            runner = runner.otlp(&otlp);
+        }
+        runner = runner.tui(self.tui || config.tui);
+        if self.console_log || config.console_log {
+            runner = runner.console_log(true);
+        }
+        if let Some(bounds) = self.viewport_jitter.or(config.viewport_jitter) {
+            let pixel_ratios = if self.pixel_ratios.is_empty() {
+                config.pixel_ratios
+            } else {
+                self.pixel_ratios
+            };
+            let mut jitter = ViewportJitter::parse(&bounds)
+                .context("Invalid viewport bounds")?
+                .pixel_ratios(pixel_ratios)?;
+            if let Some(seed) = self.viewport_seed.or(config.viewport_seed) {
+                jitter = jitter.seed(seed);
+            }
+            runner = runner.viewport_jitter(jitter);
+        }
+        if let Some(path) = self.scenario_dir.or(config.scenario_dir) {
+            let include = if self.scenario_include.is_empty() {
+                config.scenario_include
+            } else {
+                self.scenario_include
+            };
+            let exclude = if self.scenario_exclude.is_empty() {
+                config.scenario_exclude
+            } else {
+                self.scenario_exclude
+            };
+            runner = runner.scenario_dir(ScenarioDir::new(path).include(include).exclude(exclude));
+        }
+        if let Some(max_session_age) = self
+            .max_session_age
+            .or(parse_duration(config.max_session_age.as_deref())?)
+        {
+            runner = runner.max_session_age(max_session_age);
+        }
+        if let Some(max_failures) = self.max_failures.or(config.max_failures) {
+            runner = runner.max_failures(max_failures);
+        }
+        let max_failure_rate = match (self.max_failure_rate, config.max_failure_rate) {
+            (Some(rate), _) => Some(rate),
+            (None, Some(rate)) => Some(parse_failure_rate(&rate)?),
+            (None, None) => None,
+        };
+        if let Some(max_failure_rate) = max_failure_rate {
+            runner = runner.max_failure_rate(max_failure_rate);
+        }
+        if let Some(retries) = self.retries.or(config.retries) {
+            runner = runner.retries(retries);
+        }
+        if let Some(session_retries) = self.session_retries.or(config.session_retries) {
+            runner = runner.session_retries(session_retries);
+        }
+        if let Some(warm_connections) = self.warm_connections.or(config.warm_connections) {
+            runner = runner.warm_connections(warm_connections);
+        }
+        let local_addresses = if self.local_addresses.is_empty() {
+            config.local_addresses
+        } else {
+            self.local_addresses
+        };
+        for address in local_addresses {
+            runner = runner.local_address(address);
+        }
+        runner = runner.session_timing(self.session_timing || config.session_timing);
+        if let Some(grace_period) = self
+            .grace_period
+            .or(parse_duration(config.grace_period.as_deref())?)
+        {
+            runner = runner.grace_period(grace_period);
+        }
+        let events = self.events.or(config.events);
+        if let Some(events) = &events {
+            runner = runner.events(events);
+        }
+        let baselines = if self.baselines.is_empty() {
+            config.baselines
+        } else {
+            self.baselines
+        };
+        let comparison = if baselines.is_empty() {
+            None
+        } else {
+            let store = baseline::Store::open(
+                self.baseline_dir
+                    .or(config.baseline_dir)
+                    .as_deref()
+                    .unwrap_or("baselines"),
+            );
+            let events = events.or_else(|| std::env::var("EVENT_LOG").ok());
+            Some(Comparison::new(store, baselines, events)?)
+        };
+        if let Some(junit) = self.junit.or(config.junit) {
+            runner = runner.junit(&junit);
+        }
+        if let Some(screenshots) = self.screenshots.or(config.screenshots) {
+            runner = runner.screenshots(&screenshots);
+        }
 
-    pub fn add(&mut self, key: String, value: String) {
-        self.fields.insert(key, value);
+        let ramp_up = self.ramp_up.or(parse_duration(config.ramp_up.as_deref())?);
+        let steady = self
+            .steady
+            .or(self.duration)
+            .or(parse_duration(config.steady.as_deref())?)
+            .or(parse_duration(config.duration.as_deref())?);
+        let ramp_down = self
+            .ramp_down
+            .or(parse_duration(config.ramp_down.as_deref())?);
+        if ramp_up.is_some() || steady.is_some() || ramp_down.is_some() {
+            runner = runner.profile(LoadProfile {
+                ramp_up: ramp_up.unwrap_or_default(),
+                steady: steady.unwrap_or_default(),
+                ramp_down: ramp_down.unwrap_or_default(),
+            });
+        }
+
+        Ok((runner, comparison))
     }
 }
 
-impl ExtensionCommand for WebgridMetadataCommand {
-    fn parameters_json(&self) -> Option<serde_json::Value> {
-        serde_json::to_value(self.fields.clone()).ok()
-    }
+const BROWSERS: [&str; 3] = ["firefox", "chrome", "safari"];
 
-    fn method(&self) -> thirtyfour::RequestMethod {
-        thirtyfour::RequestMethod::Post
-    }
+#[derive(Clone, Copy, ValueEnum)]
+enum TeardownStep {
+    /// Deletes all cookies
+    Cookies,
+    /// Navigates to about:blank
+    Blank,
+}
 
-    fn endpoint(&self) -> String {
-        "/webgrid/metadata".into()
-    }
+#[derive(Clone, Copy, ValueEnum)]
+enum SuiteMode {
+    Sequential,
+    Parallel,
 }
 
 #[tokio::main]
@@ -55,183 +574,81 @@ async fn main() -> Result<()> {
         .filter_level(log::LevelFilter::Info)
         .init();
 
-    let args: Vec<String> = std::env::args().collect();
-
-    let endpoint = &args[1];
-    let count = args[2].parse::<u64>().unwrap();
-    let browser = if args.len() > 3 {
-        args[3].to_ascii_lowercase()
-    } else {
-        "firefox".to_owned()
-    };
-
-    let timeout_secs = std::env::var("TIMEOUT")
-        .unwrap_or("600".into())
-        .parse::<u64>()
-        .expect("Failed to parse timeout!");
-    let timeout = Some(Duration::from_secs(timeout_secs));
-
-    log::info!("Running {} tests against '{}'", count, endpoint);
-
-    let mut handles = Vec::new();
-
-    let failed = Arc::new(AtomicU64::new(0));
-
-    for id in 0..count {
-        let failed = failed.clone();
-        let endpoint = endpoint.clone();
-        let browser = browser.clone();
-        let handle = spawn(async move {
-            // Wait a tiny bit to stagger the requests
-            sleep(Duration::from_millis(id * 25)).await;
-
-            // Run the test
-            let start = Instant::now();
-            let result = run_test(&endpoint.clone(), &browser.clone(), timeout.clone()).await;
-            let duration = Instant::now() - start;
-
-            // Report the result (and duration)
-            match result {
-                Ok(_) => {
-                    log::info!("Test #{} finished in {}.", id, format_duration(duration));
-                    Ok(())
-                }
-                Err(e) => {
-                    log::info!("Test #{} failed: {}", id, e);
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    Err(e)
-                }
+    match Cli::parse().command {
+        Command::Run(args) => {
+            let (runner, comparison) = args.into_runner()?;
+            let outcome = runner.run().await?;
+            if let Some(comparison) = comparison {
+                comparison.log()?;
             }
-        });
-        handles.push(handle);
-    }
-
-    for handle in handles.into_iter() {
-        handle.await?.ok();
-    }
-
-    let failed = failed.load(Ordering::SeqCst);
-
-    log::info!(
-        "All tests finished. {} / {} succeeded.",
-        count - failed,
-        count
-    );
-
-    if failed > 0 {
-        std::process::exit(1);
-    }
-
-    Ok(())
-}
-
-async fn run_test(endpoint: &str, browser: &str, timeout: Option<Duration>) -> Result<()> {
-    let mut metadata = HashMap::new();
-    metadata.insert("name", "test-name");
-    metadata.insert("build", "test-build");
-
-    let mut driver = if browser == "firefox" {
-        let mut caps = DesiredCapabilities::firefox();
-        caps.add_subkey("webgrid:options", "metadata", metadata)?;
-        WebDriver::new_with_timeout(endpoint, &caps, timeout).await?
-    } else if browser == "chrome" {
-        let mut caps = DesiredCapabilities::chrome();
-        caps.add_subkey("webgrid:options", "metadata", metadata)?;
-        WebDriver::new_with_timeout(endpoint, &caps, timeout).await?
-    } else if browser == "safari" {
-        let mut caps = DesiredCapabilities::safari();
-        caps.add_subkey("webgrid:options", "metadata", metadata)?;
-        WebDriver::new_with_timeout(endpoint, &caps, timeout).await?
-    } else {
-        bail!("Unknown browser!");
-    };
-
-    let session_id = driver.session_id().to_string();
-
-    if let Err(e) = run_test_content(&mut driver).await {
-        driver.quit().await.ok();
-        bail!("{} failed due to {}", session_id, e);
-    } else {
-        driver.quit().await.ok();
-    }
-
-    Ok(())
-}
-
-async fn run_test_content(driver: &mut WebDriver) -> Result<()> {
-    send_message(&driver, "Visiting demo page").await?;
-    let page = format!(
-        "data:text/html;charset=utf-8;base64,{}",
-        base64::encode(DEMO_BODY)
-    );
-
-    driver.get(&page).await?;
-
-    // 0. Set some runtime metadata if the driver supports it
-    let metadata_command = WebgridMetadataCommand::with_field("answer".into(), "42".into());
-    driver.extension_command(metadata_command).await.ok();
-
-    // 1. Check that the `h1` contains the correct title
-    send_message(&driver, "Checking title").await?;
-    let title = driver.find_element(By::Tag("h1")).await?.text().await?;
-    if !title.eq_ignore_ascii_case("Horrible looking test-page") {
-        send_message(&driver, "Title mismatch.").await?;
-        set_status(&driver, "failure").await?;
-        bail!("Title mismatched :(");
-    }
-
-    // 2. Check that pressing the `#increment` button increments the `#counter`
-    send_message(&driver, "Checking increment").await?;
-    let counter = driver.find_element(By::Id("counter")).await?;
-    let value = counter.text().await?.parse::<i32>()?;
-    driver
-        .find_element(By::Id("increment"))
-        .await?
-        .click()
-        .await?;
-    let new_value = counter.text().await?.parse::<i32>()?;
-    if (value + 1) != new_value {
-        send_message(&driver, "Increment is broken.").await?;
-        set_status(&driver, "failure").await?;
-        bail!("Increment is broken :(");
-    }
 
-    // 3. Check that entering a new hash value actually works
-    send_message(&driver, "Checking hash value").await?;
-    let expected_hash = "No emojis allowed here :(";
-    let hash_input = driver.find_element(By::Id("newHashValue")).await?;
-    hash_input.send_keys(expected_hash).await?;
-    hash_input.send_keys(Keys::Enter).await?;
-    let hash = driver
-        .find_element(By::Id("hashValue"))
-        .await?
-        .text()
-        .await?;
-    if hash != expected_hash {
-        send_message(&driver, "Hash value updating is broken.").await?;
-        set_status(&driver, "failure").await?;
-        bail!(
-            "Hash value updating is broken: {} != {}",
-            hash,
-            expected_hash
-        );
+            // Exit codes tell CI pipelines whose problem a failed run is
+            if outcome != Outcome::Passed {
+                std::process::exit(outcome.exit_code());
+            }
+        }
+        Command::Suite {
+            endpoint,
+            file,
+            mode,
+            cache,
+        } => {
+            let runs = suite::load(&file)?;
+            let parallel = matches!(mode, SuiteMode::Parallel);
+            let cache = cache.as_deref().map(suite::Cache::open).transpose()?;
+            if !suite::execute(&endpoint, runs, parallel, cache).await? {
+                std::process::exit(1);
+            }
+        }
+        Command::Report {
+            event_logs,
+            html,
+            baselines,
+            baseline_dir,
+        } if event_logs.len() == 1 => {
+            report::summarize(&event_logs[0])?;
+            baseline::Store::open(&baseline_dir).compare(&event_logs[0], &baselines)?;
+            if let Some(html) = html {
+                html::write(&event_logs[0], &html)?;
+            }
+        }
+        Command::Report { html: Some(_), .. } => {
+            bail!("HTML reports can only be rendered for a single event log")
+        }
+        Command::Report { baselines, .. } if !baselines.is_empty() => {
+            bail!("Only a single event log can be compared with a baseline")
+        }
+        Command::Report { event_logs, .. } => report::log_matrix(&event_logs)?,
+        Command::Compare {
+            baseline,
+            candidate,
+            alpha,
+        } => {
+            if !compare::compare(&baseline, &candidate, alpha)? {
+                std::process::exit(1);
+            }
+        }
+        Command::Import {
+            access_log,
+            mappings,
+            output,
+        } => import::convert(&access_log, &mappings, &output)?,
+        Command::Baseline {
+            event_log,
+            labels,
+            baseline_dir,
+        } => baseline::Store::open(&baseline_dir).tag(&event_log, &labels)?,
+        Command::Job { results } => {
+            let exit_code = job::execute(&results).await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+        }
+        Command::Cleanup {
+            endpoint,
+            event_log,
+        } => cleanup::delete_orphans(&endpoint, &event_log).await?,
     }
 
-    send_message(&driver, "It worked!").await?;
-    set_status(&driver, "success").await?;
-
-    Ok(())
-}
-
-async fn send_message(driver: &WebDriver, message: &str) -> Result<()> {
-    let cookie = Cookie::new("webgrid:message", serde_json::json!(message));
-    driver.add_cookie(cookie).await.ok();
-    Ok(())
-}
-
-async fn set_status(driver: &WebDriver, status: &str) -> Result<()> {
-    let cookie = Cookie::new("webgrid:metadata.session:status", serde_json::json!(status));
-    driver.add_cookie(cookie).await.ok();
-
     Ok(())
 }
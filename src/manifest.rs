@@ -0,0 +1,62 @@
+//! Index of the files a run produced, written once everything else has been closed.
+//!
+//! Listing sizes and SHA-256 checksums makes it straightforward to upload the artifacts of a
+//! run to storage and to verify their integrity later on.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{fs::File, io, time::SystemTime};
+
+#[derive(Serialize)]
+struct Artifact<'a> {
+    path: &'a str,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    created: String,
+    artifacts: Vec<Artifact<'a>>,
+}
+
+/// Writes a manifest of the given artifacts to `path`
+pub fn write(path: &str, artifacts: &[&str]) -> Result<()> {
+    let artifacts = artifacts
+        .iter()
+        .map(|artifact| describe(artifact))
+        .collect::<Result<Vec<_>>>()?;
+
+    let manifest = Manifest {
+        created: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        artifacts,
+    };
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create manifest '{}'", path))?;
+    serde_json::to_writer_pretty(file, &manifest)?;
+
+    log::info!(
+        "Wrote manifest of {} artifacts to '{}'",
+        manifest.artifacts.len(),
+        path
+    );
+
+    Ok(())
+}
+
+fn describe(path: &str) -> Result<Artifact<'_>> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open artifact '{}'", path))?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Ok(Artifact { path, size, sha256 })
+}
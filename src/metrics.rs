@@ -0,0 +1,372 @@
+//! Latency recording based on HDR histograms.
+//!
+//! Every metric is recorded twice: into a histogram covering the whole run which feeds the
+//! final summary, and into one covering only the current interval which is periodically
+//! written to an HDR interval log (if requested) and then reset.
+
+use crate::{
+    console::Console,
+    events::{Event, EventLog},
+    otlp::Tracer,
+    timeouts::{self, StepClock, StepTimeouts},
+    watchdog::Watchdog,
+};
+use anyhow::{anyhow, bail, Result};
+use hdrhistogram::{
+    serialization::{
+        interval_log::{IntervalLogWriterBuilder, Tag},
+        V2DeflateSerializer,
+    },
+    Histogram,
+};
+use humantime::format_duration;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use thirtyfour::{By, WebDriver, WebElement};
+use tokio::{spawn, sync::oneshot, task::JoinHandle, time::sleep};
+
+/// Upper bound of recorded values in milliseconds, anything above is clamped
+const HIGHEST_TRACKABLE_MS: u64 = 24 * 60 * 60 * 1000;
+
+pub const SESSION_CREATE: &str = "session-create";
+/// Delay between the scheduled and the actual start of a test in open-loop runs
+pub const ARRIVAL_LAG: &str = "arrival-lag";
+pub const TOTAL: &str = "total";
+
+#[derive(Default)]
+struct Recorders {
+    run: BTreeMap<String, Histogram<u64>>,
+    interval: BTreeMap<String, Histogram<u64>>,
+    interval_start: Duration,
+}
+
+pub struct Metrics {
+    started: Instant,
+    started_at: SystemTime,
+    recorders: Mutex<Recorders>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            recorders: Mutex::new(Recorders::default()),
+        }
+    }
+
+    pub fn record(&self, metric: &str, duration: Duration) {
+        let value = duration.as_millis() as u64;
+        let mut recorders = self.recorders.lock().unwrap();
+
+        record_into(&mut recorders.run, metric, value);
+        record_into(&mut recorders.interval, metric, value);
+    }
+
+    /// Hands out the histograms of the current interval together with its start offset and length
+    fn take_interval(&self) -> (Duration, Duration, BTreeMap<String, Histogram<u64>>) {
+        let mut recorders = self.recorders.lock().unwrap();
+        let now = self.started.elapsed();
+        let start = std::mem::replace(&mut recorders.interval_start, now);
+
+        (start, now - start, std::mem::take(&mut recorders.interval))
+    }
+
+    /// Copy of the histogram covering the whole run so far
+    pub fn snapshot(&self, metric: &str) -> Option<Histogram<u64>> {
+        self.recorders.lock().unwrap().run.get(metric).cloned()
+    }
+
+    /// Copies of the histograms of all metrics covering the whole run so far
+    pub fn snapshot_all(&self) -> BTreeMap<String, Histogram<u64>> {
+        self.recorders.lock().unwrap().run.clone()
+    }
+
+    /// Logs count and percentiles of every metric recorded during the run
+    pub fn log_summary(&self) {
+        let recorders = self.recorders.lock().unwrap();
+
+        for (metric, histogram) in recorders.run.iter() {
+            log::info!(
+                "{}: n={} min={} mean={} p50={} p90={} p95={} p99={} max={}",
+                metric,
+                histogram.len(),
+                format_ms(histogram.min()),
+                format_ms(histogram.mean().round() as u64),
+                format_ms(histogram.value_at_quantile(0.5)),
+                format_ms(histogram.value_at_quantile(0.9)),
+                format_ms(histogram.value_at_quantile(0.95)),
+                format_ms(histogram.value_at_quantile(0.99)),
+                format_ms(histogram.max()),
+            );
+        }
+    }
+}
+
+fn record_into(histograms: &mut BTreeMap<String, Histogram<u64>>, metric: &str, value: u64) {
+    histograms
+        .entry(metric.to_owned())
+        .or_insert_with(|| {
+            Histogram::new_with_bounds(1, HIGHEST_TRACKABLE_MS, 3)
+                .expect("Failed to create histogram")
+        })
+        .saturating_record(value);
+}
+
+/// Quantile of the values recorded between two snapshots of the same metric, `None` if there were none
+pub fn quantile_between(
+    previous: Option<&Histogram<u64>>,
+    current: &Histogram<u64>,
+    quantile: f64,
+) -> Option<u64> {
+    let mut interval = current.clone();
+    if let Some(previous) = previous {
+        interval.subtract(previous).ok()?;
+    }
+
+    if interval.is_empty() {
+        None
+    } else {
+        Some(interval.value_at_quantile(quantile))
+    }
+}
+
+pub fn format_ms(value: u64) -> String {
+    format_duration(Duration::from_millis(value)).to_string()
+}
+
+/// Measures the steps of a single test, each step is recorded as `step-<name>` once the next one begins.
+///
+/// A step that never finishes (because the test bailed) is not recorded.
+pub struct Steps<'a> {
+    metrics: &'a Metrics,
+    events: Option<&'a EventLog>,
+    tracer: Option<&'a Tracer>,
+    test: &'a str,
+    watchdog: Watchdog,
+    console: Console,
+    timeouts: StepTimeouts,
+    current: Option<(&'static str, Instant)>,
+    /// Current step, shared with the watcher of the step timeout
+    clock: StepClock,
+    /// Most recently begun step, kept after it finished
+    last: Option<&'static str>,
+    /// Every step begun so far with its duration, missing for the current one
+    trail: Vec<(&'static str, Option<Duration>)>,
+}
+
+impl<'a> Steps<'a> {
+    pub fn new(
+        metrics: &'a Metrics,
+        events: Option<&'a EventLog>,
+        test: &'a str,
+        watchdog: Watchdog,
+    ) -> Self {
+        Self {
+            metrics,
+            events,
+            tracer: None,
+            test,
+            watchdog,
+            console: Console::Off,
+            timeouts: StepTimeouts::default(),
+            current: None,
+            clock: StepClock::default(),
+            last: None,
+            trail: Vec::new(),
+        }
+    }
+
+    /// Adds the steps to the traces of the tests
+    pub(crate) fn traced(mut self, tracer: Option<&'a Tracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Bounds the steps, navigations and element lookups of the test
+    pub(crate) fn timeouts(mut self, timeouts: StepTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Collects the browser console through the given mechanism
+    pub(crate) fn console(mut self, console: Console) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Handle of the current step, to time it out from outside of the scenario
+    pub(crate) fn clock(&self) -> StepClock {
+        self.clock.clone()
+    }
+
+    /// Begins the next step once the page errors of the current one were checked
+    pub async fn begin(&mut self, driver: &WebDriver, name: &'static str) -> Result<()> {
+        self.check_page_errors(driver).await?;
+        self.finish();
+        self.current = Some((name, Instant::now()));
+        self.clock.set(self.current);
+        self.last = Some(name);
+        self.trail.push((name, None));
+
+        self.emit(Event::StepStarted {
+            test: self.test,
+            name,
+        });
+
+        self.watchdog.arm(driver).await;
+        self.console.arm(driver).await;
+        Ok(())
+    }
+
+    /// Reports uncaught page errors since the current step began, failing if the watchdog
+    /// is configured to
+    pub async fn check_page_errors(&mut self, driver: &WebDriver) -> Result<()> {
+        let errors = self.watchdog.collect(driver).await;
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let step = self.step();
+        if self.watchdog == Watchdog::Fail {
+            bail!(
+                "{} uncaught page errors during step {}: {}",
+                errors.len(),
+                step,
+                errors.join("; ")
+            );
+        }
+
+        log::warn!(
+            "Test #{} caught {} page errors during step {}: {}",
+            self.test,
+            errors.len(),
+            step,
+            errors.join("; ")
+        );
+        Ok(())
+    }
+
+    /// Navigates to `url`, failing if it takes longer than `NAVIGATION_TIMEOUT`
+    pub async fn navigate(&self, driver: &WebDriver, url: &str) -> Result<()> {
+        timeouts::bounded(self.timeouts.navigation, driver.get(url), || {
+            format!("Navigation to {} in step {}", url, self.step())
+        })
+        .await
+    }
+
+    /// Finds an element, failing if the lookup takes longer than `LOOKUP_TIMEOUT`
+    pub async fn find<'d>(&self, driver: &'d WebDriver, by: By<'_>) -> Result<WebElement<'d>> {
+        let element = format!("{:?}", by);
+        timeouts::bounded(self.timeouts.lookup, driver.find_element(by), || {
+            format!("Lookup of {} in step {}", element, self.step())
+        })
+        .await
+    }
+
+    /// Name of the current step for errors
+    fn step(&self) -> &'static str {
+        self.last.unwrap_or("setup")
+    }
+
+    /// Step the test was in when it ended, which is the failing one for failed tests
+    pub fn last(&self) -> Option<&'static str> {
+        self.last
+    }
+
+    /// Breadcrumbs of the steps so far, like `list 1s 200ms > detail 350ms > order`
+    pub fn trail(&self) -> String {
+        self.trail
+            .iter()
+            .map(|(name, duration)| match duration {
+                Some(duration) => format!("{} {}", name, format_ms(duration.as_millis() as u64)),
+                None => name.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+
+    pub fn finish(&mut self) {
+        self.clock.set(None);
+        if let Some((name, start)) = self.current.take() {
+            let duration = start.elapsed();
+            if let Some((_, finished)) = self.trail.last_mut() {
+                *finished = Some(duration);
+            }
+            self.metrics.record(&format!("step-{}", name), duration);
+
+            self.emit(Event::StepFinished {
+                test: self.test,
+                name,
+                duration_ms: duration.as_millis() as u64,
+            });
+        }
+    }
+
+    fn emit(&self, event: Event<'_>) {
+        if let Some(tracer) = self.tracer {
+            tracer.observe(&event);
+        }
+        if let Some(events) = self.events {
+            events.emit(event);
+        }
+    }
+}
+
+/// Background task writing interval histograms to an HDR interval log file
+pub struct IntervalLog {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl IntervalLog {
+    pub fn start(metrics: Arc<Metrics>, path: &str, interval: Duration) -> Result<Self> {
+        let file = File::create(path)?;
+        let (stop, mut stopped) = oneshot::channel();
+
+        let task = spawn(async move {
+            let mut file = BufWriter::new(file);
+            let mut serializer = V2DeflateSerializer::new();
+            let mut writer = IntervalLogWriterBuilder::new()
+                .with_base_time(metrics.started_at)
+                .with_start_time(metrics.started_at)
+                .begin_log_with(&mut file, &mut serializer)?;
+
+            loop {
+                let finished = tokio::select! {
+                    _ = sleep(interval) => false,
+                    _ = &mut stopped => true,
+                };
+
+                let (start, duration, histograms) = metrics.take_interval();
+                for (metric, histogram) in histograms.iter() {
+                    writer
+                        .write_histogram(histogram, start, duration, Tag::new(metric))
+                        .map_err(|e| anyhow!("Failed to write interval log: {:?}", e))?;
+                }
+
+                if finished {
+                    break;
+                }
+            }
+
+            drop(writer);
+            file.flush()?;
+
+            Ok(())
+        });
+
+        Ok(Self { stop, task })
+    }
+
+    /// Writes the last (partial) interval and closes the log
+    pub async fn finish(self) -> Result<()> {
+        self.stop.send(()).ok();
+        self.task.await?
+    }
+}
@@ -0,0 +1,144 @@
+//! Weighted mix of scenarios within a single run, so the run resembles the traffic of real users.
+//!
+//! The mix is configured as `SCENARIO_MIX=browse:70,search:20,checkout:10`, naming built-in
+//! scenarios or scripted journeys like `SCENARIO` does, where the weights determine the share of
+//! the sessions running each scenario. Sessions are assigned by their index along a low-discrepancy
+//! sequence, so every stretch of the run resembles the whole mix instead of running the scenarios
+//! one after another, and a rerun assigns the same scenarios again.
+
+use anyhow::{bail, Context, Result};
+
+/// Fractional part of the golden ratio, whose multiples spread most evenly over the unit interval
+const GOLDEN_RATIO: f64 = 0.618_033_988_749_895;
+
+pub struct ScenarioMix {
+    scenarios: Vec<(String, u64)>,
+}
+
+impl ScenarioMix {
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("SCENARIO_MIX") {
+            Ok(list) => Ok(Some(Self::parse(&list).context("Invalid SCENARIO_MIX")?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn parse(list: &str) -> Result<Self> {
+        let mut scenarios: Vec<(String, u64)> = Vec::new();
+
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, weight) = entry
+                .split_once(':')
+                .with_context(|| format!("Expected 'scenario:weight', got '{}'", entry))?;
+            let (name, weight) = (
+                name.trim(),
+                weight
+                    .trim()
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid weight of scenario '{}'", name.trim()))?,
+            );
+
+            if weight == 0 {
+                bail!("Scenario '{}' needs a weight above zero", name);
+            }
+            if scenarios.iter().any(|(other, _)| other == name) {
+                bail!("Scenario '{}' is listed twice", name);
+            }
+
+            scenarios.push((name.to_owned(), weight));
+        }
+
+        if scenarios.is_empty() {
+            bail!("The mix does not contain any scenarios");
+        }
+
+        Ok(Self { scenarios })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.scenarios.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Scenario of the session with the given index
+    pub fn scenario(&self, index: u64) -> &str {
+        let total = self.scenarios.iter().map(|(_, weight)| weight).sum::<u64>();
+        let mut position =
+            (((index as f64 * GOLDEN_RATIO).fract() * total as f64) as u64).min(total - 1);
+
+        for (name, weight) in self.scenarios.iter() {
+            if position < *weight {
+                return name;
+            }
+            position -= weight;
+        }
+
+        &self.scenarios[self.scenarios.len() - 1].0
+    }
+
+    pub fn log(&self) {
+        let total = self.scenarios.iter().map(|(_, weight)| weight).sum::<u64>();
+        log::info!(
+            "Mixing scenarios: {}",
+            self.scenarios
+                .iter()
+                .map(|(name, weight)| format!(
+                    "{} {:.1}%",
+                    name,
+                    *weight as f64 * 100.0 / total as f64
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mix() -> ScenarioMix {
+        ScenarioMix::parse("browse:70, search:20 ,checkout:10").unwrap()
+    }
+
+    #[test]
+    fn parses_weighted_scenarios() {
+        assert_eq!(
+            mix().names().collect::<Vec<_>>(),
+            vec!["browse", "search", "checkout"]
+        );
+        assert_eq!(
+            ScenarioMix::parse("checkout/guest:1").unwrap().scenario(7),
+            "checkout/guest"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_mixes() {
+        for list in ["", " , ", "browse", "browse:0", "browse:many", "a:1,a:2"] {
+            assert!(ScenarioMix::parse(list).is_err(), "{}", list);
+        }
+    }
+
+    #[test]
+    fn sessions_follow_the_weights() {
+        let mix = mix();
+        let count = |name: &str, sessions: u64| {
+            (0..sessions)
+                .filter(|index| mix.scenario(*index) == name)
+                .count()
+        };
+
+        assert_eq!(count("browse", 100), 70);
+        assert_eq!(count("search", 100), 20);
+        assert_eq!(count("checkout", 100), 10);
+    }
+
+    #[test]
+    fn scenarios_are_interleaved() {
+        let mix = mix();
+        let first = (0..10).map(|index| mix.scenario(index)).collect::<Vec<_>>();
+        assert_eq!(first.iter().filter(|name| **name == "browse").count(), 7);
+        assert!(first.contains(&"search"));
+        assert!(first.contains(&"checkout"));
+    }
+}
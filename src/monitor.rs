@@ -0,0 +1,287 @@
+//! Alert rules evaluated against test outcomes in long-running modes.
+//!
+//! Rules are written like `streak > 3` (more than three failures in a row) or
+//! `p95 > 30s for 5m` (p95 duration of the tests that passed within the last five
+//! minutes above 30 seconds). A rule notifies once when it starts firing and once when it
+//! resolves, and does not fire again until its cool-down has passed.
+
+use crate::redact::Redactor;
+use anyhow::{bail, Context, Result};
+use humantime::format_duration;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::spawn;
+
+/// Bounds of a webhook notification, so a hook that stops answering does not pile up requests
+const WEBHOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub enum Condition {
+    /// At least `min` tests failed in a row
+    Streak { min: u64 },
+    /// A quantile of the recent passing test durations exceeds a threshold
+    Latency {
+        quantile: f64,
+        threshold: Duration,
+        inclusive: bool,
+        window: Duration,
+    },
+}
+
+pub struct Rule {
+    condition: Condition,
+    text: String,
+}
+
+impl Rule {
+    /// Describes the current value if the rule is violated
+    fn evaluate(&self, state: &State) -> Option<String> {
+        match &self.condition {
+            Condition::Streak { min } => {
+                if state.streak >= *min {
+                    Some(format!("{} tests failed in a row", state.streak))
+                } else {
+                    None
+                }
+            }
+            Condition::Latency {
+                quantile,
+                threshold,
+                inclusive,
+                window,
+            } => {
+                let now = Instant::now();
+                let mut samples = state
+                    .samples
+                    .iter()
+                    .filter(|(finished, _)| now.duration_since(*finished) <= *window)
+                    .map(|(_, duration)| *duration)
+                    .collect::<Vec<_>>();
+
+                if samples.is_empty() {
+                    return None;
+                }
+
+                samples.sort();
+                let index = ((samples.len() as f64 * quantile).ceil() as usize).max(1) - 1;
+                let value = samples[index.min(samples.len() - 1)];
+
+                let violated = if *inclusive {
+                    value >= *threshold
+                } else {
+                    value > *threshold
+                };
+
+                if violated {
+                    Some(format!(
+                        "quantile {} of the last {} is {}",
+                        quantile,
+                        format_duration(*window),
+                        format_duration(Duration::from_millis(value.as_millis() as u64))
+                    ))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn window(&self) -> Duration {
+        match self.condition {
+            Condition::Streak { .. } => Duration::from_secs(0),
+            Condition::Latency { window, .. } => window,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let normalized = text
+            .replace(">=", " ≥ ")
+            .replace('>', " > ")
+            .replace('≥', ">=");
+        let tokens = normalized.split_whitespace().collect::<Vec<_>>();
+
+        let (metric, operator, value, window) = match tokens.as_slice() {
+            [metric, operator, value] => (*metric, *operator, *value, None),
+            [metric, operator, value, "for", window] => (*metric, *operator, *value, Some(*window)),
+            _ => bail!(
+                "Expected '<metric> > <value> [for <window>]', got '{}'",
+                text
+            ),
+        };
+
+        let inclusive = match operator {
+            ">" => false,
+            ">=" => true,
+            other => bail!("Unsupported operator '{}'", other),
+        };
+
+        let condition = if metric == "streak" {
+            let count = value
+                .parse::<u64>()
+                .with_context(|| format!("Invalid streak length '{}'", value))?;
+            Condition::Streak {
+                min: if inclusive { count } else { count + 1 },
+            }
+        } else if let Some(percentile) = metric.strip_prefix('p') {
+            let percentile = percentile
+                .parse::<f64>()
+                .with_context(|| format!("Invalid percentile '{}'", metric))?;
+            let window = window.with_context(|| {
+                format!("Latency rules require a window ('for 5m'), got '{}'", text)
+            })?;
+
+            Condition::Latency {
+                quantile: percentile / 100.0,
+                threshold: humantime::parse_duration(value)?,
+                inclusive,
+                window: humantime::parse_duration(window)?,
+            }
+        } else {
+            bail!("Unknown alert metric '{}'", metric);
+        };
+
+        Ok(Rule {
+            condition,
+            text: text.trim().to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parses a semicolon separated list of rules
+pub fn parse_rules(list: &str) -> Result<Vec<Rule>> {
+    list.split(';')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[derive(Default, Clone, Copy)]
+struct RuleState {
+    firing: bool,
+    last_notified: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    streak: u64,
+    samples: VecDeque<(Instant, Duration)>,
+    rules: Vec<RuleState>,
+    /// Whether any rule was violated at some point
+    fired: bool,
+}
+
+pub struct Monitor {
+    rules: Vec<Rule>,
+    cooldown: Duration,
+    webhook: Option<String>,
+    state: Mutex<State>,
+    client: reqwest::Client,
+}
+
+impl Monitor {
+    pub fn new(rules: Vec<Rule>, cooldown: Duration, webhook: Option<String>) -> Result<Self> {
+        let state = State {
+            rules: vec![RuleState::default(); rules.len()],
+            ..State::default()
+        };
+        let client = reqwest::Client::builder()
+            .connect_timeout(WEBHOOK_CONNECT_TIMEOUT)
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Failed to build the webhook client")?;
+
+        Ok(Self {
+            rules,
+            cooldown,
+            webhook,
+            state: Mutex::new(state),
+            client,
+        })
+    }
+
+    pub fn record(&self, success: bool, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if success {
+            state.streak = 0;
+            state.samples.push_back((now, duration));
+        } else {
+            state.streak += 1;
+        }
+
+        // Only keep as many samples as the widest latency window needs
+        let retention = self
+            .rules
+            .iter()
+            .map(Rule::window)
+            .max()
+            .unwrap_or_default();
+        while state.samples.front().map_or(false, |(finished, _)| {
+            now.duration_since(*finished) > retention
+        }) {
+            state.samples.pop_front();
+        }
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let violation = rule.evaluate(&state);
+            state.fired |= violation.is_some();
+            let rule_state = &mut state.rules[index];
+
+            match violation {
+                Some(description) if !rule_state.firing => {
+                    let cooled_down = rule_state.last_notified.map_or(true, |notified| {
+                        now.duration_since(notified) >= self.cooldown
+                    });
+
+                    if cooled_down {
+                        rule_state.firing = true;
+                        rule_state.last_notified = Some(now);
+                        self.notify(format!("Alert '{}' is firing: {}.", rule, description));
+                    }
+                }
+                None if rule_state.firing => {
+                    rule_state.firing = false;
+                    self.notify(format!("Alert '{}' resolved.", rule));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether any rule was violated since the monitor was created, regardless of cool-down
+    pub fn has_fired(&self) -> bool {
+        self.state.lock().unwrap().fired
+    }
+
+    fn notify(&self, message: String) {
+        log::warn!("{}", message);
+
+        if let Some(webhook) = self.webhook.clone() {
+            let request = self.client.post(&webhook).json(&json!({ "text": message }));
+
+            spawn(async move {
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    let error = Redactor::new(&webhook).redact(&e.to_string());
+                    log::error!("Failed to deliver webhook notification: {}", error);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Traces of the tests exported to an OpenTelemetry collector via OTLP/HTTP (JSON encoding).
+//!
+//! Every test becomes one trace built from the lifecycle events of the run: a root span covering
+//! the whole test, a `session-create` span until the grid handed out the session, and one span per
+//! step. All spans created after the session was started carry its ID as `session_id`, so they
+//! can be correlated with the grid's own traces.
+
+use crate::events::Event;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{spawn, task::JoinHandle};
+
+/// Bounds of an export, so a collector that stops answering can not hold the end of the run back
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Spans of a test that has not finished yet
+struct OpenTrace {
+    trace_id: String,
+    root_id: String,
+    started: u128,
+    session_id: Option<String>,
+    step: Option<(String, u128)>,
+    spans: Vec<Value>,
+}
+
+pub struct Tracer {
+    endpoint: String,
+    service: String,
+    client: reqwest::Client,
+    open: Mutex<HashMap<String, OpenTrace>>,
+    /// Exports still in flight, awaited before the run ends
+    pending: Mutex<Vec<JoinHandle<()>>>,
+    ids: AtomicU64,
+}
+
+impl Tracer {
+    /// Collector at `endpoint` (e.g. `http://localhost:4318`), the service name is taken from
+    /// `OTLP_SERVICE`
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(EXPORT_TIMEOUT)
+            .build()
+            .context("Failed to build the OTLP client")?;
+
+        Ok(Self {
+            endpoint: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            service: std::env::var("OTLP_SERVICE").unwrap_or("parallel-selenium-test".into()),
+            client,
+            open: Mutex::new(HashMap::new()),
+            pending: Mutex::new(Vec::new()),
+            ids: AtomicU64::new(0),
+        })
+    }
+
+    /// Updates the trace of the test the event belongs to, exporting it once the test finished
+    pub fn observe(&self, event: &Event<'_>) {
+        let now = now();
+        let mut open = self.open.lock().unwrap();
+
+        match event {
+            Event::SessionScheduled { test } => {
+                let trace = OpenTrace {
+                    trace_id: self.id(test, 16),
+                    root_id: self.id(test, 8),
+                    started: now,
+                    session_id: None,
+                    step: None,
+                    spans: Vec::new(),
+                };
+                open.insert(test.to_string(), trace);
+            }
+            Event::SessionStarted { test, session_id } => {
+                if let Some(trace) = open.get_mut(*test) {
+                    trace.session_id = Some(session_id.to_string());
+                    let span = self.span(trace, "session-create", trace.started, now, None);
+                    trace.spans.push(span);
+                }
+            }
+            Event::StepStarted { test, name } => {
+                if let Some(trace) = open.get_mut(*test) {
+                    trace.step = Some((name.to_string(), now));
+                }
+            }
+            Event::StepFinished { test, .. } => {
+                if let Some(trace) = open.get_mut(*test) {
+                    if let Some((step, started)) = trace.step.take() {
+                        let span = self.span(trace, &format!("step {}", step), started, now, None);
+                        trace.spans.push(span);
+                    }
+                }
+            }
+            Event::SessionFinished {
+                test,
+                success,
+                error,
+                ..
+            } => {
+                let mut trace = match open.remove(*test) {
+                    Some(trace) => trace,
+                    None => return,
+                };
+
+                // A step that bailed never finished, it ends with the test
+                if let Some((step, started)) = trace.step.take() {
+                    let span = self.span(
+                        &trace,
+                        &format!("step {}", step),
+                        started,
+                        now,
+                        error.as_deref().or(Some("failed")),
+                    );
+                    trace.spans.push(span);
+                }
+
+                let mut root = self.span(
+                    &trace,
+                    &format!("test {}", test),
+                    trace.started,
+                    now,
+                    if *success {
+                        None
+                    } else {
+                        Some(error.as_deref().unwrap_or("failed"))
+                    },
+                );
+                root["spanId"] = json!(trace.root_id);
+                root.as_object_mut().unwrap().remove("parentSpanId");
+                root["attributes"]
+                    .as_array_mut()
+                    .unwrap()
+                    .push(attribute("test", test));
+                trace.spans.push(root);
+
+                self.export(trace.spans);
+            }
+            _ => {}
+        }
+    }
+
+    /// Waits for all exports that are still in flight
+    pub async fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for handle in pending {
+            handle.await.ok();
+        }
+    }
+
+    fn span(
+        &self,
+        trace: &OpenTrace,
+        name: &str,
+        start: u128,
+        end: u128,
+        error: Option<&str>,
+    ) -> Value {
+        let mut attributes = Vec::new();
+        if let Some(session_id) = &trace.session_id {
+            attributes.push(attribute("session_id", session_id));
+        }
+
+        let status = match error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        };
+
+        json!({
+            "traceId": trace.trace_id,
+            "spanId": self.id(name, 8),
+            "parentSpanId": trace.root_id,
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": start.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": attributes,
+            "status": status,
+        })
+    }
+
+    fn export(&self, spans: Vec<Value>) {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": { "attributes": [attribute("service.name", &self.service)] },
+                "scopeSpans": [{
+                    "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        let request = self.client.post(&self.endpoint).json(&body);
+        let handle = spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                log::warn!("Failed to export trace: {}", e);
+            }
+        });
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|handle| !handle.is_finished());
+        pending.push(handle);
+    }
+
+    /// Random-looking ID of the given length in bytes, hex encoded
+    fn id(&self, seed: &str, length: usize) -> String {
+        let counter = self.ids.fetch_add(1, Ordering::Relaxed);
+        let digest = Sha256::new()
+            .chain_update(seed.as_bytes())
+            .chain_update(counter.to_le_bytes())
+            .chain_update(now().to_le_bytes())
+            .chain_update(std::process::id().to_le_bytes())
+            .finalize();
+
+        digest[..length]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
@@ -0,0 +1,89 @@
+//! Priority classes of sessions, for exercising the queueing of grids that prioritise requests.
+//!
+//! Classes are configured as `PRIORITIES=high:1,normal:4`, listed from highest to lowest
+//! priority, where the weights determine how sessions are distributed among the classes (here
+//! one in five sessions is `high`). Sessions of higher classes are launched first, and the class
+//! is passed to the grid through the capabilities of the dialect.
+
+use anyhow::{bail, Context, Result};
+use std::ops::Range;
+
+pub struct Priority {
+    pub label: String,
+    /// Position in the list of classes, `0` being the highest priority
+    pub rank: u32,
+    weight: u64,
+}
+
+#[derive(Default)]
+pub struct PriorityClasses {
+    classes: Vec<Priority>,
+}
+
+impl PriorityClasses {
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("PRIORITIES") {
+            Ok(list) => Self::parse(&list),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(list: &str) -> Result<Self> {
+        let mut classes = Vec::new();
+
+        for (rank, class) in list
+            .split(',')
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+            .enumerate()
+        {
+            let (label, weight) = match class.split_once(':') {
+                Some((label, weight)) => (
+                    label.trim(),
+                    weight
+                        .trim()
+                        .parse::<u64>()
+                        .with_context(|| format!("Invalid weight of priority class '{}'", label))?,
+                ),
+                None => (class, 1),
+            };
+
+            if weight == 0 {
+                bail!("Priority class '{}' needs a weight above zero", label);
+            }
+
+            classes.push(Priority {
+                label: label.to_owned(),
+                rank: rank as u32,
+                weight,
+            });
+        }
+
+        Ok(Self { classes })
+    }
+
+    /// Class of the session with the given index
+    pub fn class(&self, index: u64) -> Option<&Priority> {
+        let total = self.classes.iter().map(|c| c.weight).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut position = index % total;
+        for class in self.classes.iter() {
+            if position < class.weight {
+                return Some(class);
+            }
+            position -= class.weight;
+        }
+
+        None
+    }
+
+    /// Session indices in the order they should be launched, highest priority first
+    pub fn launch_order(&self, indices: Range<u64>) -> Vec<u64> {
+        let mut order = indices.collect::<Vec<_>>();
+        order.sort_by_key(|index| self.class(*index).map_or(0, |class| class.rank));
+        order
+    }
+}
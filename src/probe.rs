@@ -0,0 +1,141 @@
+//! Direct HTTP health checks of the application under test.
+//!
+//! The probe bypasses browser and grid entirely so outages of the application itself
+//! can be told apart from grid-side failures when looking at a run.
+
+use crate::redact::Redactor;
+use anyhow::{Context, Result};
+use humantime::format_duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{spawn, task::JoinHandle, time::sleep};
+
+/// Period during which the application did not respond successfully
+struct Outage {
+    start: Duration,
+    duration: Duration,
+    reason: String,
+}
+
+#[derive(Default)]
+struct ProbeState {
+    checks: u64,
+    failures: u64,
+    outages: Vec<Outage>,
+    /// Offset from the run start and reason of the outage in progress
+    down_since: Option<(Duration, String)>,
+}
+
+pub struct HealthProbe {
+    state: Arc<Mutex<ProbeState>>,
+    started: Instant,
+    task: JoinHandle<()>,
+}
+
+impl HealthProbe {
+    /// Starts probing `url` every `interval` in the background
+    pub fn start(url: String, interval: Duration) -> Result<HealthProbe> {
+        let state = Arc::new(Mutex::new(ProbeState::default()));
+        let started = Instant::now();
+        let client = reqwest::Client::builder()
+            .timeout(interval.max(Duration::from_secs(1)))
+            .build()
+            .context("Failed to build health probe client")?;
+
+        let task = {
+            let state = state.clone();
+            spawn(async move {
+                let redactor = Redactor::new(&url);
+
+                loop {
+                    let failure = match client.get(&url).send().await {
+                        Ok(response) if response.status().is_success() => None,
+                        Ok(response) => Some(format!("status {}", response.status())),
+                        Err(e) => Some(redactor.redact(&e.to_string())),
+                    };
+
+                    record(&state, started.elapsed(), failure);
+                    sleep(interval).await;
+                }
+            })
+        };
+
+        log::info!(
+            "Probing application health every {}",
+            format_duration(interval)
+        );
+
+        Ok(HealthProbe {
+            state,
+            started,
+            task,
+        })
+    }
+
+    /// Stops probing and logs a summary including every outage observed
+    pub fn stop(self) {
+        self.task.abort();
+
+        let mut state = self.state.lock().unwrap();
+        let now = self.started.elapsed();
+        if let Some((start, reason)) = state.down_since.take() {
+            state.outages.push(Outage {
+                start,
+                duration: now - start,
+                reason,
+            });
+        }
+
+        log::info!(
+            "Application health: {} / {} probes succeeded, {} outages.",
+            state.checks - state.failures,
+            state.checks,
+            state.outages.len()
+        );
+
+        for outage in state.outages.iter() {
+            log::info!(
+                "Application was down from +{} for {} ({})",
+                format_duration(round(outage.start)),
+                format_duration(round(outage.duration)),
+                outage.reason
+            );
+        }
+    }
+}
+
+fn record(state: &Mutex<ProbeState>, offset: Duration, failure: Option<String>) {
+    let mut state = state.lock().unwrap();
+    state.checks += 1;
+
+    match (failure, state.down_since.take()) {
+        (Some(reason), None) => {
+            log::warn!("Application became unhealthy: {}", reason);
+            state.failures += 1;
+            state.down_since = Some((offset, reason));
+        }
+        (Some(_), Some(outage)) => {
+            state.failures += 1;
+            state.down_since = Some(outage);
+        }
+        (None, Some((start, reason))) => {
+            log::info!(
+                "Application recovered after {}",
+                format_duration(round(offset - start))
+            );
+            state.outages.push(Outage {
+                start,
+                duration: offset - start,
+                reason,
+            });
+        }
+        (None, None) => {}
+    }
+}
+
+/// Drops sub-second precision so durations stay readable in the log
+fn round(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs())
+}
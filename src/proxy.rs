@@ -0,0 +1,1056 @@
+//! HTTP proxy that sits between the runner and the grid.
+//!
+//! Every WebDriver request passes through it and is matched against a list of rules
+//! which can log, mutate, delay, or short-circuit the request before it reaches the grid.
+//! Given a command timeout, it also bounds every request but the session creation. Given a
+//! [`SessionTiming`], it times the phases of every session creation. Given a [`PayloadShape`],
+//! it restructures the capabilities of every New Session request.
+
+use crate::{
+    attribution::{Phases, SessionTiming},
+    redact::{percent_decode, Redactor},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use humantime::format_duration;
+use hyper::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{lookup_host, TcpSocket, TcpStream},
+    spawn,
+    time::{sleep, timeout},
+};
+
+/// What happens to a request that matches a [`Rule`]
+pub enum Action {
+    /// Log the request and its response at info level
+    Log,
+    /// Hold the request back for the given duration before forwarding it
+    Delay(Duration),
+    /// Answer with the given status code instead of forwarding the request, successful ones with
+    /// an empty WebDriver response
+    Status(StatusCode),
+    /// Set a header on the forwarded request
+    Header(HeaderName, HeaderValue),
+}
+
+/// W3C WebDriver commands which rules can target by name, as `(name, method, path template)`
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("newSession", "POST", "session"),
+    ("deleteSession", "DELETE", "session/*"),
+    ("navigateTo", "POST", "session/*/url"),
+    ("getCurrentUrl", "GET", "session/*/url"),
+    ("getTitle", "GET", "session/*/title"),
+    ("findElement", "POST", "session/*/element"),
+    ("findElements", "POST", "session/*/elements"),
+    (
+        "findElementFromElement",
+        "POST",
+        "session/*/element/*/element",
+    ),
+    (
+        "findElementsFromElement",
+        "POST",
+        "session/*/element/*/elements",
+    ),
+    ("getElementText", "GET", "session/*/element/*/text"),
+    (
+        "getElementAttribute",
+        "GET",
+        "session/*/element/*/attribute/*",
+    ),
+    (
+        "getElementProperty",
+        "GET",
+        "session/*/element/*/property/*",
+    ),
+    ("getElementCssValue", "GET", "session/*/element/*/css/*"),
+    ("isElementSelected", "GET", "session/*/element/*/selected"),
+    ("isElementEnabled", "GET", "session/*/element/*/enabled"),
+    ("isElementDisplayed", "GET", "session/*/element/*/displayed"),
+    ("elementClick", "POST", "session/*/element/*/click"),
+    ("elementClear", "POST", "session/*/element/*/clear"),
+    ("elementSendKeys", "POST", "session/*/element/*/value"),
+    ("executeScript", "POST", "session/*/execute/sync"),
+    ("executeAsyncScript", "POST", "session/*/execute/async"),
+    ("addCookie", "POST", "session/*/cookie"),
+    ("deleteAllCookies", "DELETE", "session/*/cookie"),
+    ("takeScreenshot", "GET", "session/*/screenshot"),
+    ("getPageSource", "GET", "session/*/source"),
+];
+
+/// Requests a [`Rule`] applies to
+pub enum Target {
+    /// Any request whose path contains the string, `*` matches everything
+    Path(String),
+    /// Requests for one WebDriver command, written as `@name` in rules files
+    Command {
+        method: &'static str,
+        template: &'static str,
+    },
+}
+
+/// Session creation, which is not bounded by the command timeout
+const NEW_SESSION: Target = Target::Command {
+    method: "POST",
+    template: "session",
+};
+
+/// Connection to the grid, with or without TLS
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Session deletion, which is answered by the proxy if sessions are left to the grid
+const DELETE_SESSION: Target = Target::Command {
+    method: "DELETE",
+    template: "session/*",
+};
+
+impl Target {
+    pub fn command(name: &str) -> Result<Self> {
+        COMMANDS
+            .iter()
+            .find(|(command, _, _)| *command == name)
+            .map(|(_, method, template)| Target::Command {
+                method: *method,
+                template: *template,
+            })
+            .with_context(|| format!("Unknown WebDriver command '{}'", name))
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        match self {
+            Target::Path(pattern) => pattern == "*" || path.contains(pattern.as_str()),
+            Target::Command {
+                method: command_method,
+                template,
+            } => {
+                let path = path.split('?').next().unwrap_or_default();
+                let segments = path
+                    .split('/')
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>();
+                let template = template.split('/').collect::<Vec<_>>();
+
+                method.as_str() == *command_method
+                    && segments.len() == template.len()
+                    && segments
+                        .iter()
+                        .zip(template.iter())
+                        .all(|(segment, expected)| *expected == "*" || segment == expected)
+            }
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix('@') {
+            Some(name) => Target::command(name),
+            None => Ok(Target::Path(pattern.to_owned())),
+        }
+    }
+}
+
+/// Single line of a rules file: `<action> <method|*> <path|@command|*> [argument]`
+pub struct Rule {
+    method: Option<Method>,
+    target: Target,
+    action: Action,
+}
+
+impl Rule {
+    pub fn new(method: Option<Method>, target: Target, action: Action) -> Self {
+        Self {
+            method,
+            target,
+            action,
+        }
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let method_matches = self.method.as_ref().map_or(true, |m| m == method);
+
+        method_matches && self.target.matches(method, path)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut parts = line.split_whitespace();
+
+        let (action, method, pattern) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(action), Some(method), Some(pattern)) => (action, method, pattern),
+            _ => bail!(
+                "Expected '<action> <method> <path> [argument]', got '{}'",
+                line
+            ),
+        };
+
+        let argument = parts.collect::<Vec<_>>().join(" ");
+
+        let method = if method == "*" {
+            None
+        } else {
+            Some(method.to_ascii_uppercase().parse::<Method>()?)
+        };
+
+        let action = match action {
+            "log" => Action::Log,
+            "delay" => Action::Delay(humantime::parse_duration(&argument)?),
+            "status" => Action::Status(StatusCode::from_u16(argument.parse()?)?),
+            "header" => {
+                let (name, value) = argument
+                    .split_once(':')
+                    .context("Header rules expect an argument of the form 'Name: value'")?;
+                Action::Header(name.trim().parse()?, value.trim().parse()?)
+            }
+            other => bail!("Unknown proxy rule action '{}'", other),
+        };
+
+        Ok(Rule::new(method, pattern.parse()?, action))
+    }
+}
+
+/// Reads a rules file, ignoring blank lines and lines starting with `#`
+pub fn load_rules(path: &str) -> Result<Vec<Rule>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read proxy rules '{}'", path))?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            line.parse::<Rule>()
+                .with_context(|| format!("Invalid proxy rule in line {}", index + 1))
+        })
+        .collect()
+}
+
+/// Rule answering session deletions in place of the grid, so drivers can be quit and their
+/// connections released while the grid keeps the sessions until they time out
+pub fn detach_rule() -> Rule {
+    Rule::new(None, DELETE_SESSION, Action::Status(StatusCode::OK))
+}
+
+/// Builds delay rules from a list like `findElement=500ms,elementClick=200ms`
+pub fn latency_rules(spec: &str) -> Result<Vec<Rule>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (command, delay) = entry
+                .split_once('=')
+                .with_context(|| format!("Expected '<command>=<delay>', got '{}'", entry))?;
+            let delay = humantime::parse_duration(delay.trim())?;
+
+            Ok(Rule::new(
+                None,
+                Target::command(command.trim())?,
+                Action::Delay(delay),
+            ))
+        })
+        .collect()
+}
+
+/// Where the W3C capabilities of a New Session request are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matching {
+    /// Everything in `alwaysMatch`, unless there are several `firstMatch` branches
+    AlwaysMatch,
+    /// Every branch in `firstMatch`, with `alwaysMatch` merged into each of them
+    FirstMatch,
+}
+
+/// Structure of the New Session payload sent to the grid, configured as a comma separated list
+/// like `first-match,no-legacy`. Parts not configured are forwarded as the client sent them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PayloadShape {
+    pub matching: Option<Matching>,
+    /// Whether to send the legacy JSON Wire `desiredCapabilities` next to the W3C capabilities
+    pub legacy: Option<bool>,
+}
+
+impl FromStr for PayloadShape {
+    type Err = anyhow::Error;
+
+    fn from_str(list: &str) -> Result<Self> {
+        let mut shape = Self::default();
+
+        for option in list.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option {
+                "always-match" => shape.matching = Some(Matching::AlwaysMatch),
+                "first-match" => shape.matching = Some(Matching::FirstMatch),
+                "legacy" => shape.legacy = Some(true),
+                "no-legacy" => shape.legacy = Some(false),
+                _ => bail!(
+                    "Unknown payload option '{}', expected always-match, first-match, legacy or no-legacy",
+                    option
+                ),
+            }
+        }
+
+        if shape == Self::default() {
+            bail!("No payload option given");
+        }
+
+        Ok(shape)
+    }
+}
+
+impl PayloadShape {
+    /// Restructures a New Session payload, leaving anything but an object untouched
+    fn apply(&self, payload: &mut Value) {
+        let payload = match payload.as_object_mut() {
+            Some(payload) => payload,
+            None => return,
+        };
+
+        let capabilities = payload.remove("capabilities");
+        let always = capabilities
+            .as_ref()
+            .and_then(|c| c.get("alwaysMatch"))
+            .and_then(Value::as_object)
+            .cloned()
+            .unwrap_or_default();
+        let first = capabilities
+            .as_ref()
+            .and_then(|c| c.get("firstMatch"))
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        // Legacy clients only send `desiredCapabilities`, which then stand in for the W3C ones
+        let desired = payload
+            .get("desiredCapabilities")
+            .and_then(Value::as_object)
+            .cloned()
+            .filter(|_| capabilities.is_none());
+
+        let mut branches = first
+            .iter()
+            .filter_map(Value::as_object)
+            .map(|branch| {
+                let mut merged = always.clone();
+                merged.extend(branch.clone());
+                merged
+            })
+            .collect::<Vec<_>>();
+        if branches.is_empty() {
+            branches.push(desired.unwrap_or(always));
+        }
+
+        let capabilities = match (self.matching, capabilities) {
+            (Some(Matching::FirstMatch), _) => {
+                serde_json::json!({ "firstMatch": branches })
+            }
+            (Some(Matching::AlwaysMatch), _) if branches.len() == 1 => {
+                serde_json::json!({ "alwaysMatch": branches[0] })
+            }
+            (_, Some(capabilities)) => capabilities,
+            (_, None) => Value::Null,
+        };
+        if !capabilities.is_null() {
+            payload.insert("capabilities".into(), capabilities);
+        }
+
+        match self.legacy {
+            Some(true) if !payload.contains_key("desiredCapabilities") => {
+                payload.insert("desiredCapabilities".into(), branches[0].clone().into());
+            }
+            Some(false) => {
+                payload.remove("desiredCapabilities");
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Proxy {
+    upstream: String,
+    rules: Vec<Rule>,
+    /// One client per local address requests are sent from
+    clients: Vec<reqwest::Client>,
+    local_addresses: Vec<IpAddr>,
+    next_client: AtomicUsize,
+    /// Bound of every request but the session creation, which the runner bounds itself
+    command_timeout: Option<Duration>,
+    session_timing: Option<Arc<SessionTiming>>,
+    payload_shape: Option<PayloadShape>,
+    redactor: Redactor,
+}
+
+impl Proxy {
+    async fn handle(&self, mut request: Request<Body>) -> Response<Body> {
+        let start = Instant::now();
+        let method = request.method().clone();
+        let path = request
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str().to_owned())
+            .unwrap_or_default();
+
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(&method, &path))
+            .collect::<Vec<_>>();
+
+        let logged = rules.iter().any(|rule| matches!(rule.action, Action::Log));
+
+        for rule in rules.iter() {
+            match &rule.action {
+                Action::Log => {}
+                Action::Delay(duration) => sleep(*duration).await,
+                Action::Header(name, value) => {
+                    request.headers_mut().insert(name.clone(), value.clone());
+                }
+                Action::Status(status) if status.is_success() => {
+                    log::debug!("{} {} -> {} (injected)", method, path, status);
+                    return success_response(*status);
+                }
+                Action::Status(status) => {
+                    log::info!("{} {} -> {} (injected)", method, path, status);
+                    return error_response(*status, "Response injected by proxy rule");
+                }
+            }
+        }
+
+        let new_session = NEW_SESSION.matches(&method, &path);
+        if let Some(shape) = self.payload_shape.filter(|_| new_session) {
+            request = match reshape(request, &shape).await {
+                Ok(request) => request,
+                Err(e) => {
+                    log::warn!("{} {} could not be read: {}", method, path, e);
+                    return error_response(StatusCode::BAD_REQUEST, &e.to_string());
+                }
+            };
+        }
+        let limit = self.command_timeout.filter(|_| !new_session);
+        let forward = async {
+            match &self.session_timing {
+                Some(timing) if new_session => self.create_session(request, &path, timing).await,
+                _ => self.forward(request, &path).await,
+            }
+        };
+        let forwarded = match limit {
+            Some(limit) => timeout(limit, forward).await,
+            None => Ok(forward.await),
+        };
+
+        let response = match forwarded {
+            Ok(Ok(response)) => response,
+            Err(_) => {
+                let message = format!(
+                    "Command {} {} timed out after {}",
+                    method,
+                    path,
+                    format_duration(limit.unwrap_or_default())
+                );
+                log::warn!("{}", message);
+                error_response(StatusCode::GATEWAY_TIMEOUT, &message)
+            }
+            Ok(Err(e)) => {
+                let message = self.redactor.redact(&e.to_string());
+                log::warn!("{} {} could not be forwarded: {}", method, path, message);
+                error_response(StatusCode::BAD_GATEWAY, &message)
+            }
+        };
+
+        let duration = format_duration(start.elapsed());
+        if logged {
+            log::info!(
+                "{} {} -> {} ({})",
+                method,
+                path,
+                response.status(),
+                duration
+            );
+        } else {
+            log::debug!(
+                "{} {} -> {} ({})",
+                method,
+                path,
+                response.status(),
+                duration
+            );
+        }
+
+        response
+    }
+
+    /// Client of the local address whose turn it is
+    fn client(&self) -> &reqwest::Client {
+        let next = self.next_client.fetch_add(1, Ordering::Relaxed);
+        &self.clients[next % self.clients.len()]
+    }
+
+    /// Opens connections to the upstream by sending that many status requests at once, so each
+    /// of them needs a connection of its own which then stays in the pool
+    async fn warm(&self, connections: usize) {
+        let start = Instant::now();
+        let url = format!("{}/status", self.upstream);
+        let requests = (0..connections)
+            .map(|_| {
+                let request = self.client().get(&url);
+                // Connections only return to the pool once the response was read completely
+                spawn(async move { request.send().await?.bytes().await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut opened = 0;
+        let mut error = None;
+        for request in requests {
+            match request.await {
+                Ok(Ok(_)) => opened += 1,
+                Ok(Err(e)) => error = Some(e.to_string()),
+                Err(e) => error = Some(e.to_string()),
+            }
+        }
+
+        log::info!(
+            "Pre-opened {} of {} connections to the grid in {}",
+            opened,
+            connections,
+            format_duration(Duration::from_millis(start.elapsed().as_millis() as u64))
+        );
+        if let Some(error) = error {
+            log::warn!(
+                "Failed to pre-open connections: {}",
+                self.redactor.redact(&error)
+            );
+        }
+    }
+
+    async fn forward(&self, request: Request<Body>, path: &str) -> Result<Response<Body>> {
+        let (mut parts, body) = request.into_parts();
+        parts.headers.remove(HOST);
+
+        let upstream_response = self
+            .client()
+            .request(parts.method, format!("{}{}", self.upstream, path))
+            .headers(parts.headers)
+            .body(hyper::body::to_bytes(body).await?)
+            .send()
+            .await?;
+
+        let status = upstream_response.status();
+        let headers = upstream_response.headers().clone();
+        let body = upstream_response.bytes().await?;
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+
+        Ok(response)
+    }
+
+    /// Sends a New Session request over a connection of its own and records how long each phase
+    /// took, pooled connections would hide the connection setup
+    async fn create_session(
+        &self,
+        request: Request<Body>,
+        path: &str,
+        timing: &SessionTiming,
+    ) -> Result<Response<Body>> {
+        let url = reqwest::Url::parse(&format!("{}{}", self.upstream, path))?;
+        let host = url
+            .host_str()
+            .context("The grid endpoint has no host")?
+            .to_owned();
+        let port = url
+            .port_or_known_default()
+            .context("The grid endpoint has no port")?;
+        let tls = url.scheme() == "https";
+
+        let start = Instant::now();
+        let addresses = lookup_host((host.as_str(), port))
+            .await?
+            .collect::<Vec<_>>();
+        let dns = start.elapsed();
+
+        let start = Instant::now();
+        let stream = self.open(&addresses).await?;
+        let connect = start.elapsed();
+
+        let start = Instant::now();
+        let stream: Box<dyn Stream> = if tls {
+            let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+            Box::new(connector.connect(&host, stream).await?)
+        } else {
+            Box::new(stream)
+        };
+        let handshake = Some(start.elapsed()).filter(|_| tls);
+
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+        // Closes the connection once the response was read and the sender is gone
+        spawn(async move { connection.await.ok() });
+
+        let (mut parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        parts.uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        }
+        .parse()?;
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host,
+        };
+        parts
+            .headers
+            .insert(HOST, HeaderValue::from_str(&authority)?);
+        parts.headers.remove(TRANSFER_ENCODING);
+        parts.headers.insert(CONTENT_LENGTH, body.len().into());
+        // The client of `forward` sends the credentials of the endpoint by itself
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            if let Some(credentials) = basic_auth(&url) {
+                parts.headers.insert(AUTHORIZATION, credentials);
+            }
+        }
+
+        let start = Instant::now();
+        let response = sender
+            .send_request(Request::from_parts(parts, Body::from(body)))
+            .await?;
+        let first_byte = start.elapsed();
+
+        let start = Instant::now();
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let transfer = start.elapsed();
+
+        // W3C grids answer with `value.sessionId`, legacy ones with `sessionId`
+        let session_id = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .and_then(|value| {
+                value["value"]["sessionId"]
+                    .as_str()
+                    .or_else(|| value["sessionId"].as_str())
+                    .map(str::to_owned)
+            });
+        timing.record(
+            session_id.as_deref(),
+            &Phases {
+                dns,
+                connect,
+                tls: handshake,
+                first_byte,
+                transfer,
+            },
+        );
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    /// Opens a TCP connection to the first of the addresses that accepts one, from the local
+    /// address whose turn it is
+    async fn open(&self, addresses: &[SocketAddr]) -> Result<TcpStream> {
+        let local_address = match self.local_addresses.len() {
+            0 => None,
+            count => {
+                let next = self.next_client.fetch_add(1, Ordering::Relaxed);
+                Some(self.local_addresses[next % count])
+            }
+        };
+
+        let mut error = None;
+        for address in addresses {
+            let socket = if address.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            if let Some(local_address) = local_address {
+                if local_address.is_ipv4() != address.is_ipv4() {
+                    continue;
+                }
+                socket.bind(SocketAddr::new(local_address, 0))?;
+            }
+
+            match socket.connect(*address).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        Err(match error {
+            Some(e) => e.into(),
+            None => anyhow!("The grid has no address to connect to"),
+        })
+    }
+}
+
+/// `Authorization` header carrying the credentials contained in `url`
+fn basic_auth(url: &reqwest::Url) -> Option<HeaderValue> {
+    if url.username().is_empty() && url.password().is_none() {
+        return None;
+    }
+
+    let decode = |part: &str| percent_decode(part).unwrap_or_else(|| part.to_owned());
+    let credentials = format!(
+        "{}:{}",
+        decode(url.username()),
+        url.password().map(decode).unwrap_or_default()
+    );
+    let mut value =
+        HeaderValue::from_str(&format!("Basic {}", base64::encode(credentials))).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+/// Builds an empty WebDriver response, like the one to a deleted session
+fn success_response(status: StatusCode) -> Response<Body> {
+    let body = serde_json::json!({ "value": null });
+
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Builds a response shaped like a WebDriver error so clients surface it properly
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({
+        "value": {
+            "error": "unknown error",
+            "message": message,
+            "stacktrace": ""
+        }
+    });
+
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Rewrites the body of a New Session request into the given shape, bodies which are not JSON are
+/// left to the grid to reject
+async fn reshape(request: Request<Body>, shape: &PayloadShape) -> Result<Request<Body>> {
+    let (mut parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(mut payload) => {
+            shape.apply(&mut payload);
+            serde_json::to_vec(&payload)?.into()
+        }
+        Err(_) => body,
+    };
+
+    parts.headers.remove(TRANSFER_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, body.len().into());
+
+    Ok(Request::from_parts(parts, Body::from(body)))
+}
+
+/// Starts the proxy on a random local port and returns the endpoint clients should use instead of `upstream`.
+/// The proxy forwards requests over a shared connection pool, `warm_connections` of which are
+/// opened right away. Given local addresses, requests are sent from each of them in turn.
+pub async fn start(
+    upstream: &str,
+    rules: Vec<Rule>,
+    warm_connections: usize,
+    local_addresses: &[IpAddr],
+    command_timeout: Option<Duration>,
+    session_timing: Option<Arc<SessionTiming>>,
+    payload_shape: Option<PayloadShape>,
+) -> Result<String> {
+    let client = |local_address: Option<IpAddr>| {
+        let mut builder = reqwest::Client::builder().local_address(local_address);
+        // Warmed connections would otherwise be closed after 90 seconds of waiting for the run
+        if warm_connections > 0 {
+            builder = builder.pool_idle_timeout(None);
+        }
+        builder.build()
+    };
+    let clients = if local_addresses.is_empty() {
+        vec![client(None)?]
+    } else {
+        log::info!(
+            "Sending WebDriver requests from {}",
+            local_addresses
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        local_addresses
+            .iter()
+            .map(|address| client(Some(*address)))
+            .collect::<reqwest::Result<Vec<_>>>()?
+    };
+    let proxy = Arc::new(Proxy {
+        upstream: upstream.trim_end_matches('/').to_owned(),
+        rules,
+        clients,
+        local_addresses: local_addresses.to_vec(),
+        next_client: AtomicUsize::new(0),
+        command_timeout,
+        session_timing,
+        payload_shape,
+        redactor: Redactor::new(upstream),
+    });
+
+    if warm_connections > 0 {
+        proxy.warm(warm_connections).await;
+    }
+
+    let make_service = make_service_fn(move |_| {
+        let proxy = proxy.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let proxy = proxy.clone();
+                async move { Ok::<_, Infallible>(proxy.handle(request).await) }
+            }))
+        }
+    });
+
+    let address = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server = Server::try_bind(&address)?.serve(make_service);
+    let local_address = server.local_addr();
+
+    spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Proxy stopped unexpectedly: {}", e);
+        }
+    });
+
+    Ok(format!("http://{}/", local_address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(line: &str) -> Rule {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_every_action() {
+        assert!(matches!(rule("log * *").action, Action::Log));
+        assert!(matches!(
+            rule("delay POST @findElement 1s 500ms").action,
+            Action::Delay(delay) if delay == Duration::from_millis(1500)
+        ));
+        assert!(matches!(
+            rule("status GET /title 503").action,
+            Action::Status(StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        match rule("header * * X-Trace-Id: a b").action {
+            Action::Header(name, value) => {
+                assert_eq!(name, "x-trace-id");
+                assert_eq!(value, "a b");
+            }
+            _ => panic!("expected a header action"),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for line in [
+            "log *",
+            "drop * *",
+            "delay * * soon",
+            "status * * 42",
+            "status * * ok",
+            "header * * X-Trace-Id",
+            "log * @fetchEverything",
+            "log G(ET *",
+        ] {
+            assert!(line.parse::<Rule>().is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn methods_are_case_insensitive() {
+        let rule = rule("log get *");
+        assert!(rule.matches(&Method::GET, "/session/1/url"));
+        assert!(!rule.matches(&Method::POST, "/session/1/url"));
+    }
+
+    #[test]
+    fn path_targets_match_substrings() {
+        let rule = rule("log * /screenshot");
+        assert!(rule.matches(&Method::GET, "/wd/hub/session/1/screenshot"));
+        assert!(!rule.matches(&Method::GET, "/wd/hub/session/1/source"));
+        assert!(rule("log * *").matches(&Method::DELETE, "/anything"));
+    }
+
+    #[test]
+    fn command_targets_match_whole_paths() {
+        let rule = rule("log * @navigateTo");
+        assert!(rule.matches(&Method::POST, "/session/abc/url"));
+        assert!(rule.matches(&Method::POST, "/session/abc/url?retry=1"));
+        assert!(!rule.matches(&Method::GET, "/session/abc/url"));
+        assert!(!rule.matches(&Method::POST, "/session/abc/url/extra"));
+        assert!(!rule.matches(&Method::POST, "/session/abc/title"));
+
+        assert!(NEW_SESSION.matches(&Method::POST, "/session"));
+        assert!(!NEW_SESSION.matches(&Method::POST, "/session/abc/url"));
+    }
+
+    #[test]
+    fn detaching_answers_only_session_deletions() {
+        let rule = detach_rule();
+        assert!(rule.matches(&Method::DELETE, "/session/abc"));
+        assert!(!rule.matches(&Method::DELETE, "/session/abc/cookie"));
+        assert!(matches!(rule.action, Action::Status(StatusCode::OK)));
+    }
+
+    #[test]
+    fn latency_rules_delay_commands() {
+        let rules = latency_rules(" findElement=500ms, ,elementClick = 1s ").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].matches(&Method::POST, "/session/1/element"));
+        assert!(matches!(
+            rules[1].action,
+            Action::Delay(delay) if delay == Duration::from_secs(1)
+        ));
+
+        assert!(latency_rules("findElement").is_err());
+        assert!(latency_rules("teleport=1s").is_err());
+    }
+
+    #[test]
+    fn parses_payload_shapes() {
+        assert_eq!(
+            "first-match, no-legacy".parse::<PayloadShape>().unwrap(),
+            PayloadShape {
+                matching: Some(Matching::FirstMatch),
+                legacy: Some(false),
+            }
+        );
+        assert_eq!(
+            "legacy".parse::<PayloadShape>().unwrap(),
+            PayloadShape {
+                matching: None,
+                legacy: Some(true),
+            }
+        );
+        assert!("".parse::<PayloadShape>().is_err());
+        assert!("any-match".parse::<PayloadShape>().is_err());
+    }
+
+    fn reshaped(shape: &str, payload: Value) -> Value {
+        let mut payload = payload;
+        shape.parse::<PayloadShape>().unwrap().apply(&mut payload);
+        payload
+    }
+
+    #[test]
+    fn moves_capabilities_between_always_and_first_match() {
+        let payload = serde_json::json!({
+            "capabilities": { "alwaysMatch": { "browserName": "firefox" } },
+            "desiredCapabilities": { "browserName": "firefox" },
+        });
+
+        assert_eq!(
+            reshaped("first-match,no-legacy", payload.clone()),
+            serde_json::json!({
+                "capabilities": { "firstMatch": [{ "browserName": "firefox" }] },
+            })
+        );
+        assert_eq!(
+            reshaped("always-match", reshaped("first-match", payload.clone())),
+            payload
+        );
+    }
+
+    #[test]
+    fn keeps_several_first_match_branches_apart() {
+        let payload = serde_json::json!({
+            "capabilities": {
+                "alwaysMatch": { "acceptInsecureCerts": true },
+                "firstMatch": [{ "browserName": "firefox" }, { "browserName": "chrome" }],
+            },
+        });
+
+        assert_eq!(reshaped("always-match", payload.clone()), payload);
+        assert_eq!(
+            reshaped("first-match,legacy", payload),
+            serde_json::json!({
+                "capabilities": {
+                    "firstMatch": [
+                        { "acceptInsecureCerts": true, "browserName": "firefox" },
+                        { "acceptInsecureCerts": true, "browserName": "chrome" },
+                    ],
+                },
+                "desiredCapabilities": { "acceptInsecureCerts": true, "browserName": "firefox" },
+            })
+        );
+    }
+
+    #[test]
+    fn upgrades_legacy_payloads() {
+        let payload = serde_json::json!({ "desiredCapabilities": { "browserName": "chrome" } });
+
+        assert_eq!(reshaped("legacy", payload.clone()), payload);
+        assert_eq!(
+            reshaped("always-match", payload),
+            serde_json::json!({
+                "capabilities": { "alwaysMatch": { "browserName": "chrome" } },
+                "desiredCapabilities": { "browserName": "chrome" },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn timed_session_creation_sends_the_endpoint_credentials() {
+        let received = Arc::new(std::sync::Mutex::new(None));
+        let make_service = {
+            let received = received.clone();
+            make_service_fn(move |_| {
+                let received = received.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        *received.lock().unwrap() = request.headers().get(AUTHORIZATION).cloned();
+                        async {
+                            Ok::<_, Infallible>(Response::new(Body::from(
+                                r#"{"value":{"sessionId":"abc","capabilities":{}}}"#,
+                            )))
+                        }
+                    }))
+                }
+            })
+        };
+        let grid = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let upstream = format!("http://user:p%40ss@{}/wd/hub", grid.local_addr());
+        spawn(grid);
+
+        let timing = Arc::new(SessionTiming::new(Arc::new(crate::metrics::Metrics::new())));
+        let endpoint = start(&upstream, Vec::new(), 0, &[], None, Some(timing), None)
+            .await
+            .unwrap();
+        let response = reqwest::Client::new()
+            .post(format!("{}session", endpoint))
+            .body(r#"{"capabilities":{}}"#)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(
+            received.lock().unwrap().as_ref().unwrap(),
+            &format!("Basic {}", base64::encode("user:p@ss"))
+        );
+    }
+}
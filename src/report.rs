@@ -0,0 +1,263 @@
+//! Summary of the event log written by a previous run.
+
+use anyhow::{Context, Result};
+use humantime::format_duration;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+/// Reads all records of an event log, skipping a last line cut off by a killed run
+pub fn read(path: &str) -> Result<Vec<Value>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read event log '{}'", path))?;
+
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Sessions of tests that started but never finished
+pub fn orphaned_sessions(records: &[Value]) -> Vec<String> {
+    let mut sessions = HashMap::new();
+
+    for record in records {
+        let test = record["test"].as_str().unwrap_or_default();
+        if record["event"] == "session_started" {
+            if let Some(id) = record["session_id"].as_str() {
+                sessions.insert(test.to_owned(), id.to_owned());
+            }
+        } else if record["event"] == "session_finished" {
+            sessions.remove(test);
+        }
+    }
+
+    let mut sessions = sessions.into_values().collect::<Vec<_>>();
+    sessions.sort();
+    sessions.dedup();
+    sessions
+}
+
+/// Logs outcomes, durations and failures of the run recorded in the event log
+pub fn summarize(path: &str) -> Result<()> {
+    let records = read(path)?;
+
+    if let Some(run) = records.iter().find(|r| r["event"] == "run_started") {
+        log::info!(
+            "Run of {} {} sessions with the {} scenario against '{}' started at {}",
+            run["count"],
+            run["browser"].as_str().unwrap_or_default(),
+            run["scenario"].as_str().unwrap_or("unknown"),
+            run["endpoint"].as_str().unwrap_or_default(),
+            run["timestamp"].as_str().unwrap_or_default()
+        );
+    }
+
+    let finished = records
+        .iter()
+        .filter(|r| r["event"] == "session_finished")
+        .collect::<Vec<_>>();
+    let failures = finished
+        .iter()
+        .filter(|r| r["success"] != true)
+        .collect::<Vec<_>>();
+
+    log::info!(
+        "{} / {} tests succeeded.",
+        finished.len() - failures.len(),
+        finished.len()
+    );
+
+    // Passes that needed a re-run or session retries are instability a clean count would hide
+    let attempts = |record: &Value, key: &str| record[key].as_u64().unwrap_or(1);
+    let flaky = finished
+        .iter()
+        .filter(|r| r["success"] == true && attempts(r, "attempts") > 1)
+        .count();
+    if flaky > 0 {
+        log::info!(
+            "{} of the passed tests failed at first and only passed when re-run.",
+            flaky
+        );
+    }
+    let session_retries = finished
+        .iter()
+        .map(|r| attempts(r, "session_attempts") - 1)
+        .sum::<u64>();
+    if session_retries > 0 {
+        log::info!(
+            "Session creations were retried {} times in {} tests.",
+            session_retries,
+            finished
+                .iter()
+                .filter(|r| attempts(r, "session_attempts") > 1)
+                .count()
+        );
+    }
+
+    let mut durations = finished
+        .iter()
+        .filter_map(|r| r["duration_ms"].as_u64())
+        .collect::<Vec<_>>();
+    durations.sort_unstable();
+    if !durations.is_empty() {
+        let quantile = |q: f64| format_duration(Duration::from_millis(quantile(&durations, q)));
+        log::info!(
+            "Durations: median {}, p95 {}, max {}",
+            quantile(0.5),
+            quantile(0.95),
+            quantile(1.0)
+        );
+    }
+
+    for failure in failures {
+        log::info!(
+            "Test #{} failed: {}",
+            failure["test"].as_str().unwrap_or_default(),
+            failure["error"].as_str().unwrap_or_default()
+        );
+    }
+
+    // Runs on several browser versions or with a replay mixing scenarios are a grid themselves
+    let mut matrix = Matrix::default();
+    matrix.add(&records);
+    if matrix.len() > 1 {
+        matrix.log();
+    }
+
+    let orphaned = orphaned_sessions(&records);
+    if !orphaned.is_empty() {
+        log::warn!(
+            "{} sessions never finished, the run was probably aborted. `cleanup` deletes them.",
+            orphaned.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Pass rate and p95 duration per browser version and scenario, so a regression in a single
+/// combination stands out
+#[derive(Default)]
+pub struct Matrix {
+    /// Failed tests and durations of all tests per browser and scenario
+    cells: BTreeMap<(String, String), (u64, Vec<u64>)>,
+}
+
+impl Matrix {
+    /// Matrix of all tests in the event logs
+    pub fn from_logs(paths: &[String]) -> Result<Self> {
+        let mut matrix = Self::default();
+        for path in paths {
+            matrix.add(&read(path)?);
+        }
+
+        Ok(matrix)
+    }
+
+    /// Adds the tests of one run, each in the cell of the browser version its session reported
+    /// and the scenario it ran
+    pub fn add(&mut self, records: &[Value]) {
+        let run = records.iter().find(|r| r["event"] == "run_started");
+        let label = |key: &str| run.and_then(|r| r[key].as_str()).unwrap_or("unknown");
+
+        for record in records.iter().filter(|r| r["event"] == "session_finished") {
+            let browser = match record["browser_version"].as_str() {
+                Some(version) => format!("{} {}", label("browser"), version),
+                None => label("browser").to_owned(),
+            };
+            let scenario = record["scenario"].as_str().unwrap_or(label("scenario"));
+
+            let (failed, durations) = self
+                .cells
+                .entry((browser, scenario.to_owned()))
+                .or_default();
+            durations.push(record["duration_ms"].as_u64().unwrap_or_default());
+            if record["success"] != true {
+                *failed += 1;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn browsers(&self) -> BTreeSet<&str> {
+        self.cells.keys().map(|(b, _)| b.as_str()).collect()
+    }
+
+    pub fn scenarios(&self) -> BTreeSet<&str> {
+        self.cells.keys().map(|(_, s)| s.as_str()).collect()
+    }
+
+    /// Pass rate and p95 duration of a cell, `-` if no test ran in it
+    pub fn text(&self, browser: &str, scenario: &str) -> String {
+        match self.cells.get(&(browser.to_owned(), scenario.to_owned())) {
+            Some((failed, durations)) if !durations.is_empty() => {
+                let mut durations = durations.clone();
+                durations.sort_unstable();
+                let total = durations.len() as u64;
+                format!(
+                    "{:.0}% {:.1}s",
+                    (total - failed) as f64 * 100.0 / total as f64,
+                    quantile(&durations, 0.95) as f64 / 1000.0
+                )
+            }
+            _ => "-".to_owned(),
+        }
+    }
+
+    pub fn log(&self) {
+        let (browsers, scenarios) = (self.browsers(), self.scenarios());
+
+        let first = browsers.iter().map(|b| b.len()).max().unwrap_or(0);
+        let widths = scenarios
+            .iter()
+            .map(|scenario| {
+                browsers
+                    .iter()
+                    .map(|browser| self.text(browser, scenario).len())
+                    .chain(std::iter::once(scenario.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        log::info!("Pass rate and p95 per browser and scenario:");
+        let header = scenarios
+            .iter()
+            .zip(widths.iter())
+            .map(|(scenario, width)| format!("{:>width$}", scenario, width = width))
+            .collect::<Vec<_>>();
+        log::info!("  {:first$}  {}", "", header.join("  "), first = first);
+
+        for browser in browsers.iter() {
+            let row = scenarios
+                .iter()
+                .zip(widths.iter())
+                .map(|(scenario, width)| {
+                    format!("{:>width$}", self.text(browser, scenario), width = width)
+                })
+                .collect::<Vec<_>>();
+            log::info!("  {:first$}  {}", browser, row.join("  "), first = first);
+        }
+    }
+}
+
+/// Logs the grid of the pass rate and p95 duration per browser and scenario of several runs
+pub fn log_matrix(paths: &[String]) -> Result<()> {
+    Matrix::from_logs(paths)?.log();
+    Ok(())
+}
+
+/// Value at the given quantile of sorted values
+fn quantile(sorted: &[u64], q: f64) -> u64 {
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
@@ -0,0 +1,177 @@
+//! Retries of failed session creations, so transient errors of a grid that is still scaling up
+//! do not count as failed tests.
+//!
+//! Retries wait with exponential backoff and full jitter: the n-th retry waits a random time of
+//! up to `delay * 2^n`, capped at the maximum delay, so tests that failed together do not hit
+//! the grid together again.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::Duration,
+};
+
+pub struct SessionRetries {
+    retries: u32,
+    delay: Duration,
+    max_delay: Duration,
+    /// Tests that got a session and tests that did not, by the number of attempts they made
+    attempts: Mutex<BTreeMap<u32, (u64, u64)>>,
+}
+
+impl SessionRetries {
+    /// Reads the number of retries from `SESSION_RETRIES` unless given (default 0), the initial
+    /// delay from `SESSION_RETRY_DELAY` (default 1s) and its cap from `SESSION_RETRY_MAX_DELAY`
+    /// (default 30s)
+    pub fn from_env(retries: Option<u32>) -> Result<Self> {
+        let retries = match retries {
+            Some(retries) => retries,
+            None => std::env::var("SESSION_RETRIES")
+                .unwrap_or("0".into())
+                .parse::<u32>()
+                .context("Invalid SESSION_RETRIES")?,
+        };
+        let duration = |variable: &str, default: &str| -> Result<Duration> {
+            humantime::parse_duration(&std::env::var(variable).unwrap_or(default.into()))
+                .with_context(|| format!("Invalid {}", variable))
+        };
+
+        Ok(Self {
+            retries,
+            delay: duration("SESSION_RETRY_DELAY", "1s")?,
+            max_delay: duration("SESSION_RETRY_MAX_DELAY", "30s")?,
+            attempts: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Whether another attempt is allowed after the given number of failed ones
+    pub fn allows(&self, failed_attempts: u32) -> bool {
+        failed_attempts <= self.retries
+    }
+
+    /// Time to wait before the given retry, counting from zero
+    pub fn delay(&self, retry: u32) -> Duration {
+        let random = RandomState::new().build_hasher().finish();
+
+        self.ceiling(retry).mul_f64(random as f64 / u64::MAX as f64)
+    }
+
+    /// Longest time to wait before the given retry
+    fn ceiling(&self, retry: u32) -> Duration {
+        self.delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// Records the attempts a test made and whether it got a session in the end
+    pub fn record(&self, attempts: u32, created: bool) {
+        let mut counts = self.attempts.lock().unwrap();
+        let (succeeded, failed) = counts.entry(attempts).or_default();
+        if created {
+            *succeeded += 1;
+        } else {
+            *failed += 1;
+        }
+    }
+
+    /// Number of retries of all finished session creations
+    pub fn retried(&self) -> u64 {
+        self.attempts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(attempts, (created, failed))| (*attempts as u64 - 1) * (created + failed))
+            .sum()
+    }
+
+    /// Logs how many tests needed retries, if any did
+    pub fn log_summary(&self) {
+        let counts = self.attempts.lock().unwrap();
+        let retried = counts
+            .iter()
+            .filter(|(attempts, _)| **attempts > 1)
+            .collect::<Vec<_>>();
+        if retried.is_empty() {
+            return;
+        }
+
+        log::info!("Session creations that needed retries:");
+        for (attempts, (created, failed)) in retried {
+            log::info!(
+                "  {} attempts: {} created, {} gave up",
+                attempts,
+                created,
+                failed
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: u32, delay: Duration, max_delay: Duration) -> SessionRetries {
+        SessionRetries {
+            retries,
+            delay,
+            max_delay,
+            attempts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    #[test]
+    fn allows_the_configured_number_of_retries() {
+        let retries = policy(2, Duration::from_secs(1), Duration::from_secs(30));
+        assert!(retries.allows(1));
+        assert!(retries.allows(2));
+        assert!(!retries.allows(3));
+    }
+
+    #[test]
+    fn ceiling_doubles_up_to_the_maximum() {
+        let retries = policy(10, Duration::from_secs(1), Duration::from_secs(30));
+        let ceilings = (0..7)
+            .map(|retry| retries.ceiling(retry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ceilings,
+            [1, 2, 4, 8, 16, 30, 30]
+                .iter()
+                .map(|seconds| Duration::from_secs(*seconds))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn ceiling_does_not_overflow() {
+        let retries = policy(100, Duration::from_secs(1), Duration::from_secs(30));
+        assert_eq!(retries.ceiling(100), Duration::from_secs(30));
+
+        let retries = policy(100, Duration::MAX / 2, Duration::MAX);
+        assert_eq!(retries.ceiling(1), Duration::MAX - Duration::from_nanos(1));
+        assert_eq!(retries.ceiling(2), Duration::MAX);
+    }
+
+    #[test]
+    fn delays_are_jittered_below_the_ceiling() {
+        let retries = policy(10, Duration::from_secs(1), Duration::from_secs(30));
+        let delays = (0..100).map(|_| retries.delay(2)).collect::<Vec<_>>();
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_secs(4)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn counts_retries_of_all_attempts() {
+        let retries = policy(3, Duration::from_secs(1), Duration::from_secs(30));
+        retries.record(1, true);
+        retries.record(1, false);
+        retries.record(2, true);
+        retries.record(4, false);
+        retries.record(4, true);
+        assert_eq!(retries.retried(), 1 + 3 * 2);
+    }
+}
@@ -0,0 +1,2591 @@
+//! Orchestration of a run: creating sessions, running the scenario on them and reporting the
+//! outcome. Everything besides the endpoint, session count, browser and scenario is configured
+//! through the same environment variables the binary reads.
+
+#[cfg(feature = "tui")]
+use crate::dashboard::{self, Dashboard};
+#[cfg(feature = "demo-server")]
+use crate::demo;
+#[cfg(feature = "prometheus")]
+use crate::exporter;
+#[cfg(feature = "proxy")]
+use crate::proxy;
+use crate::{
+    abort::{AbortRule, FailureSpike},
+    allure::{self, AllureResults},
+    annotations::Annotations,
+    attribution::SessionTiming,
+    autoscaler::{Reactions, SquareWave},
+    breaker::CircuitBreaker,
+    budget::ErrorBudget,
+    capacity::{CapacitySearch, Round, Search},
+    console::Console,
+    dialect::{self, GridDialect},
+    echo,
+    events::{Event, EventLog},
+    flag::env_flag,
+    invariants, journey,
+    junit::JunitReport,
+    load::{LoadProfile, Timeline},
+    manifest,
+    metrics::{self, IntervalLog, Metrics, Steps},
+    mix::ScenarioMix,
+    monitor::{self, Monitor},
+    otlp::Tracer,
+    priority::PriorityClasses,
+    probe,
+    redact::Redactor,
+    retry::SessionRetries,
+    scenario::{DemoPage, Scenario, Session},
+    schedule::{ArrivalSchedule, ConstantRate, Replay},
+    script::{Journeys, ScenarioDir},
+    site,
+    targeting::{self, Breakdown, Targeting},
+    teardown::Teardown,
+    threshold::FailureThreshold,
+    timeouts::{bounded, StepTimeouts},
+    variables::Variables,
+    viewport::ViewportJitter,
+    watchdog::Watchdog,
+    InfrastructureFailure,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use humantime::format_duration;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    io::IsTerminal,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use thirtyfour::{prelude::*, Capabilities};
+use tokio::{
+    spawn,
+    sync::{watch, Semaphore},
+    task::JoinHandle,
+    time::{sleep, sleep_until},
+};
+
+/// Slack of the timeout of the WebDriver client over the session and command timeouts, so those
+/// fire first and name what timed out
+const CLIENT_TIMEOUT_MARGIN: Duration = Duration::from_secs(1);
+
+/// Bound of connecting to the after-quit hook and the console log endpoint
+const HOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs a scenario on many sessions in parallel
+pub struct Runner {
+    endpoint: String,
+    count: u64,
+    browser: String,
+    timeout: Option<Duration>,
+    session_timeout: Option<Duration>,
+    command_timeout: Option<Duration>,
+    step_timeouts: StepTimeouts,
+    teardown: Teardown,
+    console_log: Option<bool>,
+    viewport_jitter: Option<ViewportJitter>,
+    max_concurrent: Option<usize>,
+    profile: Option<LoadProfile>,
+    rate: Option<f64>,
+    square_wave: Option<SquareWave>,
+    schedule: Option<Arc<dyn ArrivalSchedule>>,
+    capacity: Option<CapacitySearch>,
+    abort: Option<AbortRule>,
+    junit: Option<String>,
+    screenshots: Option<String>,
+    events: Option<String>,
+    max_session_age: Option<Duration>,
+    grace_period: Option<Duration>,
+    session_retries: Option<u32>,
+    retries: Option<u32>,
+    max_failures: Option<u64>,
+    max_failure_rate: Option<f64>,
+    warm_connections: Option<usize>,
+    local_addresses: Vec<IpAddr>,
+    session_timing: bool,
+    slo: Option<f64>,
+    abort_on_budget: bool,
+    metrics_port: Option<u16>,
+    otlp: Option<String>,
+    tui: bool,
+    scenario: Option<Arc<dyn Scenario>>,
+    scenario_dir: Option<ScenarioDir>,
+}
+
+/// How a run ended, from the perspective of a CI pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// Tests failed or invariants were violated
+    Failed,
+    /// Most failures were caused by the grid rather than the application
+    GridFailed,
+    /// The tests passed (or stayed within the failure threshold) but alert rules fired during the
+    /// run
+    AlertsFired,
+    /// The run was interrupted before all tests finished
+    Interrupted,
+}
+
+impl Outcome {
+    /// Exit code the binary terminates with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Passed => 0,
+            Outcome::Failed => 1,
+            Outcome::GridFailed => 2,
+            Outcome::AlertsFired => 3,
+            Outcome::Interrupted => 130,
+        }
+    }
+}
+
+/// Configuration and shared state of a run, handed to every test
+struct Run {
+    endpoint: String,
+    browser: String,
+    scenario: Arc<dyn Scenario>,
+    /// Built-in scenarios and scripted journeys the arrival schedule or the mix selects for some
+    /// tests, by name
+    alternatives: HashMap<String, Arc<dyn Scenario>>,
+    mix: Option<ScenarioMix>,
+    /// Scripted journeys, reloaded between the rounds of continuous runs
+    journeys: Option<Journeys>,
+    /// Bound of the session creation, which includes queueing on the grid
+    session_timeout: Duration,
+    /// Bound of every further WebDriver request
+    command_timeout: Duration,
+    step_timeouts: StepTimeouts,
+    teardown: Teardown,
+    /// Client of the after-quit hook and the console log endpoint, bounded by the command timeout
+    hooks: reqwest::Client,
+    console: Console,
+    viewports: Option<ViewportJitter>,
+    /// Profile uploaded with every Firefox session, as a base64 encoded zip archive
+    firefox_profile: Option<String>,
+    /// User-data-dir on the grid nodes, Chrome sessions use a copy of their own next to it
+    chrome_user_data_dir: Option<String>,
+    iterations: u64,
+    started: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    /// Failed tests that did not even get a session
+    infrastructure_failures: AtomicU64,
+    /// Pooled sessions recreated because they reached their maximum age
+    renewals: AtomicU64,
+    session_retries: SessionRetries,
+    session_timing: Option<Arc<SessionTiming>>,
+    /// Times a failed test is re-run before it counts as failed
+    reruns: u32,
+    /// Tests that failed at first but passed on a re-run
+    flaky: AtomicU64,
+    threshold: FailureThreshold,
+    /// Holds new sessions back while the endpoint keeps failing to create them
+    breaker: Option<CircuitBreaker>,
+    /// Aborts the run once the failure rate spikes
+    spike: Option<FailureSpike>,
+    max_session_age: Option<Duration>,
+    /// Variables of every test, only collected if they are needed at the end of the run
+    captured: Option<Mutex<Vec<Variables>>>,
+    metrics: Arc<Metrics>,
+    redactor: Redactor,
+    events: Option<EventLog>,
+    tracer: Option<Tracer>,
+    #[cfg(feature = "tui")]
+    dashboard: Option<Dashboard>,
+    annotations: Option<Annotations>,
+    allure: Option<AllureResults>,
+    junit: Option<JunitReport>,
+    /// Directory receiving screenshots and page sources of failed tests
+    screenshots: Option<PathBuf>,
+    /// Screenshots and page sources saved so far, listed in the artifact manifest
+    artifacts: Mutex<Vec<String>>,
+    monitor: Option<Monitor>,
+    targeting: Targeting,
+    /// Outcomes per platform and browser version the tests actually ran on
+    platforms: Breakdown,
+    breakpoints: Breakdown,
+    versions: Breakdown,
+    priority_classes: PriorityClasses,
+    /// Outcomes per priority class
+    priorities: Breakdown,
+    /// Outcomes per scenario, of runs mixing several
+    scenarios: Breakdown,
+    dialect: Box<dyn GridDialect>,
+    /// Maximum time session messages are buffered before they are sent
+    status_interval: Duration,
+    watchdog: Watchdog,
+    /// Limits the number of sessions in flight if set
+    slots: Option<Arc<Semaphore>>,
+    profile: Option<LoadProfile>,
+    /// Outcomes per stage of the load profile, by the stage a test started in
+    stages: Breakdown,
+    timeline: Timeline,
+    budget: Option<ErrorBudget>,
+    /// Session creations around the transitions of a square wave
+    reactions: Option<Reactions>,
+    /// Set once the run is interrupted, stops launching tests and aborts the ones in flight
+    interrupt: watch::Sender<bool>,
+    launched: Instant,
+}
+
+impl Run {
+    fn emit(&self, event: Event<'_>) {
+        if let Some(tracer) = &self.tracer {
+            tracer.observe(&event);
+        }
+        if let Some(events) = &self.events {
+            events.emit(event);
+        }
+    }
+
+    /// Stops the run, returns whether it was interrupted before
+    fn interrupt(&self) -> bool {
+        self.interrupt.send_replace(true)
+    }
+
+    fn is_interrupted(&self) -> bool {
+        *self.interrupt.borrow()
+    }
+
+    /// Resolves once the run is interrupted
+    async fn interrupted(&self) {
+        let mut receiver = self.interrupt.subscribe();
+        while !*receiver.borrow() {
+            receiver.changed().await.ok();
+        }
+    }
+
+    /// Sleeps for the given duration unless the run is interrupted meanwhile, returns whether it
+    /// still runs
+    async fn pause(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = sleep(duration) => true,
+            _ = self.interrupted() => false,
+        }
+    }
+
+    /// Whether the error budget ran out and the run was aborted
+    fn budget_exhausted(&self) -> bool {
+        self.budget.as_ref().map_or(false, ErrorBudget::aborted)
+    }
+
+    fn capture(&self, variables: Variables) {
+        if let Some(captured) = &self.captured {
+            captured.lock().unwrap().push(variables);
+        }
+    }
+}
+
+impl Runner {
+    /// Runner for `count` sessions of Firefox against the WebDriver `endpoint`
+    pub fn new(endpoint: &str, count: u64) -> Self {
+        Self {
+            endpoint: endpoint.to_owned(),
+            count,
+            browser: "firefox".to_owned(),
+            timeout: None,
+            session_timeout: None,
+            command_timeout: None,
+            step_timeouts: StepTimeouts::default(),
+            teardown: Teardown::default(),
+            console_log: None,
+            viewport_jitter: None,
+            max_concurrent: None,
+            profile: None,
+            rate: None,
+            square_wave: None,
+            schedule: None,
+            capacity: None,
+            abort: None,
+            junit: None,
+            screenshots: None,
+            events: None,
+            max_session_age: None,
+            grace_period: None,
+            session_retries: None,
+            retries: None,
+            max_failures: None,
+            max_failure_rate: None,
+            warm_connections: None,
+            local_addresses: Vec::new(),
+            session_timing: false,
+            slo: None,
+            abort_on_budget: false,
+            metrics_port: None,
+            otlp: None,
+            tui: false,
+            scenario: None,
+            scenario_dir: None,
+        }
+    }
+
+    /// Browser to request, one of `firefox`, `chrome` or `safari`
+    pub fn browser(mut self, browser: &str) -> Self {
+        self.browser = browser.to_ascii_lowercase();
+        self
+    }
+
+    /// Timeout per session, defaults to `TIMEOUT` seconds or ten minutes
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of the session creation including the time queued on the grid, defaults to
+    /// `SESSION_TIMEOUT` or the timeout per session
+    pub fn session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of every WebDriver request after the session was created, defaults to
+    /// `COMMAND_TIMEOUT` or the timeout per session
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds every step, navigation and element lookup of the tests, timeouts that are not set
+    /// default to `STEP_TIMEOUT`, `NAVIGATION_TIMEOUT` and `LOOKUP_TIMEOUT`
+    pub fn step_timeouts(mut self, timeouts: StepTimeouts) -> Self {
+        self.step_timeouts = timeouts;
+        self
+    }
+
+    /// Collects the browser console of every test, counting its errors in the `console_errors`
+    /// variable, defaults to `CONSOLE_LOG`
+    pub fn console_log(mut self, enabled: bool) -> Self {
+        self.console_log = Some(enabled);
+        self
+    }
+
+    /// Gives every session a random viewport within the bounds, defaults to `VIEWPORT_JITTER`
+    /// and `PIXEL_RATIOS`
+    pub fn viewport_jitter(mut self, jitter: ViewportJitter) -> Self {
+        self.viewport_jitter = Some(jitter);
+        self
+    }
+
+    /// Cleans sessions up before they are quit, or leaves them to the grid, in addition to the
+    /// teardown configured through `TEARDOWN`, `TEARDOWN_SCRIPTS`, `SKIP_QUIT` and `AFTER_QUIT`
+    pub fn teardown(mut self, teardown: Teardown) -> Self {
+        self.teardown = teardown;
+        self
+    }
+
+    /// Number of sessions in flight at the same time, defaults to `MAX_CONCURRENT` or all of them
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+
+    /// Ramps the sessions in flight up to the session count and back down instead of running
+    /// the count once, defaults to the profile configured by `RAMP_UP`, `STEADY` and `RAMP_DOWN`
+    pub fn profile(mut self, profile: LoadProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Launches the sessions at a fixed rate per second regardless of how quickly earlier ones
+    /// finish, defaults to `RATE`
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+
+    /// Launches the sessions at the offsets given by a custom schedule instead of a fixed rate,
+    /// defaults to replaying the timestamps in the file named by `REPLAY`
+    pub fn schedule(mut self, schedule: impl ArrivalSchedule + 'static) -> Self {
+        self.schedule = Some(Arc::new(schedule));
+        self
+    }
+
+    /// Binary-searches the highest number of sessions in flight, up to the session count, that
+    /// still meets the objectives, defaults to the search configured by `FIND_CAPACITY`
+    pub fn find_capacity(mut self, search: CapacitySearch) -> Self {
+        self.capacity = Some(search);
+        self
+    }
+
+    /// Aborts the run once the failure rate within a rolling window exceeds a threshold, the
+    /// tests in flight quit their sessions like on Ctrl+C, defaults to `ABORT_FAILURE_RATE`
+    pub fn abort_on_failure_rate(mut self, rule: AbortRule) -> Self {
+        self.abort = Some(rule);
+        self
+    }
+
+    /// Alternates between the session count and a few sessions in flight to observe the grid's
+    /// autoscaler, defaults to the wave configured by `SQUARE_WAVE`
+    pub fn square_wave(mut self, square_wave: SquareWave) -> Self {
+        self.square_wave = Some(square_wave);
+        self
+    }
+
+    /// Writes a JUnit XML report of all tests to `path` at the end of the run, defaults to
+    /// `JUNIT_REPORT`
+    pub fn junit(mut self, path: &str) -> Self {
+        self.junit = Some(path.to_owned());
+        self
+    }
+
+    /// Saves a screenshot and the source of the page every failed test ended on to `directory`,
+    /// defaults to `SCREENSHOT_DIR`
+    pub fn screenshots(mut self, directory: &str) -> Self {
+        self.screenshots = Some(directory.to_owned());
+        self
+    }
+
+    /// Appends every lifecycle event as NDJSON to `path`, or streams them to stdout for `-`,
+    /// defaults to `EVENT_LOG`
+    pub fn events(mut self, path: &str) -> Self {
+        self.events = Some(path.to_owned());
+        self
+    }
+
+    /// Quits and recreates pooled sessions once they reached the given age, defaults to
+    /// `MAX_SESSION_AGE`
+    pub fn max_session_age(mut self, max_session_age: Duration) -> Self {
+        self.max_session_age = Some(max_session_age);
+        self
+    }
+
+    /// Retries failed session creations this many times with exponential backoff, defaults to
+    /// `SESSION_RETRIES` or none
+    pub fn session_retries(mut self, retries: u32) -> Self {
+        self.session_retries = Some(retries);
+        self
+    }
+
+    /// Re-runs failed tests on a fresh session up to this many times, tests that pass on a re-run
+    /// are reported as flaky instead of failed, defaults to `RETRIES` or none
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Number of failed tests the run tolerates before it counts as failed, defaults to
+    /// `MAX_FAILURES`
+    pub fn max_failures(mut self, max_failures: u64) -> Self {
+        self.max_failures = Some(max_failures);
+        self
+    }
+
+    /// Share of failed tests in percent the run tolerates before it counts as failed, defaults
+    /// to `MAX_FAILURE_RATE`
+    pub fn max_failure_rate(mut self, max_failure_rate: f64) -> Self {
+        self.max_failure_rate = Some(max_failure_rate);
+        self
+    }
+
+    /// Pre-opens this many connections to the grid before the run starts, so connection setup
+    /// does not add to the first session creations, defaults to `WARM_CONNECTIONS` or none
+    pub fn warm_connections(mut self, connections: usize) -> Self {
+        self.warm_connections = Some(connections);
+        self
+    }
+
+    /// Sends the WebDriver requests from this local address, requests alternate between all
+    /// addresses given, defaults to the comma separated `LOCAL_ADDRESSES`
+    pub fn local_address(mut self, address: IpAddr) -> Self {
+        self.local_addresses.push(address);
+        self
+    }
+
+    /// Times the phases of every session creation in the traffic proxy to attribute its latency
+    /// to the client, the network and the grid, defaults to `SESSION_TIMING`
+    pub fn session_timing(mut self, enabled: bool) -> Self {
+        self.session_timing = enabled;
+        self
+    }
+
+    /// Time tests in flight get to quit their sessions once the run is interrupted with Ctrl+C,
+    /// defaults to `GRACE_PERIOD` or 30 seconds
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Tracks the error budget of runs with a planned duration against a success rate objective
+    /// in percent, defaults to `SLO`
+    pub fn slo(mut self, slo: f64) -> Self {
+        self.slo = Some(slo);
+        self
+    }
+
+    /// Stops launching tests once the error budget is projected to run out before the planned
+    /// end, defaults to `SLO_ABORT`
+    pub fn abort_on_budget(mut self, abort: bool) -> Self {
+        self.abort_on_budget = abort;
+        self
+    }
+
+    /// Publishes live metrics for Prometheus on `/metrics` of the given port, defaults to
+    /// `METRICS_PORT`
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.metrics_port = Some(port);
+        self
+    }
+
+    /// Exports every test as a trace to the OpenTelemetry collector at `endpoint` via OTLP/HTTP,
+    /// defaults to `OTLP_ENDPOINT`
+    pub fn otlp(mut self, endpoint: &str) -> Self {
+        self.otlp = Some(endpoint.to_owned());
+        self
+    }
+
+    /// Shows a live dashboard in the terminal instead of logging every test, defaults to `TUI`
+    pub fn tui(mut self, tui: bool) -> Self {
+        self.tui = tui;
+        self
+    }
+
+    /// Scenario every session runs, defaults to the one selected by `SCENARIO`
+    pub fn scenario(mut self, scenario: impl Scenario + 'static) -> Self {
+        self.scenario = Some(Arc::new(scenario));
+        self
+    }
+
+    /// Loads the scripted journeys of a directory as scenarios selectable by name, defaults to
+    /// `SCENARIO_DIR`
+    pub fn scenario_dir(mut self, dir: ScenarioDir) -> Self {
+        self.scenario_dir = Some(dir);
+        self
+    }
+
+    /// Executes the run and waits for all tests to finish.
+    ///
+    /// Runs that keep launching tests (`TRICKLE_INTERVAL`) never return.
+    pub async fn run(self) -> Result<Outcome> {
+        let endpoint = self.endpoint.as_str();
+        let browser = self.browser;
+
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => Duration::from_secs(
+                std::env::var("TIMEOUT")
+                    .unwrap_or("600".into())
+                    .parse::<u64>()
+                    .context("Invalid TIMEOUT")?,
+            ),
+        };
+        let session_timeout = match self.session_timeout {
+            Some(timeout) => timeout,
+            None => match std::env::var("SESSION_TIMEOUT") {
+                Ok(value) => {
+                    humantime::parse_duration(&value).context("Invalid SESSION_TIMEOUT")?
+                }
+                Err(_) => timeout,
+            },
+        };
+        let command_timeout = match self.command_timeout {
+            Some(timeout) => timeout,
+            None => match std::env::var("COMMAND_TIMEOUT") {
+                Ok(value) => {
+                    humantime::parse_duration(&value).context("Invalid COMMAND_TIMEOUT")?
+                }
+                Err(_) => timeout,
+            },
+        };
+        // The client of each session only has a single timeout, the proxy bounds the commands
+        let split_timeouts = session_timeout != command_timeout;
+        if split_timeouts {
+            log::info!(
+                "Sessions may take {} to be created, every further command {}",
+                format_duration(session_timeout),
+                format_duration(command_timeout)
+            );
+        }
+
+        let max_concurrent = match self.max_concurrent {
+            Some(max_concurrent) => Some(max_concurrent),
+            None => match std::env::var("MAX_CONCURRENT") {
+                Ok(value) => Some(value.parse::<usize>().context("Invalid MAX_CONCURRENT")?),
+                Err(_) => None,
+            },
+        };
+        if max_concurrent == Some(0) {
+            bail!("At least one session has to be allowed in flight");
+        }
+
+        let profile = match self.profile {
+            Some(profile) => Some(profile),
+            None => LoadProfile::from_env()?,
+        };
+        if profile.map_or(false, |profile| profile.total().is_zero()) {
+            bail!("The load profile does not contain any stage with a duration");
+        }
+
+        let rate = match self.rate {
+            Some(rate) => Some(rate),
+            None => match std::env::var("RATE") {
+                Ok(value) => Some(value.parse::<f64>().context("Invalid RATE")?),
+                Err(_) => None,
+            },
+        };
+        if rate.map_or(false, |rate| !(rate > 0.0 && rate.is_finite())) {
+            bail!("The arrival rate has to be a positive number of sessions per second");
+        }
+
+        let schedule = match self.schedule {
+            Some(schedule) => Some(schedule),
+            None => match std::env::var("REPLAY") {
+                Ok(path) => Some(Arc::new(Replay::load(&path)?) as Arc<dyn ArrivalSchedule>),
+                Err(_) => None,
+            },
+        };
+        if rate.is_some() && schedule.is_some() {
+            bail!("An arrival rate can not be combined with an arrival schedule");
+        }
+        if (rate.is_some() || schedule.is_some()) && profile.is_some() {
+            bail!("An arrival rate or schedule can not be combined with a load profile");
+        }
+
+        let capacity = match self.capacity {
+            Some(capacity) => Some(capacity),
+            None => CapacitySearch::from_env()?,
+        };
+        if let Some(capacity) = &capacity {
+            capacity.validate()?;
+            if profile.is_some() || rate.is_some() || schedule.is_some() {
+                bail!("A capacity search can not be combined with a load profile or arrival rate");
+            }
+        }
+
+        let abort = match self.abort {
+            Some(rule) => Some(rule),
+            None => AbortRule::from_env()?,
+        };
+        if let Some(rule) = &abort {
+            rule.validate()?;
+            if capacity.is_some() {
+                bail!(
+                    "A capacity search expects failures and can not be aborted on a failure rate"
+                );
+            }
+        }
+
+        let budget = match profile {
+            Some(profile) => match self.slo {
+                Some(slo) => Some(ErrorBudget::new(
+                    slo,
+                    profile.total(),
+                    self.abort_on_budget,
+                )?),
+                None => ErrorBudget::from_env(profile.total())?,
+            },
+            None => {
+                if capacity.is_none() && (self.slo.is_some() || std::env::var("SLO").is_ok()) {
+                    log::warn!("The SLO only applies to soak tests and load profiles");
+                }
+                None
+            }
+        };
+
+        let square_wave = match self.square_wave {
+            Some(square_wave) => Some(square_wave),
+            None => SquareWave::from_env()?,
+        };
+        if let Some(square_wave) = square_wave {
+            if square_wave.total().is_zero() {
+                bail!("The square wave needs a period and at least one cycle");
+            }
+            if square_wave.low >= self.count {
+                bail!("The low level of the square wave has to be below the session count");
+            }
+            if profile.is_some() || rate.is_some() || schedule.is_some() || capacity.is_some() {
+                bail!("A square wave can not be combined with a load profile, arrival rate or capacity search");
+            }
+        }
+
+        let iterations = std::env::var("ITERATIONS")
+            .unwrap_or("1".into())
+            .parse::<u64>()
+            .context("Invalid ITERATIONS")?;
+        let max_session_age = match self.max_session_age {
+            Some(max_session_age) => Some(max_session_age),
+            None => match std::env::var("MAX_SESSION_AGE") {
+                Ok(value) => {
+                    Some(humantime::parse_duration(&value).context("Invalid MAX_SESSION_AGE")?)
+                }
+                Err(_) => None,
+            },
+        };
+        if max_session_age.is_some() && iterations < 2 {
+            log::warn!("MAX_SESSION_AGE only applies to pooled sessions (ITERATIONS > 1)");
+        }
+
+        // Every browser version gets its own set of sessions
+        let targeting = Targeting::from_env()?;
+        let count = self.count * targeting.matrix_size();
+        let priority_classes = PriorityClasses::from_env()?;
+
+        let redactor = Redactor::new(endpoint);
+
+        if iterations > 1 {
+            log::info!(
+                "Running {} iterations on a pool of {} sessions against '{}'",
+                iterations,
+                count,
+                redactor.redact(endpoint)
+            );
+        } else {
+            log::info!(
+                "Running {} tests against '{}'",
+                count,
+                redactor.redact(endpoint)
+            );
+        }
+
+        if let Some(max_concurrent) = max_concurrent {
+            log::info!(
+                "At most {} sessions are in flight at a time",
+                max_concurrent
+            );
+        }
+
+        let breaker = CircuitBreaker::from_env(&redactor.redact(endpoint))?;
+
+        let grid = dialect::select(std::env::var("DIALECT").ok().as_deref(), endpoint).await?;
+
+        let warm_connections = match self.warm_connections {
+            Some(connections) => connections,
+            None => std::env::var("WARM_CONNECTIONS")
+                .unwrap_or("0".into())
+                .parse::<usize>()
+                .context("Invalid WARM_CONNECTIONS")?,
+        };
+        let local_addresses = if self.local_addresses.is_empty() {
+            std::env::var("LOCAL_ADDRESSES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(|address| address.parse::<IpAddr>())
+                .collect::<Result<Vec<_>, _>>()
+                .context("Invalid LOCAL_ADDRESSES")?
+        } else {
+            self.local_addresses
+        };
+        let teardown = Teardown::from_env(self.teardown)?;
+        let metrics = Arc::new(Metrics::new());
+        let session_timing = if self.session_timing || env_flag("SESSION_TIMING")? {
+            Some(Arc::new(SessionTiming::new(metrics.clone())))
+        } else {
+            None
+        };
+        let endpoint = route_through_proxy(
+            endpoint,
+            warm_connections,
+            &local_addresses,
+            Some(command_timeout).filter(|_| split_timeouts),
+            teardown.skip_quit,
+            session_timing.clone(),
+        )
+        .await?;
+
+        let invariants = invariants::parse_list(&std::env::var("INVARIANTS").unwrap_or_default())?;
+
+        let probe = match std::env::var("HEALTH_URL") {
+            Ok(url) => {
+                let interval = humantime::parse_duration(
+                    &std::env::var("HEALTH_INTERVAL").unwrap_or("5s".into()),
+                )
+                .context("Invalid HEALTH_INTERVAL")?;
+                Some(probe::HealthProbe::start(url, interval)?)
+            }
+            Err(_) => None,
+        };
+
+        let interval_log = match std::env::var("HDR_LOG") {
+            Ok(path) => {
+                let interval = humantime::parse_duration(
+                    &std::env::var("HDR_INTERVAL").unwrap_or("10s".into()),
+                )
+                .context("Invalid HDR_INTERVAL")?;
+                Some(IntervalLog::start(metrics.clone(), &path, interval)?)
+            }
+            Err(_) => None,
+        };
+
+        let event_log = self.events.or_else(|| std::env::var("EVENT_LOG").ok());
+        let events = match &event_log {
+            Some(path) => Some(EventLog::create(path)?),
+            None => None,
+        };
+        let tracer = self
+            .otlp
+            .or_else(|| std::env::var("OTLP_ENDPOINT").ok())
+            .map(|endpoint| Tracer::new(&endpoint))
+            .transpose()?;
+
+        let tui = self.tui || env_flag("TUI")?;
+        if tui && event_log.as_deref() == Some("-") {
+            bail!("The dashboard can not be combined with streaming events to stdout");
+        }
+        #[cfg(not(feature = "tui"))]
+        if tui {
+            bail!("This build does not include the dashboard (feature `tui`)");
+        }
+
+        let trickle = match std::env::var("TRICKLE_INTERVAL") {
+            Ok(interval) => {
+                Some(humantime::parse_duration(&interval).context("Invalid TRICKLE_INTERVAL")?)
+            }
+            Err(_) => None,
+        };
+
+        let monitor = if trickle.is_some() || std::env::var("ALERT_RULES").is_ok() {
+            let rules = match std::env::var("ALERT_RULES") {
+                Ok(rules) => monitor::parse_rules(&rules)?,
+                Err(_) => monitor::parse_rules(&format!(
+                    "streak >= {}",
+                    std::env::var("ALERT_AFTER").unwrap_or("3".into())
+                ))?,
+            };
+            let cooldown =
+                humantime::parse_duration(&std::env::var("ALERT_COOLDOWN").unwrap_or("15m".into()))
+                    .context("Invalid ALERT_COOLDOWN")?;
+            Some(Monitor::new(
+                rules,
+                cooldown,
+                std::env::var("WEBHOOK_URL").ok(),
+            )?)
+        } else {
+            None
+        };
+
+        let status_interval =
+            humantime::parse_duration(&std::env::var("STATUS_INTERVAL").unwrap_or("5s".into()))
+                .context("Invalid STATUS_INTERVAL")?;
+        let summary_interval = match std::env::var("SUMMARY_INTERVAL") {
+            Ok(interval) => {
+                Some(humantime::parse_duration(&interval).context("Invalid SUMMARY_INTERVAL")?)
+            }
+            Err(_) => None,
+        };
+
+        let mut selected = match &schedule {
+            Some(schedule) => (0..)
+                .take_while(|position| schedule.arrival(*position).is_some())
+                .filter_map(|position| schedule.scenario(position).map(str::to_owned))
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        let mix = ScenarioMix::from_env()?;
+        if let Some(mix) = &mix {
+            selected.extend(mix.names().map(str::to_owned));
+            mix.log();
+        }
+        let journeys = match ScenarioDir::from_env(self.scenario_dir)? {
+            Some(dir) => Some(dir.load()?),
+            None => None,
+        };
+        let scripts = journeys
+            .as_ref()
+            .map(Journeys::scenarios)
+            .unwrap_or_default();
+        let (scenario, alternatives) = builtin_scenarios(self.scenario, &selected, scripts).await?;
+
+        let annotations = Annotations::from_env()?;
+        let allure = match std::env::var("ALLURE_RESULTS") {
+            Ok(path) => Some(AllureResults::create(&path, &browser)?),
+            Err(_) => None,
+        };
+        let junit = self
+            .junit
+            .or_else(|| std::env::var("JUNIT_REPORT").ok())
+            .map(|path| JunitReport::new(&path))
+            .transpose()?;
+        let console = Console::from_env(self.console_log, &browser)?;
+        // Read once, so a missing profile fails the run rather than every test
+        let firefox_profile = match std::env::var("FIREFOX_PROFILE") {
+            Ok(path) if browser == "firefox" => {
+                // Geckodriver expects the profile as a base64 encoded zip archive
+                let archive = std::fs::read(&path)
+                    .with_context(|| format!("Failed to read Firefox profile '{}'", path))?;
+                Some(base64::encode(archive))
+            }
+            _ => None,
+        };
+        let viewports = ViewportJitter::from_env(self.viewport_jitter)?;
+        if let Some(viewports) = &viewports {
+            viewports.log();
+        }
+        let screenshots = match self
+            .screenshots
+            .or_else(|| std::env::var("SCREENSHOT_DIR").ok())
+        {
+            Some(directory) => {
+                std::fs::create_dir_all(&directory).with_context(|| {
+                    format!("Failed to create screenshot directory '{}'", directory)
+                })?;
+                Some(PathBuf::from(directory))
+            }
+            None => None,
+        };
+
+        let run = Arc::new(Run {
+            endpoint,
+            browser,
+            scenario,
+            alternatives,
+            mix,
+            journeys,
+            session_timeout,
+            command_timeout,
+            step_timeouts: StepTimeouts::from_env(
+                self.step_timeouts.step,
+                self.step_timeouts.navigation,
+                self.step_timeouts.lookup,
+            )?,
+            teardown,
+            hooks: reqwest::Client::builder()
+                .connect_timeout(command_timeout.min(HOOK_CONNECT_TIMEOUT))
+                .timeout(command_timeout)
+                .build()
+                .context("Failed to build the hook client")?,
+            console,
+            viewports,
+            firefox_profile,
+            chrome_user_data_dir: std::env::var("CHROME_USER_DATA_DIR").ok(),
+            iterations,
+            started: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            infrastructure_failures: AtomicU64::new(0),
+            renewals: AtomicU64::new(0),
+            session_retries: SessionRetries::from_env(self.session_retries)?,
+            session_timing,
+            reruns: match self.retries {
+                Some(retries) => retries,
+                None => std::env::var("RETRIES")
+                    .unwrap_or("0".into())
+                    .parse::<u32>()
+                    .context("Invalid RETRIES")?,
+            },
+            flaky: AtomicU64::new(0),
+            threshold: FailureThreshold::from_env(self.max_failures, self.max_failure_rate)?,
+            breaker,
+            spike: abort.map(FailureSpike::new),
+            max_session_age,
+            captured: if invariants.is_empty() {
+                None
+            } else {
+                Some(Mutex::new(Vec::new()))
+            },
+            metrics,
+            redactor,
+            events,
+            tracer,
+            #[cfg(feature = "tui")]
+            dashboard: if tui { Some(Dashboard::new()) } else { None },
+            annotations,
+            allure,
+            junit,
+            screenshots,
+            artifacts: Mutex::new(Vec::new()),
+            monitor,
+            targeting,
+            platforms: Breakdown::new("Platform"),
+            breakpoints: Breakdown::new("Viewport"),
+            versions: Breakdown::new("Browser version"),
+            priority_classes,
+            priorities: Breakdown::new("Priority"),
+            scenarios: Breakdown::new("Scenario"),
+            dialect: grid,
+            status_interval,
+            watchdog: Watchdog::from_env()?,
+            slots: max_concurrent.map(|permits| Arc::new(Semaphore::new(permits))),
+            profile,
+            stages: Breakdown::new("Stage"),
+            timeline: Timeline::new(Duration::from_secs(10 * 60)),
+            budget,
+            reactions: square_wave.map(|square_wave| Reactions::new(square_wave, count)),
+            interrupt: watch::channel(false).0,
+            launched: Instant::now(),
+        });
+        spawn_interrupt_handler(run.clone());
+
+        run.emit(Event::RunStarted {
+            endpoint: &run.redactor.redact(&run.endpoint),
+            browser: &run.browser,
+            scenario: run.scenario.name(),
+            count,
+            iterations,
+        });
+
+        let metrics_port = match self.metrics_port {
+            Some(port) => Some(port),
+            None => match std::env::var("METRICS_PORT") {
+                Ok(value) => Some(value.parse::<u16>().context("Invalid METRICS_PORT")?),
+                Err(_) => None,
+            },
+        };
+        if let Some(port) = metrics_port {
+            start_exporter(&run, port).await?;
+        }
+        #[cfg(feature = "tui")]
+        start_dashboard(&run)?;
+
+        let rollup = summary_interval.map(|interval| spawn_rollup(run.clone(), interval));
+
+        let modes = async {
+            let mut found = None;
+            if let Some(interval) = trickle {
+                log::info!(
+                    "Launching {} tests every {} until stopped",
+                    count,
+                    format_duration(interval)
+                );
+                run_trickle(&run, count, interval).await;
+            } else if let Some(profile) = profile {
+                run_staged(&run, count, profile).await?;
+            } else if let Some(capacity) = capacity {
+                found = Some(run_capacity_search(&run, count, capacity).await?);
+            } else if let Some(square_wave) = square_wave {
+                run_square_wave(&run, count, square_wave).await?;
+            } else if let Some(rate) = rate {
+                run_open_loop(&run, &ConstantRate::new(rate, count)).await?;
+            } else if let Some(schedule) = &schedule {
+                run_open_loop(&run, schedule.as_ref()).await?;
+            } else {
+                // A progress bar is only useful if it can redraw itself and nothing else owns stdout
+                let progress =
+                    !tui && event_log.as_deref() != Some("-") && std::io::stdout().is_terminal();
+                run_batch(&run, count, progress).await?;
+            }
+            Ok::<_, anyhow::Error>(found)
+        };
+
+        let grace_period = match self.grace_period {
+            Some(grace_period) => grace_period,
+            None => {
+                humantime::parse_duration(&std::env::var("GRACE_PERIOD").unwrap_or("30s".into()))
+                    .context("Invalid GRACE_PERIOD")?
+            }
+        };
+        let found = tokio::select! {
+            found = modes => found?,
+            _ = async {
+                run.interrupted().await;
+                sleep(grace_period).await;
+            } => {
+                log::warn!(
+                    "{} tests did not quit their sessions within the grace period of {}",
+                    in_flight(&run),
+                    format_duration(grace_period)
+                );
+                None
+            }
+        };
+
+        if let Some(rollup) = rollup {
+            rollup.abort();
+        }
+
+        if let Some(probe) = probe {
+            probe.stop();
+        }
+
+        if let Some(interval_log) = interval_log {
+            interval_log.finish().await?;
+        }
+
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = &run.dashboard {
+            dashboard.stop();
+        }
+
+        let failed = run.failed.load(Ordering::SeqCst);
+        let total = run.completed.load(Ordering::SeqCst);
+
+        let aborted = run.spike.as_ref().map_or(false, FailureSpike::tripped);
+        if aborted {
+            log::info!(
+                "Run aborted as the failure rate spiked. {} / {} finished tests succeeded.",
+                total - failed,
+                total
+            );
+        } else if run.is_interrupted() {
+            log::info!(
+                "Run interrupted. {} / {} finished tests succeeded.",
+                total - failed,
+                total
+            );
+        } else {
+            log::info!(
+                "All tests finished. {} / {} succeeded.",
+                total - failed,
+                total
+            );
+        }
+
+        let renewals = run.renewals.load(Ordering::SeqCst);
+        if renewals > 0 {
+            log::info!(
+                "Renewed {} pooled sessions that reached their maximum age.",
+                renewals
+            );
+        }
+
+        let flaky = run.flaky.load(Ordering::SeqCst);
+        if flaky > 0 {
+            log::info!(
+                "{} tests failed at first but passed when re-run and are flaky.",
+                flaky
+            );
+        }
+
+        run.metrics.log_summary();
+        if let Some(timing) = &run.session_timing {
+            timing.log_summary();
+        }
+        run.session_retries.log_summary();
+        if let Some(breaker) = &run.breaker {
+            breaker.log_summary();
+        }
+        run.platforms.log_summary();
+        run.breakpoints.log_summary();
+        run.versions.log_summary();
+        run.priorities.log_summary();
+        run.scenarios.log_summary();
+        run.stages.log_summary();
+        run.timeline.log_summary();
+        if let Some(budget) = &run.budget {
+            budget.log_summary();
+        }
+        if let Some(reactions) = &run.reactions {
+            reactions.log_summary();
+        }
+        run.emit(Event::RunFinished { total, failed });
+
+        if let Some(annotations) = &run.annotations {
+            annotations.run_finished(total, failed);
+        }
+
+        if let Some(junit) = &run.junit {
+            junit.write()?;
+            run.artifacts.lock().unwrap().push(junit.path().to_owned());
+        }
+        if let Some(tracer) = &run.tracer {
+            tracer.flush().await;
+        }
+
+        if let Ok(path) = std::env::var("MANIFEST") {
+            // Events streamed to stdout did not end up in a file
+            let artifacts = std::env::var("HDR_LOG")
+                .ok()
+                .into_iter()
+                .chain(event_log.filter(|path| path != "-"))
+                .chain(run.artifacts.lock().unwrap().drain(..))
+                .collect::<Vec<_>>();
+            let artifacts = artifacts.iter().map(String::as_str).collect::<Vec<_>>();
+            manifest::write(&path, &artifacts)?;
+        }
+
+        let captured = match &run.captured {
+            Some(captured) => captured.lock().unwrap().clone(),
+            None => Vec::new(),
+        };
+        let mut violated = 0;
+        for invariant in invariants.iter() {
+            match invariant.check(&captured) {
+                Ok(_) => log::info!("Invariant {} holds.", invariant),
+                Err(e) => {
+                    log::error!("Invariant {} violated: {}", invariant, e);
+                    violated += 1;
+                }
+            }
+        }
+
+        // An aborted run was interrupted as well, but failed rather than being stopped by the user
+        if run.is_interrupted() && !aborted {
+            return Ok(Outcome::Interrupted);
+        }
+
+        // Failures beyond the capacity are expected, a search fails only if nothing meets the
+        // objectives
+        if let Some(capacity) = found {
+            return Ok(if capacity == 0 || violated > 0 {
+                Outcome::Failed
+            } else {
+                Outcome::Passed
+            });
+        }
+
+        // Outcomes tell CI pipelines whose problem a failed run is
+        let infrastructure_failures = run.infrastructure_failures.load(Ordering::SeqCst);
+        let slo_violated = run.monitor.as_ref().map_or(false, Monitor::has_fired);
+        let tolerated = !aborted && run.threshold.tolerates(failed, total);
+        if !tolerated && infrastructure_failures * 2 > failed {
+            log::error!(
+                "{} of {} failures were caused by the grid.",
+                infrastructure_failures,
+                failed
+            );
+            Ok(Outcome::GridFailed)
+        } else if !tolerated || violated > 0 {
+            Ok(Outcome::Failed)
+        } else if slo_violated {
+            log::error!("The tests passed but alert rules fired during the run.");
+            Ok(Outcome::AlertsFired)
+        } else {
+            Ok(Outcome::Passed)
+        }
+    }
+}
+
+/// Starts the traffic proxy if any rules are configured, connections should be pre-opened or sent
+/// from specific local addresses, commands have a timeout of their own, sessions are left to the
+/// grid instead of being quit, or session creations are timed, and returns the endpoint tests
+/// should use
+#[cfg(feature = "proxy")]
+async fn route_through_proxy(
+    endpoint: &str,
+    warm_connections: usize,
+    local_addresses: &[IpAddr],
+    command_timeout: Option<Duration>,
+    skip_quit: bool,
+    session_timing: Option<Arc<SessionTiming>>,
+) -> Result<String> {
+    let mut proxy_rules = Vec::new();
+    if let Ok(path) = std::env::var("PROXY_RULES") {
+        proxy_rules.extend(proxy::load_rules(&path)?);
+    }
+    if let Ok(spec) = std::env::var("LATENCY") {
+        proxy_rules.extend(proxy::latency_rules(&spec)?);
+    }
+    if skip_quit {
+        proxy_rules.push(proxy::detach_rule());
+    }
+    let payload_shape = match std::env::var("SESSION_PAYLOAD") {
+        Ok(list) => Some(
+            list.parse::<proxy::PayloadShape>()
+                .context("Invalid SESSION_PAYLOAD")?,
+        ),
+        Err(_) => None,
+    };
+
+    if proxy_rules.is_empty()
+        && warm_connections == 0
+        && local_addresses.is_empty()
+        && command_timeout.is_none()
+        && session_timing.is_none()
+        && payload_shape.is_none()
+    {
+        return Ok(endpoint.to_owned());
+    }
+
+    let address = proxy::start(
+        endpoint,
+        proxy_rules,
+        warm_connections,
+        local_addresses,
+        command_timeout,
+        session_timing,
+        payload_shape,
+    )
+    .await?;
+    log::info!("Routing WebDriver traffic through proxy at {}", address);
+
+    Ok(address)
+}
+
+#[cfg(not(feature = "proxy"))]
+async fn route_through_proxy(
+    endpoint: &str,
+    warm_connections: usize,
+    local_addresses: &[IpAddr],
+    command_timeout: Option<Duration>,
+    skip_quit: bool,
+    session_timing: Option<Arc<SessionTiming>>,
+) -> Result<String> {
+    if std::env::var("PROXY_RULES").is_ok() || std::env::var("LATENCY").is_ok() {
+        bail!("This build does not include the traffic proxy (feature `proxy`)");
+    }
+    if std::env::var("SESSION_PAYLOAD").is_ok() {
+        bail!("Reshaping the New Session payload needs the traffic proxy (feature `proxy`)");
+    }
+    if warm_connections > 0 {
+        bail!("Pre-opening connections needs the traffic proxy (feature `proxy`)");
+    }
+    if !local_addresses.is_empty() {
+        bail!("Binding to local addresses needs the traffic proxy (feature `proxy`)");
+    }
+    if command_timeout.is_some() {
+        bail!("Separate session and command timeouts need the traffic proxy (feature `proxy`)");
+    }
+    if skip_quit {
+        bail!("Skipping the quit of sessions needs the traffic proxy (feature `proxy`)");
+    }
+    if session_timing.is_some() {
+        bail!("Timing the phases of session creations needs the traffic proxy (feature `proxy`)");
+    }
+
+    Ok(endpoint.to_owned())
+}
+
+/// Publishes the metrics and counters of the run for Prometheus
+#[cfg(feature = "prometheus")]
+async fn start_exporter(run: &Arc<Run>, port: u16) -> Result<()> {
+    let scraped = run.clone();
+    let address = exporter::start(port, run.metrics.clone(), move || exporter::Counters {
+        started: scraped.started.load(Ordering::Relaxed),
+        completed: scraped.completed.load(Ordering::Relaxed),
+        failed: scraped.failed.load(Ordering::Relaxed),
+        infrastructure_failures: scraped.infrastructure_failures.load(Ordering::Relaxed),
+        flaky: scraped.flaky.load(Ordering::Relaxed),
+        session_retries: scraped.session_retries.retried(),
+    })
+    .await?;
+    log::info!("Publishing metrics on http://{}/metrics", address);
+
+    Ok(())
+}
+
+#[cfg(not(feature = "prometheus"))]
+async fn start_exporter(_run: &Arc<Run>, _port: u16) -> Result<()> {
+    bail!("This build does not include the metrics endpoint (feature `prometheus`)");
+}
+
+/// Takes over the terminal with the live dashboard if the run has one
+#[cfg(feature = "tui")]
+fn start_dashboard(run: &Arc<Run>) -> Result<()> {
+    if let Some(dashboard) = &run.dashboard {
+        let observed = run.clone();
+        let interrupted = run.clone();
+        dashboard.start(
+            move || dashboard::Counters {
+                started: observed.started.load(Ordering::Relaxed),
+                completed: observed.completed.load(Ordering::Relaxed),
+                failed: observed.failed.load(Ordering::Relaxed),
+                infrastructure_failures: observed.infrastructure_failures.load(Ordering::Relaxed),
+                circuit: observed
+                    .breaker
+                    .as_ref()
+                    .map(|breaker| breaker.state().label()),
+            },
+            move || interrupted.interrupt(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Starts the demo server if `DEMO_SERVER` is set and returns the URL browsers reach it at
+#[cfg(feature = "demo-server")]
+async fn start_demo_server(page: &str) -> Result<Option<String>> {
+    let bind = match std::env::var("DEMO_SERVER") {
+        Ok(bind) => bind,
+        Err(_) => return Ok(None),
+    };
+
+    let address = demo::start(&bind, page.to_owned()).await?;
+    let base = std::env::var("DEMO_URL").unwrap_or_else(|_| format!("http://{}", address));
+    log::info!("Serving the demo application on {} as '{}'", address, base);
+
+    Ok(Some(base.trim_end_matches('/').to_owned()))
+}
+
+#[cfg(not(feature = "demo-server"))]
+async fn start_demo_server(_page: &str) -> Result<Option<String>> {
+    if std::env::var("DEMO_SERVER").is_ok() {
+        bail!("This build does not include the demo server (feature `demo-server`)");
+    }
+
+    Ok(None)
+}
+
+/// Scenario selected by `SCENARIO` unless the run brings its own, and the built-in scenarios
+/// the arrival schedule or the mix selects by name. Scripted journeys take precedence over built-in
+/// scenarios of the same name. Starts the demo server they run against if requested.
+async fn builtin_scenarios(
+    own: Option<Arc<dyn Scenario>>,
+    selected: &BTreeSet<String>,
+    scripts: HashMap<String, Arc<dyn Scenario>>,
+) -> Result<(Arc<dyn Scenario>, HashMap<String, Arc<dyn Scenario>>)> {
+    if let Some(scenario) = own.as_ref().filter(|_| selected.is_empty()) {
+        return Ok((scenario.clone(), HashMap::new()));
+    }
+
+    let complexity = site::Complexity::from_env()?;
+    let html = site::generate(&complexity);
+    let demo_base = start_demo_server(&html).await?;
+
+    // Faults are requested from the demo server through the query of the first page
+    let query = match std::env::var("DEMO_FAULT") {
+        Ok(fault) if demo_base.is_some() => format!("?{}", fault),
+        Ok(_) => bail!("Injecting faults requires the demo server (DEMO_SERVER)"),
+        Err(_) => String::new(),
+    };
+
+    let builtin = |name: &str| -> Result<Arc<dyn Scenario>> {
+        match scripts.get(name) {
+            Some(script) => Ok(script.clone()),
+            None => builtin_scenario(name, &html, demo_base.as_deref(), &query),
+        }
+    };
+
+    let scenario = match own {
+        Some(scenario) => scenario,
+        None => builtin(&std::env::var("SCENARIO").unwrap_or("demo".into()))?,
+    };
+
+    let mut alternatives = HashMap::new();
+    for name in selected.iter().filter(|name| *name != scenario.name()) {
+        let alternative = builtin(name)
+            .with_context(|| format!("Selected scenario '{}' is not available", name))?;
+        alternatives.insert(name.clone(), alternative);
+    }
+
+    Ok((scenario, alternatives))
+}
+
+/// Built-in scenario with the given name
+fn builtin_scenario(
+    name: &str,
+    html: &str,
+    demo_base: Option<&str>,
+    query: &str,
+) -> Result<Arc<dyn Scenario>> {
+    let scenario: Arc<dyn Scenario> = match name {
+        "demo" => Arc::new(DemoPage {
+            page: match demo_base {
+                Some(base) => format!("{}/{}", base, query),
+                None => site::data_url(html),
+            },
+        }),
+        "journey" => match demo_base {
+            Some(base) => Arc::new(journey::Journey {
+                entry: format!("{}/items{}", base, query),
+            }),
+            None => bail!("The journey scenario requires the demo server (DEMO_SERVER)"),
+        },
+        "echo" => match demo_base {
+            Some(base) => Arc::new(echo::Echo {
+                page: format!("{}/echo{}", base, query),
+                checks: echo::parse_checks(&std::env::var("ECHO_EXPECT").unwrap_or_default())?,
+            }),
+            None => bail!("The echo scenario requires the demo server (DEMO_SERVER)"),
+        },
+        other => bail!("Unknown scenario '{}'", other),
+    };
+
+    Ok(scenario)
+}
+
+/// Stops the run on Ctrl+C (or Ctrl+Break in Windows consoles), a second one exits right away
+fn spawn_interrupt_handler(run: Arc<Run>) -> JoinHandle<()> {
+    spawn(async move {
+        #[cfg(windows)]
+        let mut ctrl_break = tokio::signal::windows::ctrl_break().ok();
+
+        loop {
+            #[cfg(windows)]
+            let received = tokio::select! {
+                received = tokio::signal::ctrl_c() => received.is_ok(),
+                Some(_) = async { ctrl_break.as_mut()?.recv().await } => true,
+            };
+            #[cfg(not(windows))]
+            let received = tokio::signal::ctrl_c().await.is_ok();
+            if !received {
+                break;
+            }
+
+            if run.interrupt() {
+                log::error!("Interrupted again, exiting without quitting the remaining sessions");
+                std::process::exit(Outcome::Interrupted.exit_code());
+            }
+
+            log::warn!(
+                "Interrupted, quitting the sessions of {} tests in flight (Ctrl+C again to exit right away)",
+                in_flight(&run)
+            );
+        }
+    })
+}
+
+/// Periodically logs a one-line summary of what happened since the previous one
+fn spawn_rollup(run: Arc<Run>, interval: Duration) -> JoinHandle<()> {
+    spawn(async move {
+        let mut previous = (0, 0, 0);
+        let mut previous_histogram = None;
+
+        loop {
+            sleep(interval).await;
+
+            let current = (
+                run.started.load(Ordering::Relaxed),
+                run.completed.load(Ordering::Relaxed),
+                run.failed.load(Ordering::Relaxed),
+            );
+            let (started, completed, failed) = (
+                current.0 - previous.0,
+                current.1 - previous.1,
+                current.2 - previous.2,
+            );
+
+            let histogram = run.metrics.snapshot(metrics::TOTAL);
+            let p95 = histogram
+                .as_ref()
+                .and_then(|h| metrics::quantile_between(previous_histogram.as_ref(), h, 0.95))
+                .map(metrics::format_ms)
+                .unwrap_or_else(|| "-".into());
+
+            let queued = match run.dialect.queue_size().await {
+                Some(size) => format!(", {} queued on the grid", size),
+                None => String::new(),
+            };
+
+            log::info!(
+                "Last {}: {} started, {} passed, {} failed, {} in flight, p95 {}{}",
+                format_duration(interval),
+                started,
+                completed.saturating_sub(failed),
+                failed,
+                current.0.saturating_sub(current.1),
+                p95,
+                queued
+            );
+
+            previous = current;
+            previous_histogram = histogram;
+        }
+    })
+}
+
+/// Launches `count` tests (or session pools) at once and waits for them to finish
+async fn run_batch(run: &Arc<Run>, count: u64, progress: bool) -> Result<()> {
+    let progress = if progress {
+        Some(spawn_progress(run.clone(), count * run.iterations))
+    } else {
+        None
+    };
+    let mut handles = Vec::new();
+    let order = run.priority_classes.launch_order(0..count);
+
+    for (position, id) in order.into_iter().enumerate() {
+        // Wait a tiny bit to stagger the requests, higher priority classes go first
+        if position > 0 {
+            sleep(Duration::from_millis(25)).await;
+        }
+
+        let permit = match &run.slots {
+            Some(slots) => Some(slots.clone().acquire_owned().await?),
+            None => None,
+        };
+        if run.is_interrupted() {
+            break;
+        }
+
+        let run = run.clone();
+        let handle = spawn(async move {
+            let _permit = permit;
+
+            // Pooled sessions report each iteration on their own
+            if run.iterations > 1 {
+                return run_pooled(id, &run).await;
+            }
+
+            run_single(id.to_string(), &run, id).await
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles.into_iter() {
+        handle.await?.ok();
+    }
+    if let Some(progress) = progress {
+        progress.await?;
+    }
+
+    Ok(())
+}
+
+/// Shows a progress bar of the finished tests with an estimate of the remaining time until all
+/// `total` tests finished. The log lines of every test are muted meanwhile, warnings still show.
+fn spawn_progress(run: Arc<Run>, total: u64) -> JoinHandle<()> {
+    let bar = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stdout());
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{elapsed_precise} [{wide_bar}] {pos}/{len} tests, {msg}, ETA {eta}",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+
+    let level = log::max_level();
+    log::set_max_level(level.min(log::LevelFilter::Warn));
+
+    spawn(async move {
+        loop {
+            let completed = run.completed.load(Ordering::Relaxed);
+            bar.set_position(completed);
+            bar.set_message(format!("{} failed", run.failed.load(Ordering::Relaxed)));
+            if completed >= total || run.is_interrupted() {
+                break;
+            }
+
+            sleep(Duration::from_millis(250)).await;
+        }
+
+        bar.finish();
+        log::set_max_level(level);
+    })
+}
+
+/// Runs `users` virtual users following the load profile, each running one test after another
+async fn run_staged(run: &Arc<Run>, users: u64, profile: LoadProfile) -> Result<()> {
+    log::info!(
+        "Ramping up to {} sessions over {}, holding them for {} and ramping down over {}",
+        users,
+        format_duration(profile.ramp_up),
+        format_duration(profile.steady),
+        format_duration(profile.ramp_down)
+    );
+
+    let next_id = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
+
+    for user in run.priority_classes.launch_order(0..users) {
+        let (join, leave) = profile.window(user, users);
+        let (run, next_id) = (run.clone(), next_id.clone());
+
+        handles.push(spawn(async move {
+            if !run.pause(join).await {
+                return Some(());
+            }
+
+            while run.launched.elapsed() < leave && !run.budget_exhausted() && !run.is_interrupted()
+            {
+                let _permit = match &run.slots {
+                    Some(slots) => Some(slots.clone().acquire_owned().await.ok()?),
+                    None => None,
+                };
+
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                run_single(id.to_string(), &run, user).await.ok();
+            }
+
+            Some(())
+        }));
+    }
+
+    // Stage boundaries are logged as they pass, so they can be matched with the grid's metrics
+    for (boundary, message) in [
+        (profile.ramp_up, "Ramp-up finished, holding steady"),
+        (
+            profile.ramp_up + profile.steady,
+            "Steady state finished, ramping down",
+        ),
+    ] {
+        if let Some(remaining) = boundary.checked_sub(run.launched.elapsed()) {
+            if !run.pause(remaining).await {
+                break;
+            }
+            log::info!("{} ({} sessions in flight)", message, in_flight(run));
+        }
+    }
+
+    for handle in handles.into_iter() {
+        handle.await?;
+    }
+
+    Ok(())
+}
+
+/// Binary-searches the highest number of virtual users up to `users` that meets the objectives,
+/// keeping each level busy for one probe round
+async fn run_capacity_search(run: &Arc<Run>, users: u64, config: CapacitySearch) -> Result<u64> {
+    log::info!(
+        "Searching the capacity between 1 and {} sessions with probe rounds of {}",
+        users,
+        format_duration(config.probe)
+    );
+
+    let mut search = Search::new(config, users);
+    let next_id = Arc::new(AtomicU64::new(0));
+
+    while let Some(level) = search.next() {
+        let completed = run.completed.load(Ordering::SeqCst);
+        let failed = run.failed.load(Ordering::SeqCst);
+        let previous = run.metrics.snapshot(metrics::TOTAL);
+
+        let deadline = Instant::now() + config.probe;
+        let mut handles = Vec::new();
+        for user in run.priority_classes.launch_order(0..level) {
+            let (run, next_id) = (run.clone(), next_id.clone());
+            handles.push(spawn(async move {
+                while Instant::now() < deadline && !run.is_interrupted() {
+                    let _permit = match &run.slots {
+                        Some(slots) => Some(slots.clone().acquire_owned().await.ok()?),
+                        None => None,
+                    };
+
+                    let id = next_id.fetch_add(1, Ordering::Relaxed);
+                    run_single(id.to_string(), &run, user).await.ok();
+                }
+
+                Some(())
+            }));
+        }
+        for handle in handles.into_iter() {
+            handle.await?;
+        }
+        if run.is_interrupted() {
+            break;
+        }
+
+        // Tests still running at the deadline count towards the round they started in
+        let completed = run.completed.load(Ordering::SeqCst) - completed;
+        let failed = run.failed.load(Ordering::SeqCst) - failed;
+        let p95 = run
+            .metrics
+            .snapshot(metrics::TOTAL)
+            .and_then(|current| metrics::quantile_between(previous.as_ref(), &current, 0.95));
+        search.record(Round {
+            users: level,
+            passed: completed - failed,
+            failed,
+            p95,
+        });
+    }
+
+    Ok(search.log_summary(users))
+}
+
+/// Runs `users` virtual users of which only the ones within the current level of the square wave
+/// are active, logging each transition as it passes
+async fn run_square_wave(run: &Arc<Run>, users: u64, square_wave: SquareWave) -> Result<()> {
+    log::info!(
+        "Alternating between {} and {} sessions every {} for {} cycles",
+        users,
+        square_wave.low,
+        format_duration(square_wave.period / 2),
+        square_wave.cycles
+    );
+
+    let next_id = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
+
+    for (position, user) in run
+        .priority_classes
+        .launch_order(0..users)
+        .into_iter()
+        .enumerate()
+    {
+        let (run, next_id) = (run.clone(), next_id.clone());
+
+        handles.push(spawn(async move {
+            loop {
+                let elapsed = run.launched.elapsed();
+                if elapsed >= square_wave.total() || run.is_interrupted() {
+                    break;
+                }
+
+                if position as u64 >= square_wave.level(elapsed, users) {
+                    run.pause(square_wave.next_transition(elapsed) - elapsed)
+                        .await;
+                    continue;
+                }
+
+                let _permit = match &run.slots {
+                    Some(slots) => Some(slots.clone().acquire_owned().await.ok()?),
+                    None => None,
+                };
+
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                run_single(id.to_string(), &run, user).await.ok();
+            }
+
+            Some(())
+        }));
+    }
+
+    for (edge, rising) in square_wave.transitions().skip(1) {
+        if let Some(remaining) = edge.checked_sub(run.launched.elapsed()) {
+            if !run.pause(remaining).await {
+                break;
+            }
+            log::info!(
+                "Square wave {} ({} sessions in flight)",
+                if rising { "rising" } else { "falling" },
+                in_flight(run)
+            );
+        }
+    }
+
+    for handle in handles.into_iter() {
+        handle.await?;
+    }
+
+    Ok(())
+}
+
+/// Launches tests at the offsets of the schedule, independent of how quickly the grid handles them
+async fn run_open_loop(run: &Arc<Run>, schedule: &dyn ArrivalSchedule) -> Result<()> {
+    let arrivals = (0..)
+        .map_while(|position| {
+            let scenario = schedule.scenario(position).map(str::to_owned);
+            schedule.arrival(position).map(|offset| (offset, scenario))
+        })
+        .collect::<Vec<_>>();
+    let count = arrivals.len() as u64;
+    let span = arrivals
+        .last()
+        .map(|(offset, _)| *offset)
+        .unwrap_or_default();
+
+    log::info!(
+        "Launching {} tests over {}",
+        count,
+        format_duration(Duration::from_millis(span.as_millis() as u64))
+    );
+
+    let start = tokio::time::Instant::now();
+    let mut handles = Vec::new();
+
+    for ((arrival, scenario), id) in arrivals
+        .into_iter()
+        .zip(run.priority_classes.launch_order(0..count))
+    {
+        let scheduled = start + arrival;
+        tokio::select! {
+            _ = sleep_until(scheduled) => {}
+            _ = run.interrupted() => break,
+        }
+
+        let run = run.clone();
+        handles.push(spawn(async move {
+            let _permit = match &run.slots {
+                Some(slots) => Some(slots.clone().acquire_owned().await?),
+                None => None,
+            };
+
+            // Tests only start late if the concurrency limit or the runner itself can't keep up
+            run.metrics
+                .record(metrics::ARRIVAL_LAG, scheduled.elapsed());
+            run_selected(id.to_string(), &run, id, scenario.as_deref()).await
+        }));
+    }
+
+    let launching = start.elapsed();
+    for handle in handles.into_iter() {
+        handle.await?.ok();
+    }
+
+    log::info!(
+        "Launched {} tests in {} against a schedule of {}",
+        count,
+        format_duration(Duration::from_millis(launching.as_millis() as u64)),
+        format_duration(Duration::from_millis(span.as_millis() as u64))
+    );
+
+    Ok(())
+}
+
+fn in_flight(run: &Run) -> u64 {
+    run.started
+        .load(Ordering::Relaxed)
+        .saturating_sub(run.completed.load(Ordering::Relaxed))
+}
+
+/// Keeps launching `count` tests every `interval`, never returns
+async fn run_trickle(run: &Arc<Run>, count: u64, interval: Duration) {
+    let mut next_id = 0u64;
+
+    loop {
+        for id in run.priority_classes.launch_order(next_id..next_id + count) {
+            let run = run.clone();
+            spawn(async move {
+                let _permit = match &run.slots {
+                    Some(slots) => Some(slots.clone().acquire_owned().await.ok()?),
+                    None => None,
+                };
+
+                run_single(id.to_string(), &run, id).await.ok()
+            });
+        }
+
+        next_id += count;
+        if !run.pause(interval).await {
+            break;
+        }
+
+        if let Some(journeys) = &run.journeys {
+            match journeys.reload() {
+                Ok(true) => log::info!(
+                    "Reloaded the journeys, now at version {}",
+                    journeys.version()
+                ),
+                Ok(false) => {}
+                Err(e) => log::warn!(
+                    "Failed to reload the journeys, keeping the last version: {:#}",
+                    e
+                ),
+            }
+        }
+    }
+
+    // The tests are not awaited individually
+    while in_flight(run) > 0 {
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Runs a test on a fresh session and reports its outcome
+async fn run_single(test: String, run: &Run, index: u64) -> Result<()> {
+    run_selected(test, run, index, None).await
+}
+
+/// Runs a test with the scenario selected by name instead of the one of the run
+async fn run_selected(test: String, run: &Run, index: u64, scenario: Option<&str>) -> Result<()> {
+    let scenario = scenario.or_else(|| Some(run.mix.as_ref()?.scenario(index)));
+    run.emit(Event::SessionScheduled { test: &test });
+    run.started.fetch_add(1, Ordering::Relaxed);
+
+    let mut attempt = 1;
+    loop {
+        let start = Instant::now();
+        let mut variables = Variables::new();
+        if let Some(scenario) = scenario {
+            variables.set("scenario", scenario);
+        }
+        if let Some(journeys) = &run.journeys {
+            variables.set("scenario_version", journeys.version());
+        }
+        if let Some(priority) = run.priority_classes.class(index) {
+            variables.set("priority", &priority.label);
+        }
+        if run.reruns > 0 {
+            variables.set("attempts", attempt.to_string());
+        }
+        let result = run_test(run, &test, &mut variables, index).await;
+        let duration = Instant::now() - start;
+
+        match result {
+            Err(e) if attempt <= run.reruns && !run.is_interrupted() => {
+                log::info!(
+                    "Test #{} failed on attempt {} of {}, re-running it: {}",
+                    test,
+                    attempt,
+                    run.reruns + 1,
+                    run.redactor.redact(&format!("{:#}", e))
+                );
+                attempt += 1;
+            }
+            result => {
+                run.completed.fetch_add(1, Ordering::Relaxed);
+                if result.is_ok() && attempt > 1 {
+                    run.flaky.fetch_add(1, Ordering::Relaxed);
+                }
+
+                // Report the result (and duration) of the last attempt
+                report(run, &test, &result, duration, &variables);
+                run.capture(variables);
+                return result;
+            }
+        }
+    }
+}
+
+/// Logs and records the outcome of a single test
+fn report(run: &Run, test: &str, result: &Result<()>, duration: Duration, variables: &Variables) {
+    let error = match result {
+        Ok(_) => {
+            match variables.get("attempts") {
+                Ok(attempts) if attempts != "1" => log::info!(
+                    "Test #{} finished in {} on attempt {} and is flaky.",
+                    test,
+                    format_duration(duration),
+                    attempts
+                ),
+                _ => log::info!("Test #{} finished in {}.", test, format_duration(duration)),
+            }
+            run.metrics.record(metrics::TOTAL, duration);
+            None
+        }
+        Err(e) => {
+            let error = run.redactor.redact(&format!("{:#}", e));
+            log::info!("Test #{} failed: {}", test, error);
+            run.failed.fetch_add(1, Ordering::Relaxed);
+            if e.downcast_ref::<InfrastructureFailure>().is_some() {
+                run.infrastructure_failures.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(error)
+        }
+    };
+
+    if let Some(monitor) = &run.monitor {
+        monitor.record(result.is_ok(), duration);
+    }
+    if let Some(spike) = &run.spike {
+        if spike.record(result.is_ok()) {
+            run.interrupt();
+        }
+    }
+
+    // Tests that did not even get a session can not be attributed to a platform
+    if let Ok(platform) = variables.get("platform") {
+        run.platforms.record(platform, result.is_ok());
+    }
+    if let Ok(version) = variables.get("browser_version") {
+        run.versions.record(version, result.is_ok());
+    }
+    if let Ok(breakpoint) = variables.get("breakpoint") {
+        run.breakpoints.record(breakpoint, result.is_ok());
+    }
+    if let Ok(priority) = variables.get("priority") {
+        run.priorities.record(priority, result.is_ok());
+    }
+    run.scenarios.record(
+        variables.get("scenario").unwrap_or(run.scenario.name()),
+        result.is_ok(),
+    );
+    run.timeline.record(run.launched.elapsed(), result.is_ok());
+    if let Some(budget) = &run.budget {
+        budget.record(run.launched.elapsed(), result.is_ok());
+    }
+    if let Some(profile) = &run.profile {
+        let started = run.launched.elapsed().saturating_sub(duration);
+        run.stages
+            .record(profile.stage(started).label(), result.is_ok());
+    }
+
+    if let Some(annotations) = &run.annotations {
+        let step = variables.get("failed_step").ok();
+        annotations.test_finished(test, error.as_deref(), step, duration);
+    }
+
+    if let Some(allure) = &run.allure {
+        let status = match result {
+            Ok(_) => allure::Status::Passed,
+            Err(e) if e.downcast_ref::<InfrastructureFailure>().is_some() => allure::Status::Broken,
+            Err(_) => allure::Status::Failed,
+        };
+        let (browser, scenario) = cell(run, variables);
+        let written = allure.write(
+            test,
+            (&browser, &scenario),
+            status,
+            error.as_deref(),
+            duration,
+            variables,
+        );
+        run.artifacts.lock().unwrap().extend(written);
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = &run.dashboard {
+        dashboard.record(test, duration, error.as_deref());
+    }
+
+    if let Some(junit) = &run.junit {
+        let (browser, scenario) = cell(run, variables);
+        junit.record(
+            test,
+            (&browser, &scenario),
+            duration,
+            variables,
+            error.as_deref(),
+        );
+    }
+
+    run.emit(Event::SessionFinished {
+        test,
+        session_id: variables.get("session_id").ok(),
+        success: result.is_ok(),
+        duration_ms: duration.as_millis() as u64,
+        error,
+        session_attempts: variables
+            .get("session_attempts")
+            .ok()
+            .and_then(|attempts| attempts.parse().ok()),
+        attempts: variables
+            .get("attempts")
+            .ok()
+            .and_then(|attempts| attempts.parse().ok()),
+        browser_version: variables.get("browser_version").ok(),
+        scenario: variables.get("scenario").ok(),
+        scenario_version: variables.get("scenario_version").ok(),
+        screenshot: variables.get("screenshot").ok(),
+        page_source: variables.get("page_source").ok(),
+    });
+}
+
+/// Browser with the version its session reported and scenario of a test, by which reports group
+/// the results into a grid
+fn cell(run: &Run, variables: &Variables) -> (String, String) {
+    let browser = match variables.get("browser_version") {
+        Ok(version) => format!("{} {}", run.browser, version),
+        Err(_) => run.browser.clone(),
+    };
+    let scenario = variables.get("scenario").unwrap_or(run.scenario.name());
+
+    (browser, scenario.to_owned())
+}
+
+async fn run_test(run: &Run, test: &str, variables: &mut Variables, index: u64) -> Result<()> {
+    let mut driver = create_driver(run, test, index, variables)
+        .await
+        .context(InfrastructureFailure)?;
+    let session_id = driver.session_id().to_string();
+    variables.set("session_id", &session_id);
+    variables.set("platform", targeting::platform(&driver));
+    variables.set("browser_version", targeting::browser_version(&driver));
+    run.emit(Event::SessionStarted {
+        test,
+        session_id: &session_id,
+    });
+    if let Err(e) = resize_viewport(run, &driver, test, variables).await {
+        tear_down(run, driver).await;
+        bail!("{} failed due to {}", session_id, e);
+    }
+
+    let steps = Steps::new(&run.metrics, run.events.as_ref(), test, run.watchdog)
+        .traced(run.tracer.as_ref())
+        .timeouts(run.step_timeouts)
+        .console(run.console);
+    let result = run_test_content(&mut driver, run, variables, steps).await;
+    collect_console(run, &driver, test, variables).await;
+    if let Err(e) = result {
+        capture_failure(run, &driver, test, variables).await;
+        tear_down(run, driver).await;
+        bail!("{} failed due to {}", session_id, e);
+    } else {
+        tear_down(run, driver).await;
+    }
+
+    Ok(())
+}
+
+/// Runs the test content repeatedly on one long-lived session, replacing it whenever it dies.
+async fn run_pooled(id: u64, run: &Run) -> Result<()> {
+    let mut slot = None;
+
+    for iteration in 0..run.iterations {
+        if run.is_interrupted() {
+            break;
+        }
+
+        let test = format!("{}.{}", id, iteration);
+        run.emit(Event::SessionScheduled { test: &test });
+        let start = Instant::now();
+        let mut variables = Variables::new();
+        if let Some(mix) = &run.mix {
+            variables.set("scenario", mix.scenario(id * run.iterations + iteration));
+        }
+        if let Some(journeys) = &run.journeys {
+            variables.set("scenario_version", journeys.version());
+        }
+        if let Some(priority) = run.priority_classes.class(id) {
+            variables.set("priority", &priority.label);
+        }
+        run.started.fetch_add(1, Ordering::Relaxed);
+        let result = run_pooled_iteration(&mut slot, run, &test, &mut variables, id).await;
+        run.completed.fetch_add(1, Ordering::Relaxed);
+        let duration = Instant::now() - start;
+
+        report(run, &test, &result, duration, &variables);
+        run.capture(variables);
+    }
+
+    if let Some((driver, _)) = slot {
+        tear_down(run, driver).await;
+    }
+
+    Ok(())
+}
+
+async fn run_pooled_iteration(
+    slot: &mut Option<(WebDriver, Instant)>,
+    run: &Run,
+    test: &str,
+    variables: &mut Variables,
+    index: u64,
+) -> Result<()> {
+    // Renew sessions that got too old, like client frameworks recycling their browsers
+    if let (Some((driver, created)), Some(max_age)) = (slot.as_ref(), run.max_session_age) {
+        if created.elapsed() >= max_age {
+            log::info!(
+                "Pooled session {} reached its maximum age of {}, renewing it",
+                driver.session_id(),
+                format_duration(max_age)
+            );
+            if let Some((driver, _)) = slot.take() {
+                tear_down(run, driver).await;
+            }
+            run.renewals.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Health check the pooled session and drop it if it no longer responds
+    if let Some((driver, created)) = slot.take() {
+        if driver.title().await.is_ok() {
+            *slot = Some((driver, created));
+        } else {
+            log::warn!(
+                "Pooled session {} is unresponsive, replacing it",
+                driver.session_id()
+            );
+            driver.quit().await.ok();
+        }
+    }
+
+    if slot.is_none() {
+        let driver = create_driver(run, test, index, variables)
+            .await
+            .context(InfrastructureFailure)?;
+        if let Err(e) = resize_viewport(run, &driver, test, variables).await {
+            let session_id = driver.session_id().to_string();
+            tear_down(run, driver).await;
+            bail!("{} failed due to {}", session_id, e);
+        }
+        *slot = Some((driver, Instant::now()));
+    }
+
+    let driver = &mut slot.as_mut().unwrap().0;
+
+    let session_id = driver.session_id().to_string();
+    variables.set("session_id", &session_id);
+    variables.set("platform", targeting::platform(driver));
+    variables.set("browser_version", targeting::browser_version(driver));
+    run.emit(Event::SessionStarted {
+        test,
+        session_id: &session_id,
+    });
+
+    let steps = Steps::new(&run.metrics, run.events.as_ref(), test, run.watchdog)
+        .traced(run.tracer.as_ref())
+        .timeouts(run.step_timeouts)
+        .console(run.console);
+    let result = run_test_content(driver, run, variables, steps).await;
+    collect_console(run, driver, test, variables).await;
+    if let Err(e) = result {
+        capture_failure(run, driver, test, variables).await;
+        bail!("{} failed due to {}", session_id, e);
+    }
+
+    Ok(())
+}
+
+/// Cleans the session up and quits it as configured
+async fn tear_down(run: &Run, driver: WebDriver) {
+    run.teardown
+        .run(driver, run.command_timeout, &run.hooks)
+        .await;
+}
+
+/// Counts the errors in the browser console of the test and saves its messages next to the
+/// screenshots
+async fn collect_console(run: &Run, driver: &WebDriver, test: &str, variables: &mut Variables) {
+    if run.console == Console::Off {
+        return;
+    }
+
+    let messages = match run.console.collect(driver, &run.endpoint, &run.hooks).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            log::debug!("Failed to collect the console of test {}: {}", test, e);
+            return;
+        }
+    };
+
+    let errors = messages.iter().filter(|message| message.is_error()).count();
+    variables.set("console_errors", errors.to_string());
+    if errors > 0 {
+        log::warn!("Test #{} logged {} console errors", test, errors);
+    }
+
+    let directory = match &run.screenshots {
+        Some(directory) if !messages.is_empty() => directory,
+        _ => return,
+    };
+    let content = messages
+        .iter()
+        .map(|message| format!("{} {}\n", message.level, message.message))
+        .collect::<String>();
+    let path = directory.join(format!("{}-{}.console.log", test, driver.session_id()));
+    save_artifact(run, test, variables, "console_log", path, Ok(content));
+}
+
+/// Saves a screenshot and the source of the page a failed test ended on, named after the test
+/// and its session
+async fn capture_failure(run: &Run, driver: &WebDriver, test: &str, variables: &mut Variables) {
+    let directory = match &run.screenshots {
+        Some(directory) => directory,
+        None => return,
+    };
+    let name = format!("{}-{}", test, driver.session_id());
+
+    let screenshot = bounded(
+        Some(run.command_timeout),
+        driver.screenshot_as_png(),
+        || "Screenshot".to_owned(),
+    )
+    .await;
+    let path = directory.join(format!("{}.png", name));
+    save_artifact(run, test, variables, "screenshot", path, screenshot);
+
+    // The DOM shows what the screenshot can not, e.g. a page that did not load at all
+    let source = bounded(Some(run.command_timeout), driver.page_source(), || {
+        "Page source".to_owned()
+    })
+    .await;
+    let path = directory.join(format!("{}.html", name));
+    save_artifact(run, test, variables, "page_source", path, source);
+}
+
+/// Writes an artifact of a failed test and stores its path in the variable named after it
+fn save_artifact(
+    run: &Run,
+    test: &str,
+    variables: &mut Variables,
+    variable: &str,
+    path: PathBuf,
+    content: Result<impl AsRef<[u8]>>,
+) {
+    let kind = variable.replace('_', " ");
+    let written = content.and_then(|content| {
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {} '{}'", kind, path.display()))
+    });
+
+    match written {
+        Ok(()) => {
+            let path = path.display().to_string();
+            log::info!("Saved the {} of test {} to '{}'", kind, test, path);
+            variables.set(variable, &path);
+            run.artifacts.lock().unwrap().push(path);
+        }
+        Err(e) => log::warn!("Failed to capture the {} of test {}: {}", kind, test, e),
+    }
+}
+
+/// Creates the session of the test with the given index on its browser version and priority
+async fn create_driver(
+    run: &Run,
+    test: &str,
+    index: u64,
+    variables: &mut Variables,
+) -> Result<WebDriver> {
+    let (endpoint, browser) = (run.endpoint.as_str(), run.browser.as_str());
+    let timeout = Some(run.session_timeout.max(run.command_timeout) + CLIENT_TIMEOUT_MARGIN);
+    let version = run.targeting.version(index);
+    let priority = run.priority_classes.class(index);
+    let viewport = run.viewports.as_ref().map(|jitter| jitter.pick(test));
+
+    // Everything that can fail happens before the circuit breaker admits the session, as only a
+    // finished session creation releases the probe of a half-open breaker
+    let caps = if browser == "firefox" {
+        let mut caps = DesiredCapabilities::firefox();
+        dialect::apply(
+            run.dialect.as_ref(),
+            &mut caps,
+            "test-name",
+            "test-build",
+            priority,
+        )?;
+        run.targeting.apply(&mut caps, version)?;
+        if let Some(viewport) = &viewport {
+            viewport.apply(browser, &mut caps)?;
+        }
+        if let Some(profile) = &run.firefox_profile {
+            caps.add_subkey("moz:firefoxOptions", "profile", profile)?;
+        }
+        caps.get().clone()
+    } else if browser == "chrome" {
+        let mut caps = DesiredCapabilities::chrome();
+        dialect::apply(
+            run.dialect.as_ref(),
+            &mut caps,
+            "test-name",
+            "test-build",
+            priority,
+        )?;
+        run.targeting.apply(&mut caps, version)?;
+        if let Some(viewport) = &viewport {
+            viewport.apply(browser, &mut caps)?;
+        }
+        run.console.apply(&mut caps)?;
+        if let Some(path) = &run.chrome_user_data_dir {
+            // Chrome can not receive a profile over the wire, and it locks the directory, so
+            // sessions running side by side on one node each need one of their own
+            caps.add_chrome_arg(&format!("--user-data-dir={}-{}", path, index))?;
+        }
+        caps.get().clone()
+    } else if browser == "safari" {
+        let mut caps = DesiredCapabilities::safari();
+        dialect::apply(
+            run.dialect.as_ref(),
+            &mut caps,
+            "test-name",
+            "test-build",
+            priority,
+        )?;
+        run.targeting.apply(&mut caps, version)?;
+        if let Some(viewport) = &viewport {
+            viewport.apply(browser, &mut caps)?;
+        }
+        caps.get().clone()
+    } else {
+        bail!("Unknown browser!");
+    };
+
+    // Time held back by an open circuit breaker does not count towards the session creation
+    let probe = admit(run).await?;
+    let start = Instant::now();
+    // Only the last attempt is attributed, earlier ones and the backoff in between are retries
+    let mut attempt = start;
+    let driver = connect(run, variables, probe, || {
+        attempt = Instant::now();
+        bounded(
+            Some(run.session_timeout),
+            WebDriver::new_with_timeout(endpoint, &caps, timeout),
+            || "Session creation".to_owned(),
+        )
+    })
+    .await?;
+
+    run.metrics.record(metrics::SESSION_CREATE, start.elapsed());
+    if let Some(timing) = &run.session_timing {
+        timing.attribute(&driver.session_id().to_string(), attempt.elapsed());
+    }
+    if let Some(reactions) = &run.reactions {
+        reactions.record(start.duration_since(run.launched), start.elapsed());
+    }
+    if let Some(priority) = priority {
+        let metric = format!("{}-{}", metrics::SESSION_CREATE, priority.label);
+        run.metrics.record(&metric, start.elapsed());
+    }
+
+    if let Err(e) = run.targeting.verify(&driver, version) {
+        driver.quit().await.ok();
+        return Err(e);
+    }
+
+    Ok(driver)
+}
+
+/// Resizes the window of a new session to the viewport its test picked, the grid provided the
+/// session already, so a browser refusing the size fails the test
+async fn resize_viewport(
+    run: &Run,
+    driver: &WebDriver,
+    test: &str,
+    variables: &mut Variables,
+) -> Result<()> {
+    let viewport = match &run.viewports {
+        Some(jitter) => jitter.pick(test),
+        None => return Ok(()),
+    };
+
+    variables.set("viewport", viewport.label());
+    variables.set("breakpoint", viewport.breakpoint());
+    viewport.resize(&run.browser, driver).await
+}
+
+/// Waits until the circuit breaker lets a session creation through, returns whether it is the
+/// probe of a half-open breaker
+async fn admit(run: &Run) -> Result<bool> {
+    let breaker = match &run.breaker {
+        Some(breaker) => breaker,
+        None => return Ok(false),
+    };
+
+    tokio::select! {
+        probe = breaker.admit() => Ok(probe),
+        _ = run.interrupted() => Err(anyhow!("Run interrupted")),
+    }
+}
+
+/// Creates a session, retrying failed attempts with backoff as configured, and records the
+/// number of attempts in `session_attempts`. The first attempt was already admitted by the
+/// circuit breaker.
+async fn connect<F>(
+    run: &Run,
+    variables: &mut Variables,
+    mut probe: bool,
+    mut create: impl FnMut() -> F,
+) -> Result<WebDriver>
+where
+    F: Future<Output = Result<WebDriver>>,
+{
+    let mut attempts = 1;
+    let result = loop {
+        let result = create().await;
+        if let Some(breaker) = &run.breaker {
+            breaker.record(probe, result.is_ok());
+        }
+
+        match result {
+            Err(e) if run.session_retries.allows(attempts) && !run.is_interrupted() => {
+                let delay = run.session_retries.delay(attempts - 1);
+                log::info!(
+                    "Session creation failed, retrying in {} (attempt {}): {}",
+                    format_duration(Duration::from_millis(delay.as_millis() as u64)),
+                    attempts + 1,
+                    e
+                );
+                if !run.pause(delay).await {
+                    break Err(e);
+                }
+                probe = match admit(run).await {
+                    Ok(probe) => probe,
+                    Err(_) => break Err(e),
+                };
+                attempts += 1;
+            }
+            result => break result,
+        }
+    };
+
+    variables.set("session_attempts", attempts.to_string());
+    run.session_retries.record(attempts, result.is_ok());
+    if attempts > 1 {
+        result.with_context(|| format!("Gave up after {} attempts", attempts))
+    } else {
+        Ok(result?)
+    }
+}
+
+/// Runs the scenario and delivers all status updates once it finished
+async fn run_test_content(
+    driver: &mut WebDriver,
+    run: &Run,
+    variables: &mut Variables,
+    steps: Steps<'_>,
+) -> Result<()> {
+    let scenario = match variables.get("scenario") {
+        Ok(name) => run.alternatives.get(name).unwrap_or(&run.scenario).clone(),
+        Err(_) => run.scenario.clone(),
+    };
+
+    let clock = steps.clock();
+    let mut session = Session::new(
+        driver,
+        variables,
+        steps,
+        run.dialect.as_ref(),
+        run.status_interval,
+    );
+    // Interrupted tests bail, so their sessions are quit right away
+    let mut result = tokio::select! {
+        result = scenario.run(&mut session) => result,
+        _ = run.interrupted() => Err(anyhow!("Run interrupted")),
+        error = clock.watch(run.step_timeouts.step) => Err(error),
+    };
+    if result.is_ok() {
+        result = session.steps.check_page_errors(session.driver).await;
+    }
+    session.finish(&result).await;
+    result
+}
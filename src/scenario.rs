@@ -0,0 +1,250 @@
+//! Content of the tests and the session it runs on.
+//!
+//! A scenario drives a single browser session. The runner creates the session, hands it to the
+//! scenario and takes care of reporting, so custom scenarios only contain the actual test steps.
+
+use crate::{
+    assertions::{self, Expectation, State},
+    dialect::{GridDialect, StatusChannel},
+    diff,
+    metrics::Steps,
+    variables::Variables,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+use thirtyfour::prelude::*;
+
+/// Test run on every session of a run
+#[async_trait]
+pub trait Scenario: Send + Sync {
+    /// Name identifying the scenario in logs and reports
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// Drives the session, failing the test by returning an error
+    async fn run(&self, session: &mut Session<'_>) -> Result<()>;
+}
+
+/// Browser session of a single test, dereferences to its `WebDriver`
+pub struct Session<'a> {
+    pub driver: &'a mut WebDriver,
+    /// Values captured during the test, available to invariants at the end of the run
+    pub variables: &'a mut Variables,
+    /// Timing of the individual steps of the test
+    pub steps: Steps<'a>,
+    /// Messages and status reported to the grid
+    pub status: StatusChannel<'a>,
+    dialect: &'a dyn GridDialect,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(
+        driver: &'a mut WebDriver,
+        variables: &'a mut Variables,
+        steps: Steps<'a>,
+        dialect: &'a dyn GridDialect,
+        status_interval: Duration,
+    ) -> Self {
+        Self {
+            driver,
+            variables,
+            steps,
+            status: StatusChannel::new(dialect, status_interval),
+            dialect,
+        }
+    }
+
+    /// Begins the next step, finishing the current one
+    pub async fn step(&mut self, name: &'static str) -> Result<()> {
+        self.steps.begin(self.driver, name).await
+    }
+
+    /// Navigates to `url`, failing if it takes longer than the navigation timeout
+    pub async fn navigate(&self, url: &str) -> Result<()> {
+        self.steps.navigate(self.driver, url).await
+    }
+
+    /// Finds an element, failing if the lookup takes longer than the lookup timeout
+    pub async fn find(&self, by: By<'_>) -> Result<WebElement<'_>> {
+        self.steps.find(self.driver, by).await
+    }
+
+    /// Queues a message for the grid
+    pub async fn message(&mut self, message: &str) {
+        self.status.message(self.driver, message).await;
+    }
+
+    pub fn dialect(&self) -> &'a dyn GridDialect {
+        self.dialect
+    }
+
+    /// Records the failing step and delivers the remaining status updates
+    pub(crate) async fn finish(mut self, result: &Result<()>) {
+        if result.is_err() {
+            // Steps that bailed early (e.g. due to a missing element) did not report a status yet
+            if !self.status.has_status() {
+                self.status.set_status("failure");
+            }
+
+            if let Some(step) = self.steps.last() {
+                self.variables.set("failed_step", step);
+                self.variables.set("steps", self.steps.trail());
+            }
+
+            let session_id = self.driver.session_id().to_string();
+            if let Some(url) = self.dialect.session_url(&session_id) {
+                log::info!("Details of failed session: {}", url);
+            }
+        }
+
+        self.status.flush(self.driver).await;
+    }
+}
+
+impl Deref for Session<'_> {
+    type Target = WebDriver;
+
+    fn deref(&self) -> &WebDriver {
+        self.driver
+    }
+}
+
+impl DerefMut for Session<'_> {
+    fn deref_mut(&mut self) -> &mut WebDriver {
+        self.driver
+    }
+}
+
+/// Single page demo at the given URL
+pub struct DemoPage {
+    pub page: String,
+}
+
+#[async_trait]
+impl Scenario for DemoPage {
+    fn name(&self) -> &str {
+        "demo"
+    }
+
+    async fn run(&self, session: &mut Session<'_>) -> Result<()> {
+        run_test_steps(
+            session.driver,
+            &self.page,
+            session.dialect,
+            session.variables,
+            &mut session.steps,
+            &mut session.status,
+        )
+        .await
+    }
+}
+
+async fn run_test_steps(
+    driver: &mut WebDriver,
+    page: &str,
+    dialect: &dyn GridDialect,
+    variables: &mut Variables,
+    steps: &mut Steps<'_>,
+    status: &mut StatusChannel<'_>,
+) -> Result<()> {
+    steps.begin(driver, "navigate").await?;
+    status.message(driver, "Visiting demo page").await;
+    steps.navigate(driver, page).await?;
+    assertions::subresources(&driver).await?;
+
+    // 0. Set some runtime metadata if the driver supports it
+    let mut metadata = HashMap::new();
+    metadata.insert("answer".to_owned(), "42".to_owned());
+    dialect.set_metadata(&driver, metadata).await;
+
+    // Remember the token the page generated so we can verify it later on
+    let token = steps.find(driver, By::Id("token")).await?;
+    variables.capture_text("token", &token).await?;
+
+    // Sessions on a jittered viewport check that the page adapts to it
+    if variables.get("viewport").is_ok() {
+        steps.begin(driver, "layout").await?;
+        status.message(driver, "Checking layout").await;
+        let title = steps.find(driver, By::Tag("h1")).await?;
+        assertions::layout(driver, &title).await?;
+    }
+
+    // 1. Check that the `h1` contains the correct title
+    steps.begin(driver, "title").await?;
+    status.message(driver, "Checking title").await;
+    assertions::count(&driver, By::Tag("h1"), 1).await?;
+    let expected_title = "Horrible looking test-page";
+    let title = steps.find(driver, By::Tag("h1")).await?.text().await?;
+    if !title.eq_ignore_ascii_case(expected_title) {
+        status.message(driver, "Title mismatch.").await;
+        status.set_status("failure");
+        bail!(
+            "Title mismatched :(\n{}",
+            diff::describe_mismatch(&title, expected_title)
+        );
+    }
+
+    // 2. Check that pressing the `#increment` button increments the `#counter`
+    steps.begin(driver, "increment").await?;
+    status.message(driver, "Checking increment").await;
+    let counter = steps.find(driver, By::Id("counter")).await?;
+    let value = counter.text().await?.parse::<i32>()?;
+    let increment = steps.find(driver, By::Id("increment")).await?;
+    assertions::state(&increment, State::Displayed).await?;
+    assertions::state(&increment, State::Enabled).await?;
+    increment.click().await?;
+    let expected_value = Expectation::number((value + 1).into(), 0.0);
+    if let Err(e) = assertions::text(&counter, &expected_value).await {
+        status.message(driver, "Increment is broken.").await;
+        status.set_status("failure");
+        bail!("Increment is broken :( {:#}", e);
+    }
+
+    // 3. Check that entering a new hash value actually works
+    steps.begin(driver, "hash").await?;
+    status.message(driver, "Checking hash value").await;
+    let expected_hash = "No emojis allowed here :(";
+    let hash_input = steps.find(driver, By::Id("newHashValue")).await?;
+    let expected_placeholder = Expectation::starts_with("New hash");
+    assertions::attribute(&hash_input, "placeholder", &expected_placeholder).await?;
+    hash_input.send_keys(expected_hash).await?;
+    hash_input.send_keys(Keys::Enter).await?;
+    let hash = steps
+        .find(driver, By::Id("hashValue"))
+        .await?
+        .text()
+        .await?;
+    if hash != expected_hash {
+        status
+            .message(driver, "Hash value updating is broken.")
+            .await;
+        status.set_status("failure");
+        bail!(
+            "Hash value updating is broken:\n{}",
+            diff::describe_mismatch(&hash, expected_hash)
+        );
+    }
+
+    // 4. Check that the page still shows the token it generated initially
+    steps.begin(driver, "token").await?;
+    status.message(driver, "Checking token").await;
+    let expected_token = Expectation::equals(variables.interpolate("${token}")?);
+    if let Err(e) = assertions::text(&token, &expected_token).await {
+        status.message(driver, "Token changed.").await;
+        status.set_status("failure");
+        bail!("Token changed during the test: {:#}", e);
+    }
+
+    steps.finish();
+
+    status.message(driver, "It worked!").await;
+    status.set_status("success");
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Arrival schedules of open-loop runs, which launch tests at given times regardless of how
+//! quickly earlier ones finish.
+//!
+//! Besides a constant rate, a schedule can replay the arrivals recorded in production, e.g.
+//! from the timestamps of an access log, along with the scenario each arrival runs. Library users
+//! can implement their own load shapes.
+
+use anyhow::{bail, Context, Result};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Times at which the tests of an open-loop run are launched
+pub trait ArrivalSchedule: Send + Sync {
+    /// Offset from the start of the run at which the test with the given position is launched,
+    /// `None` once the schedule is exhausted. Offsets must not decrease with the position.
+    fn arrival(&self, position: u64) -> Option<Duration>;
+
+    /// Name of the built-in scenario or scripted journey the test with the given position runs
+    /// instead of the one of the run
+    fn scenario(&self, _position: u64) -> Option<&str> {
+        None
+    }
+}
+
+/// Launches `count` tests at a fixed number per second
+pub struct ConstantRate {
+    rate: f64,
+    count: u64,
+}
+
+impl ConstantRate {
+    pub fn new(rate: f64, count: u64) -> Self {
+        Self { rate, count }
+    }
+}
+
+impl ArrivalSchedule for ConstantRate {
+    fn arrival(&self, position: u64) -> Option<Duration> {
+        if position < self.count {
+            Some(Duration::from_secs_f64(position as f64 / self.rate))
+        } else {
+            None
+        }
+    }
+}
+
+/// Replays arrivals from a file with one timestamp per line, either in RFC 3339 format or as
+/// (fractional) seconds, optionally followed by the scenario to run. Only the distances between
+/// the timestamps matter, the first test is launched right away.
+pub struct Replay {
+    arrivals: Vec<(Duration, Option<String>)>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read arrival timestamps '{}'", path))?;
+
+        let mut arrivals = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let arrival = parse_arrival(line)
+                .with_context(|| format!("Invalid arrival on line {} of '{}'", index + 1, path))?;
+            arrivals.push(arrival);
+        }
+
+        if arrivals.is_empty() {
+            bail!("'{}' does not contain any arrival timestamps", path);
+        }
+
+        // Stable, so simultaneous arrivals keep the order of the file
+        arrivals.sort_by_key(|(timestamp, _)| *timestamp);
+        let first = arrivals[0].0;
+
+        Ok(Self {
+            arrivals: arrivals
+                .into_iter()
+                .map(|(timestamp, scenario)| (timestamp - first, scenario))
+                .collect(),
+        })
+    }
+}
+
+impl ArrivalSchedule for Replay {
+    fn arrival(&self, position: u64) -> Option<Duration> {
+        self.arrivals
+            .get(position as usize)
+            .map(|(offset, _)| *offset)
+    }
+
+    fn scenario(&self, position: u64) -> Option<&str> {
+        self.arrivals
+            .get(position as usize)
+            .and_then(|(_, scenario)| scenario.as_deref())
+    }
+}
+
+/// Timestamp and scenario of one line of a replay file
+fn parse_arrival(line: &str) -> Result<(Duration, Option<String>)> {
+    let mut tokens = line.split_whitespace();
+    let timestamp = parse_timestamp(tokens.next().context("Missing timestamp")?)?;
+    let scenario = tokens.next().map(str::to_owned);
+    if let Some(token) = tokens.next() {
+        bail!("Unexpected '{}' after the scenario", token);
+    }
+
+    Ok((timestamp, scenario))
+}
+
+/// Time since the epoch of a timestamp in either of the supported formats
+fn parse_timestamp(text: &str) -> Result<Duration> {
+    if let Ok(seconds) = text.parse::<f64>() {
+        if !(seconds >= 0.0 && seconds.is_finite()) {
+            bail!("'{}' is not a valid number of seconds", text);
+        }
+        return Ok(Duration::from_secs_f64(seconds));
+    }
+
+    let time = humantime::parse_rfc3339_weak(text)?;
+    Ok(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
@@ -0,0 +1,540 @@
+//! Scripted journeys loaded from a directory, so new journeys do not need a build of their own.
+//!
+//! Every `.journey` file below the directory becomes a scenario named by its path relative to the
+//! directory without the extension, e.g. `checkout/guest` for `checkout/guest.journey`. The names
+//! select the journeys like the built-in scenarios, through `SCENARIO` or the scenario column of
+//! replay files. Include and exclude globs match these names, where `*` stays within a directory
+//! and `**` does not.
+//!
+//! A journey has one command per line, blank lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! step     <name>                begins the next step
+//! open     <url>                 navigates to the URL
+//! click    <selector>            clicks the element
+//! type     <selector> = <text>   types the text into the element
+//! capture  <variable> <selector> stores the text of the element in the variable
+//! expect   <selector> = <text>   fails unless the text of the element equals the given one
+//! ```
+//!
+//! Selectors are CSS selectors, URLs and texts may reference captured variables as `${name}`.
+//!
+//! The journeys are versioned by a hash of their files. Continuous runs reload them between
+//! rounds, tests already running keep the version they started with.
+
+use crate::{
+    assertions::{self, Expectation},
+    scenario::{Scenario, Session},
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+use thirtyfour::prelude::*;
+
+const EXTENSION: &str = "journey";
+
+enum Command {
+    Step(&'static str),
+    Open(String),
+    Click(String),
+    Type(String, String),
+    Capture(String, String),
+    Expect(String, String),
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Self> {
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line, ""),
+        };
+        if argument.is_empty() {
+            bail!("Missing argument of '{}'", command);
+        }
+
+        // Selectors may contain spaces, so texts are separated from them by ` = `
+        let assignment = || -> Result<(String, String)> {
+            let (selector, text) = argument
+                .split_once(" = ")
+                .with_context(|| format!("Expected '{} <selector> = <text>'", command))?;
+            Ok((selector.trim().to_owned(), text.trim().to_owned()))
+        };
+
+        Ok(match command {
+            // Steps are named for the whole run, so their names live as long as it does
+            "step" => Command::Step(Box::leak(argument.to_owned().into_boxed_str())),
+            "open" => Command::Open(argument.to_owned()),
+            "click" => Command::Click(argument.to_owned()),
+            "type" => {
+                let (selector, text) = assignment()?;
+                Command::Type(selector, text)
+            }
+            "capture" => {
+                let (variable, selector) = argument
+                    .split_once(char::is_whitespace)
+                    .context("Expected 'capture <variable> <selector>'")?;
+                Command::Capture(variable.to_owned(), selector.trim().to_owned())
+            }
+            "expect" => {
+                let (selector, text) = assignment()?;
+                Command::Expect(selector, text)
+            }
+            other => bail!("Unknown command '{}'", other),
+        })
+    }
+}
+
+fn parse_commands(content: &str) -> Result<Vec<Command>> {
+    let commands = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            Command::parse(line.trim()).with_context(|| format!("Invalid line {}", index + 1))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if commands.is_empty() {
+        bail!("The journey does not contain any commands");
+    }
+
+    Ok(commands)
+}
+
+/// Journey read from a file
+pub struct Script {
+    name: String,
+    /// Replaced when the journey is reloaded
+    commands: RwLock<Arc<Vec<Command>>>,
+}
+
+impl Script {
+    fn parse(name: &str, content: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.to_owned(),
+            commands: RwLock::new(Arc::new(parse_commands(content)?)),
+        })
+    }
+
+    fn commands(&self) -> Arc<Vec<Command>> {
+        self.commands.read().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Scenario for Script {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&self, session: &mut Session<'_>) -> Result<()> {
+        for command in self.commands().iter() {
+            match command {
+                Command::Step(name) => session.step(*name).await?,
+                Command::Open(url) => {
+                    let url = session.variables.interpolate(url)?;
+                    session.navigate(&url).await?;
+                }
+                Command::Click(selector) => session.find(By::Css(selector)).await?.click().await?,
+                Command::Type(selector, text) => {
+                    let text = session.variables.interpolate(text)?;
+                    let element = session.find(By::Css(selector)).await?;
+                    element.send_keys(text.as_str()).await?;
+                }
+                Command::Capture(variable, selector) => {
+                    let text = session.find(By::Css(selector)).await?.text().await?;
+                    session.variables.set(variable.as_str(), text);
+                }
+                Command::Expect(selector, text) => {
+                    let expected = Expectation::equals(session.variables.interpolate(text)?);
+                    let element = session.find(By::Css(selector)).await?;
+                    assertions::text(&element, &expected)
+                        .await
+                        .with_context(|| format!("Unexpected text of '{}'", selector))?;
+                }
+            }
+        }
+
+        session.steps.finish();
+        session.status.set_status("success");
+
+        Ok(())
+    }
+}
+
+/// Directory of scripted journeys and the globs selecting which of them are loaded
+pub struct ScenarioDir {
+    path: PathBuf,
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl ScenarioDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Only loads journeys whose name matches one of the globs, all of them if none are given
+    pub fn include(mut self, globs: Vec<String>) -> Self {
+        self.include = globs;
+        self
+    }
+
+    /// Skips journeys whose name matches one of the globs
+    pub fn exclude(mut self, globs: Vec<String>) -> Self {
+        self.exclude = globs;
+        self
+    }
+
+    /// Reads the directory from `SCENARIO_DIR` unless given, and the globs from the comma
+    /// separated `SCENARIO_INCLUDE` and `SCENARIO_EXCLUDE`
+    pub fn from_env(dir: Option<Self>) -> Result<Option<Self>> {
+        if dir.is_some() {
+            return Ok(dir);
+        }
+
+        let path = match std::env::var("SCENARIO_DIR") {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        let globs = |variable: &str| globs(&std::env::var(variable).unwrap_or_default());
+
+        Ok(Some(
+            Self::new(path)
+                .include(globs("SCENARIO_INCLUDE"))
+                .exclude(globs("SCENARIO_EXCLUDE")),
+        ))
+    }
+
+    /// Selected journey files below the directory as `(name, path)`, sorted by name
+    pub fn files(&self) -> Result<Vec<(String, PathBuf)>> {
+        let include = compile(&self.include).context("Invalid include glob")?;
+        let exclude = compile(&self.exclude).context("Invalid exclude glob")?;
+
+        let mut files = Vec::new();
+        collect(&self.path, &mut files).with_context(|| {
+            format!(
+                "Failed to read scenario directory '{}'",
+                self.path.display()
+            )
+        })?;
+
+        let mut selected = files
+            .into_iter()
+            .map(|file| (name(&self.path, &file), file))
+            .filter(|(name, _)| {
+                (include.is_empty() || include.iter().any(|glob| glob.is_match(name)))
+                    && !exclude.iter().any(|glob| glob.is_match(name))
+            })
+            .collect::<Vec<_>>();
+        selected.sort();
+
+        Ok(selected)
+    }
+
+    /// Contents of the selected journey files as `(name, path, content)`, sorted by name
+    fn read(&self) -> Result<Vec<(String, PathBuf, String)>> {
+        self.files()?
+            .into_iter()
+            .map(|(name, file)| {
+                let content = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read journey '{}'", file.display()))?;
+                Ok((name, file, content))
+            })
+            .collect()
+    }
+
+    /// Loads every selected journey below the directory
+    pub fn load(self) -> Result<Journeys> {
+        let files = self.read()?;
+        if files.is_empty() {
+            bail!(
+                "The scenario directory '{}' does not contain any selected journeys",
+                self.path.display()
+            );
+        }
+
+        let mut scripts = HashMap::new();
+        for (name, file, content) in files.iter() {
+            let script = Script::parse(name, content)
+                .with_context(|| format!("Invalid journey '{}'", file.display()))?;
+            scripts.insert(name.clone(), Arc::new(script));
+        }
+
+        let version = version(&files);
+        log::info!(
+            "Loaded {} journeys from '{}' (version {}): {}",
+            files.len(),
+            self.path.display(),
+            version,
+            files
+                .iter()
+                .map(|(name, _, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(Journeys {
+            dir: self,
+            scripts,
+            version: Mutex::new(version),
+        })
+    }
+}
+
+/// Journeys loaded from a scenario directory
+pub struct Journeys {
+    dir: ScenarioDir,
+    scripts: HashMap<String, Arc<Script>>,
+    version: Mutex<String>,
+}
+
+impl Journeys {
+    /// Scenarios of the journeys, by name
+    pub fn scenarios(&self) -> HashMap<String, Arc<dyn Scenario>> {
+        self.scripts
+            .iter()
+            .map(|(name, script)| (name.clone(), script.clone() as Arc<dyn Scenario>))
+            .collect()
+    }
+
+    /// Hash of the journey files the scenarios currently run
+    pub fn version(&self) -> String {
+        self.version.lock().unwrap().clone()
+    }
+
+    /// Reads the journey files again and applies changes to the journeys loaded at the start,
+    /// returns whether any changed. A journey that does not parse leaves all of them unchanged.
+    pub fn reload(&self) -> Result<bool> {
+        let files = self.dir.read()?;
+        let version = version(&files);
+        if version == self.version() {
+            return Ok(false);
+        }
+
+        let mut changes = Vec::new();
+        for (name, file, content) in files.iter() {
+            match self.scripts.get(name) {
+                Some(script) => changes.push((
+                    script,
+                    parse_commands(content)
+                        .with_context(|| format!("Invalid journey '{}'", file.display()))?,
+                )),
+                None => log::warn!(
+                    "Journey '{}' was added during the run and is not loaded before a restart",
+                    name
+                ),
+            }
+        }
+        for name in self.scripts.keys() {
+            if !files.iter().any(|(other, _, _)| other == name) {
+                log::warn!(
+                    "Journey '{}' was removed during the run and keeps running its last version",
+                    name
+                );
+            }
+        }
+
+        for (script, commands) in changes {
+            *script.commands.write().unwrap() = Arc::new(commands);
+        }
+        *self.version.lock().unwrap() = version;
+
+        Ok(true)
+    }
+}
+
+/// Short hash of the names and contents of journey files
+fn version(files: &[(String, PathBuf, String)]) -> String {
+    let mut hasher = Sha256::new();
+    for (name, _, content) in files {
+        hasher.update(name.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(content.as_bytes());
+        hasher.update([0u8]);
+    }
+
+    hasher.finalize()[..6]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Splits a comma separated list of globs
+fn globs(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|glob| !glob.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Adds the journey files below the directory to `files`
+fn collect(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path
+            .extension()
+            .map_or(false, |extension| extension == EXTENSION)
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Name of a journey file, its path relative to the directory without the extension
+fn name(directory: &Path, file: &Path) -> String {
+    file.strip_prefix(directory)
+        .unwrap_or(file)
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn compile(globs: &[String]) -> Result<Vec<Regex>> {
+    globs.iter().map(|glob| glob_to_regex(glob)).collect()
+}
+
+/// Translates a glob into an anchored regular expression
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut pattern = String::from("^");
+    let mut characters = glob.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '*' if characters.peek() == Some(&'*') => {
+                characters.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            other => pattern.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    pattern.push('$');
+
+    Regex::new(&pattern).with_context(|| format!("Invalid glob '{}'", glob))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_stay_within_directories_unless_doubled() {
+        let single = glob_to_regex("checkout/*").unwrap();
+        assert!(single.is_match("checkout/guest"));
+        assert!(!single.is_match("checkout/guest/express"));
+
+        let double = glob_to_regex("checkout/**").unwrap();
+        assert!(double.is_match("checkout/guest/express"));
+        assert!(!double.is_match("search/checkout/guest"));
+
+        let any = glob_to_regex("**/smoke-?").unwrap();
+        assert!(any.is_match("search/smoke-1"));
+        assert!(!any.is_match("search/smoke-10"));
+        assert!(glob_to_regex("a.b").unwrap().is_match("a.b"));
+        assert!(!glob_to_regex("a.b").unwrap().is_match("axb"));
+    }
+
+    #[test]
+    fn names_are_relative_paths_without_extension() {
+        assert_eq!(
+            name(
+                Path::new("journeys"),
+                Path::new("journeys/checkout/guest.journey")
+            ),
+            "checkout/guest"
+        );
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let script = Script::parse(
+            "checkout",
+            "# Guest checkout\n\
+             step list\n\
+             open ${base}/items\n\
+             \n\
+             capture product .product:first-child .name\n\
+             click .product:first-child\n\
+             type #customer input = Journey Tester\n\
+             expect #summary = 1 × ${product}\n",
+        )
+        .unwrap();
+
+        let commands = script.commands();
+        assert_eq!(script.name(), "checkout");
+        assert_eq!(commands.len(), 6);
+        assert!(matches!(commands[0], Command::Step("list")));
+        match &commands[2] {
+            Command::Capture(variable, selector) => {
+                assert_eq!(variable, "product");
+                assert_eq!(selector, ".product:first-child .name");
+            }
+            _ => panic!("expected a capture"),
+        }
+        match &commands[4] {
+            Command::Type(selector, text) => {
+                assert_eq!(selector, "#customer input");
+                assert_eq!(text, "Journey Tester");
+            }
+            _ => panic!("expected typing"),
+        }
+    }
+
+    #[test]
+    fn reloads_changed_journeys() {
+        let directory = std::env::temp_dir().join(format!("journeys-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("search.journey");
+        std::fs::write(&file, "open https://example.com/\n").unwrap();
+
+        let journeys = ScenarioDir::new(&directory).load().unwrap();
+        let script = journeys.scripts["search"].clone();
+        let loaded = journeys.version();
+        assert!(!journeys.reload().unwrap());
+
+        std::fs::write(&file, "open https://example.com/\nclick #search\n").unwrap();
+        assert!(journeys.reload().unwrap());
+        assert_eq!(script.commands().len(), 2);
+        assert_ne!(journeys.version(), loaded);
+
+        // Broken journeys leave the last version running
+        let reloaded = journeys.version();
+        std::fs::write(&file, "jump #top\n").unwrap();
+        assert!(journeys.reload().is_err());
+        assert_eq!(script.commands().len(), 2);
+        assert_eq!(journeys.version(), reloaded);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_journeys() {
+        for content in [
+            "",
+            "# only a comment",
+            "jump #top",
+            "open",
+            "type #customer",
+            "capture product",
+        ] {
+            assert!(Script::parse("broken", content).is_err(), "{}", content);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Demo page the tests run against.
+//!
+//! The page can be padded with additional DOM elements and JavaScript to tune how much work
+//! browsers and the data-plane of the grid have to do for every session, without changing
+//! the elements the test interacts with.
+
+use anyhow::{Context, Result};
+use std::fmt::Write;
+
+const DEMO_BODY: &str = include_str!("site.html");
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Complexity {
+    /// Number of filler blocks appended to the page
+    elements: usize,
+    /// Nesting depth of every filler block
+    depth: usize,
+    /// Approximate size of additional JavaScript executed on load in kilobytes
+    script_kb: usize,
+}
+
+impl Complexity {
+    /// Reads `SITE_ELEMENTS`, `SITE_DEPTH` (default 1) and `SITE_SCRIPT_KB`
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            elements: parse_variable("SITE_ELEMENTS", 0)?,
+            depth: parse_variable("SITE_DEPTH", 1)?.max(1),
+            script_kb: parse_variable("SITE_SCRIPT_KB", 0)?,
+        })
+    }
+}
+
+fn parse_variable(name: &str, default: usize) -> Result<usize> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Failed to parse {}", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Builds the demo page with the requested padding
+pub fn generate(complexity: &Complexity) -> String {
+    let mut page = String::with_capacity(DEMO_BODY.len());
+    page.push_str(DEMO_BODY);
+
+    if complexity.elements > 0 {
+        page.push_str("\n<div id=\"filler\">\n");
+        for index in 0..complexity.elements {
+            page.push_str(&"<div>".repeat(complexity.depth));
+            write!(page, "<span class=\"filler-item\">Item {}</span>", index).ok();
+            page.push_str(&"</div>".repeat(complexity.depth));
+            page.push('\n');
+        }
+        page.push_str("</div>\n");
+    }
+
+    if complexity.script_kb > 0 {
+        page.push_str("\n<script>\n    const fillerData = [");
+        let target = page.len() + complexity.script_kb * 1024;
+        let mut value = 0u64;
+        while page.len() < target {
+            write!(page, "{},", value).ok();
+            value += 1;
+        }
+        page.push_str("];\n");
+        page.push_str("    const fillerSum = fillerData.reduce((a, b) => a + b, 0);\n");
+        page.push_str("    console.log('Filler sum:', fillerSum);\n</script>\n");
+    }
+
+    page
+}
+
+/// Page encoded as a data URL so it can be loaded without any server
+pub fn data_url(page: &str) -> String {
+    format!(
+        "data:text/html;charset=utf-8;base64,{}",
+        base64::encode(page)
+    )
+}
@@ -0,0 +1,369 @@
+//! Execution of several runs described in a suite file.
+//!
+//! Every non-empty line that is not a comment describes one run as
+//! `<name> <count> [browser] [KEY=VALUE ...]`, where the assignments are passed to the run as
+//! environment variables (e.g. `ITERATIONS=20` or `TIMEOUT=120`). Each run is executed by a
+//! separate instance of this binary so runs can not influence each other. Its environment only
+//! holds those assignments and the few variables of the suite every process needs, so what a run
+//! does is written down in the suite file.
+//!
+//! Every run writes its own event log (a temporary one unless `EVENT_LOG` is assigned), from
+//! which the suite builds a grid of the results per browser and scenario.
+//!
+//! With a cache directory, the event logs of passed runs are kept there under a hash of
+//! everything that determines the run: the binary (which contains the scenarios), the endpoint,
+//! the run definition and the content of every file it references, like proxy rules or a replay,
+//! or of the journeys it selects from a scenario directory.
+//! Runs whose inputs did not change since they last passed are skipped and their previous results
+//! reused.
+
+use crate::{job, report};
+use anyhow::{anyhow, bail, Context, Result};
+use basic_test::ScenarioDir;
+use humantime::format_duration;
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::process::Command;
+
+/// Variables pointing at files the run writes, which are no input of the run
+const OUTPUTS: [&str; 6] = [
+    "EVENT_LOG",
+    "JUNIT_REPORT",
+    "ALLURE_RESULTS",
+    "SCREENSHOT_DIR",
+    "MANIFEST",
+    "HDR_LOG",
+];
+
+pub struct RunDefinition {
+    name: String,
+    count: u64,
+    browser: Option<String>,
+    variables: Vec<(String, String)>,
+}
+
+struct Outcome {
+    name: String,
+    duration: Duration,
+    result: Result<()>,
+    event_log: String,
+    /// Whether the event log was created by the suite and is removed afterwards
+    temporary: bool,
+    /// Whether the results were taken from the cache instead of executing the run
+    cached: bool,
+}
+
+/// Event logs of previously passed runs, keyed by the inputs of the run
+#[derive(Clone)]
+pub struct Cache {
+    directory: PathBuf,
+    /// Hash of the executable, so changed scenarios invalidate all entries
+    binary: String,
+}
+
+impl Cache {
+    pub fn open(directory: &str) -> Result<Self> {
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create cache directory '{}'", directory))?;
+        let executable = std::env::current_exe().context("Failed to locate own executable")?;
+        let binary = std::fs::read(&executable).context("Failed to read own executable")?;
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            binary: hex(&Sha256::digest(&binary)),
+        })
+    }
+
+    fn path(&self, endpoint: &str, run: &RunDefinition) -> Result<PathBuf> {
+        let mut variables = run
+            .variables
+            .iter()
+            .filter(|(key, _)| !OUTPUTS.contains(&key.as_str()))
+            .collect::<Vec<_>>();
+        variables.sort();
+
+        let mut hasher = Sha256::new();
+        for input in [
+            self.binary.as_str(),
+            endpoint,
+            run.count.to_string().as_str(),
+            run.browser.as_deref().unwrap_or_default(),
+        ] {
+            hasher.update(input.as_bytes());
+            hasher.update([0u8]);
+        }
+        for (key, value) in variables {
+            hasher.update(format!("{}={}", key, value).as_bytes());
+            hasher.update([0u8]);
+            // Values may list several files, e.g. `TEARDOWN_SCRIPTS`
+            for path in value.split(',').map(Path::new) {
+                if path.is_file() {
+                    let content = std::fs::read(path)
+                        .with_context(|| format!("Failed to read '{}'", path.display()))?;
+                    hasher.update(Sha256::digest(&content));
+                } else if path.is_dir() {
+                    // Scenario directories, of which only the selected journeys matter
+                    let globs = |variable: &str| {
+                        run.variables
+                            .iter()
+                            .find(|(key, _)| key == variable)
+                            .map(|(_, value)| {
+                                value
+                                    .split(',')
+                                    .map(str::trim)
+                                    .filter(|glob| !glob.is_empty())
+                                    .map(str::to_owned)
+                                    .collect::<Vec<_>>()
+                            })
+                            .unwrap_or_default()
+                    };
+                    let journeys = ScenarioDir::new(path)
+                        .include(globs("SCENARIO_INCLUDE"))
+                        .exclude(globs("SCENARIO_EXCLUDE"))
+                        .files()?;
+                    for (name, file) in journeys {
+                        let content = std::fs::read(&file)
+                            .with_context(|| format!("Failed to read '{}'", file.display()))?;
+                        hasher.update(name.as_bytes());
+                        hasher.update([0u8]);
+                        hasher.update(Sha256::digest(&content));
+                    }
+                }
+            }
+        }
+
+        let key = hex(&hasher.finalize());
+        Ok(self
+            .directory
+            .join(format!("{}-{}.ndjson", run.name, &key[..16])))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn load(path: &str) -> Result<Vec<RunDefinition>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read suite file '{}'", path))?;
+
+    let mut runs = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let run = parse_definition(line)
+            .with_context(|| format!("Invalid run on line {} of '{}'", index + 1, path))?;
+        runs.push(run);
+    }
+
+    if runs.is_empty() {
+        bail!("Suite file '{}' does not contain any runs", path);
+    }
+
+    Ok(runs)
+}
+
+fn parse_definition(line: &str) -> Result<RunDefinition> {
+    let mut tokens = line.split_whitespace();
+    let name = tokens.next().context("Missing run name")?.to_owned();
+    let count = tokens
+        .next()
+        .context("Missing session count")?
+        .parse::<u64>()
+        .context("Invalid session count")?;
+
+    let mut browser = None;
+    let mut variables = Vec::new();
+    for token in tokens {
+        match token.split_once('=') {
+            Some((key, value)) => variables.push((key.to_owned(), value.to_owned())),
+            None if browser.is_none() && variables.is_empty() => browser = Some(token.to_owned()),
+            None => bail!("Expected KEY=VALUE, got '{}'", token),
+        }
+    }
+
+    Ok(RunDefinition {
+        name,
+        count,
+        browser,
+        variables,
+    })
+}
+
+/// Executes all runs against `endpoint` and logs a report, returns whether every run passed
+pub async fn execute(
+    endpoint: &str,
+    runs: Vec<RunDefinition>,
+    parallel: bool,
+    cache: Option<Cache>,
+) -> Result<bool> {
+    let executable = std::env::current_exe().context("Failed to locate own executable")?;
+    let started = Instant::now();
+
+    log::info!(
+        "Executing {} runs {}",
+        runs.len(),
+        if parallel {
+            "in parallel"
+        } else {
+            "sequentially"
+        }
+    );
+
+    let outcomes = if parallel {
+        let handles = runs
+            .into_iter()
+            .map(|run| {
+                let executable = executable.clone();
+                let endpoint = endpoint.to_owned();
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    execute_run(&executable, &endpoint, run, cache.as_ref()).await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut outcomes = Vec::new();
+        for handle in handles {
+            outcomes.push(handle.await?);
+        }
+        outcomes
+    } else {
+        let mut outcomes = Vec::new();
+        for run in runs {
+            outcomes.push(execute_run(&executable, endpoint, run, cache.as_ref()).await);
+        }
+        outcomes
+    };
+
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+
+    log::info!(
+        "Suite finished after {}. {} / {} runs passed.",
+        format_duration(Duration::from_secs(started.elapsed().as_secs())),
+        outcomes.len() - failed,
+        outcomes.len()
+    );
+
+    for outcome in outcomes.iter() {
+        let duration = format_duration(Duration::from_secs(outcome.duration.as_secs()));
+        match &outcome.result {
+            Ok(_) if outcome.cached => log::info!("  {} unchanged, passed before", outcome.name),
+            Ok(_) => log::info!("  {} passed in {}", outcome.name, duration),
+            Err(e) => log::info!("  {} failed after {}: {}", outcome.name, duration, e),
+        }
+    }
+
+    let event_logs = outcomes
+        .iter()
+        .filter(|o| std::path::Path::new(&o.event_log).exists())
+        .map(|o| o.event_log.clone())
+        .collect::<Vec<_>>();
+    if !event_logs.is_empty() {
+        if let Err(e) = report::log_matrix(&event_logs) {
+            log::warn!("Failed to summarize the runs: {:#}", e);
+        }
+    }
+
+    for outcome in outcomes.iter().filter(|o| o.temporary) {
+        std::fs::remove_file(&outcome.event_log).ok();
+    }
+
+    Ok(failed == 0)
+}
+
+async fn execute_run(
+    executable: &Path,
+    endpoint: &str,
+    run: RunDefinition,
+    cache: Option<&Cache>,
+) -> Outcome {
+    let cached = match cache.map(|cache| cache.path(endpoint, &run)).transpose() {
+        Ok(cached) => cached,
+        Err(e) => {
+            log::warn!("Not caching run '{}': {:#}", run.name, e);
+            None
+        }
+    };
+    if let Some(path) = cached.as_ref().filter(|path| path.exists()) {
+        log::info!(
+            "Run '{}' is unchanged since it last passed, skipping it",
+            run.name
+        );
+        return Outcome {
+            name: run.name,
+            duration: Duration::default(),
+            result: Ok(()),
+            event_log: path.to_string_lossy().into_owned(),
+            temporary: false,
+            cached: true,
+        };
+    }
+
+    log::info!("Starting run '{}'", run.name);
+    let started = Instant::now();
+
+    let (event_log, temporary) = match run.variables.iter().find(|(key, _)| key == "EVENT_LOG") {
+        Some((_, path)) => (path.clone(), false),
+        None => {
+            let name = format!("suite-{}-{}.ndjson", std::process::id(), run.name);
+            let path = std::env::temp_dir().join(name);
+            (path.to_string_lossy().into_owned(), true)
+        }
+    };
+
+    let mut command = Command::new(executable);
+    command
+        .args(["run", "--endpoint", endpoint])
+        .args(["--count", &run.count.to_string()])
+        .args(
+            run.browser
+                .iter()
+                .flat_map(|browser| ["--browser", browser.as_str()]),
+        )
+        .env_clear()
+        .envs(
+            job::INHERITED
+                .iter()
+                .chain(["RUST_LOG"].iter())
+                .filter_map(|key| Some((*key, std::env::var_os(key)?))),
+        )
+        .env("EVENT_LOG", &event_log)
+        .envs(run.variables.iter().map(|(k, v)| (k, v)));
+
+    let result = match command.status().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => match status.code() {
+            Some(code) => Err(anyhow!("exited with code {}", code)),
+            None => Err(anyhow!("was terminated by a signal")),
+        },
+        Err(e) => Err(anyhow!("could not be started: {}", e)),
+    };
+
+    match &result {
+        Ok(_) => log::info!("Run '{}' passed", run.name),
+        Err(e) => log::error!("Run '{}' {}", run.name, e),
+    }
+
+    // Only passed runs are worth skipping the next time
+    if let (Ok(_), Some(path)) = (&result, &cached) {
+        if let Err(e) = std::fs::copy(&event_log, path) {
+            log::warn!("Failed to cache the results of run '{}': {}", run.name, e);
+        }
+    }
+
+    Outcome {
+        name: run.name,
+        duration: started.elapsed(),
+        result,
+        event_log,
+        temporary,
+        cached: false,
+    }
+}
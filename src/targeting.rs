@@ -0,0 +1,187 @@
+//! Selection of the platform and browser version sessions should run on.
+//!
+//! Grids are free to ignore capabilities they can not satisfy, so the capabilities returned for
+//! every new session are compared against the requested ones and a session on the wrong
+//! platform or browser version fails the test.
+
+use anyhow::{bail, Result};
+use std::{collections::BTreeMap, sync::Mutex};
+use thirtyfour::{Capabilities, WebDriver};
+
+#[derive(Default)]
+pub struct Targeting {
+    platform: Option<String>,
+    /// Browser versions to run every test against, empty if the grid may choose
+    versions: Vec<String>,
+}
+
+impl Targeting {
+    /// Reads the requested platform from `PLATFORM` (`windows`, `linux` or `mac`) and a comma
+    /// separated list of browser versions from `BROWSER_VERSION`
+    pub fn from_env() -> Result<Self> {
+        let platform = match std::env::var("PLATFORM") {
+            Ok(platform) => match family(&platform) {
+                Some(family) => Some(family.to_owned()),
+                None => bail!("Unknown platform '{}'", platform),
+            },
+            Err(_) => None,
+        };
+
+        let versions = std::env::var("BROWSER_VERSION")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|version| !version.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        Ok(Self { platform, versions })
+    }
+
+    /// Number of browser versions each test is run against
+    pub fn matrix_size(&self) -> u64 {
+        self.versions.len().max(1) as u64
+    }
+
+    /// Browser version the test with the given index should run on
+    pub fn version(&self, index: u64) -> Option<&str> {
+        if self.versions.is_empty() {
+            None
+        } else {
+            Some(&self.versions[(index % self.versions.len() as u64) as usize])
+        }
+    }
+
+    pub fn apply(&self, caps: &mut impl Capabilities, version: Option<&str>) -> Result<()> {
+        if let Some(platform) = &self.platform {
+            caps.add("platformName", platform)?;
+        }
+
+        if let Some(version) = version {
+            caps.add("browserVersion", version)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the session does not run on the requested platform and browser version
+    pub fn verify(&self, driver: &WebDriver, version: Option<&str>) -> Result<()> {
+        let platform = platform(driver);
+
+        if let Some(requested) = &self.platform {
+            if family(&platform) != Some(requested.as_str()) {
+                bail!(
+                    "Grid provided platform '{}' instead of '{}'",
+                    platform,
+                    requested
+                );
+            }
+        }
+
+        if let Some(requested) = version {
+            let provided = browser_version(driver);
+            if !version_matches(&provided, requested) {
+                bail!(
+                    "Grid provided browser version '{}' instead of '{}'",
+                    provided,
+                    requested
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Platform a session actually runs on
+pub fn platform(driver: &WebDriver) -> String {
+    returned(driver, "platformName").unwrap_or_else(|| "unknown".into())
+}
+
+/// Browser version a session actually runs
+pub fn browser_version(driver: &WebDriver) -> String {
+    returned(driver, "browserVersion")
+        .or_else(|| returned(driver, "version"))
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Whether the full version provided by the grid (`118.0.5993.70`) satisfies a requested
+/// prefix (`118`). Symbolic versions like `latest` can not be checked and always match.
+fn version_matches(provided: &str, requested: &str) -> bool {
+    if !requested.starts_with(|c: char| c.is_ascii_digit()) {
+        return true;
+    }
+
+    provided == requested
+        || provided
+            .strip_prefix(requested)
+            .map_or(false, |rest| rest.starts_with('.'))
+}
+
+/// Value of a capability as returned by the grid when the session was created
+fn returned(driver: &WebDriver, key: &str) -> Option<String> {
+    driver
+        .capabilities()
+        .get(key)
+        .and_then(|value| value.as_str())
+        .map(str::to_owned)
+}
+
+/// Maps the many spellings of a platform (`WIN10`, `Mac OS X`, `LINUX`, …) to its W3C name
+fn family(platform: &str) -> Option<&'static str> {
+    let platform = platform.to_ascii_lowercase();
+
+    if platform.starts_with("win") {
+        Some("windows")
+    } else if platform.starts_with("mac") || platform == "darwin" {
+        Some("mac")
+    } else if platform == "linux" {
+        Some("linux")
+    } else {
+        None
+    }
+}
+
+/// Test outcomes grouped by the platform or browser version they ran on
+pub struct Breakdown {
+    label: &'static str,
+    results: Mutex<BTreeMap<String, (u64, u64)>>,
+}
+
+impl Breakdown {
+    pub fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            results: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, key: &str, success: bool) {
+        let mut results = self.results.lock().unwrap();
+        let (passed, failed) = results.entry(key.to_owned()).or_default();
+
+        if success {
+            *passed += 1;
+        } else {
+            *failed += 1;
+        }
+    }
+
+    /// Logs the outcomes per group, stays silent if all tests ended up in the same one
+    pub fn log_summary(&self) {
+        let results = self.results.lock().unwrap();
+        if results.len() < 2 {
+            return;
+        }
+
+        for (key, (passed, failed)) in results.iter() {
+            log::info!(
+                "{} {}: {} / {} succeeded.",
+                self.label,
+                key,
+                passed,
+                passed + failed
+            );
+        }
+    }
+}
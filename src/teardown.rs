@@ -0,0 +1,116 @@
+//! Teardown of the session of a test once it finished.
+//!
+//! By default sessions are simply quit. Grids that hand browsers to the next session may need them
+//! cleaned up first, by deleting cookies, leaving the page for `about:blank` or running scripts.
+//! Other grids are better left to time sessions out themselves. Once a session is gone, a hook
+//! can notify a cleanup API, e.g. to release the test data the session used.
+
+use crate::{flag::env_flag, timeouts::bounded};
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::time::Duration;
+use thirtyfour::prelude::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct Teardown {
+    /// Deletes all cookies before the session is quit
+    pub delete_cookies: bool,
+    /// Navigates to `about:blank` before the session is quit
+    pub blank: bool,
+    /// Scripts executed in the browser before the session is quit
+    pub scripts: Vec<String>,
+    /// Leaves the session to the timeout of the grid instead of quitting it. The runner answers
+    /// the quit through its traffic proxy instead of the grid, so the driver is still released.
+    pub skip_quit: bool,
+    /// URL receiving a POST for every session after it was quit, `{session_id}` is replaced by
+    /// the ID of the session
+    pub after_quit: Option<String>,
+}
+
+impl Teardown {
+    /// Adds the steps listed in `TEARDOWN` (`cookies`, `blank`), the scripts whose files are
+    /// listed in `TEARDOWN_SCRIPTS`, `SKIP_QUIT` and the `AFTER_QUIT` hook to the given teardown
+    pub fn from_env(mut teardown: Teardown) -> Result<Self> {
+        for step in std::env::var("TEARDOWN").unwrap_or_default().split(',') {
+            match step.trim() {
+                "cookies" => teardown.delete_cookies = true,
+                "blank" => teardown.blank = true,
+                "" => {}
+                other => bail!(
+                    "Unknown teardown step '{}', expected cookies or blank",
+                    other
+                ),
+            }
+        }
+
+        for path in std::env::var("TEARDOWN_SCRIPTS")
+            .unwrap_or_default()
+            .split(',')
+        {
+            let path = path.trim();
+            if !path.is_empty() {
+                let script = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read teardown script '{}'", path))?;
+                teardown.scripts.push(script);
+            }
+        }
+
+        teardown.skip_quit |= env_flag("SKIP_QUIT")?;
+        if teardown.after_quit.is_none() {
+            teardown.after_quit = std::env::var("AFTER_QUIT").ok();
+        }
+
+        Ok(teardown)
+    }
+
+    /// Cleans up and quits the session, failed steps are logged without failing the test. Every
+    /// command is bounded by `limit`.
+    pub(crate) async fn run(&self, driver: WebDriver, limit: Duration, client: &reqwest::Client) {
+        let session_id = driver.session_id().to_string();
+
+        if self.delete_cookies {
+            if let Err(e) = bounded(Some(limit), driver.delete_all_cookies(), || {
+                "Deleting cookies".to_owned()
+            })
+            .await
+            {
+                log::warn!("Failed to delete the cookies of {}: {}", session_id, e);
+            }
+        }
+        if self.blank {
+            if let Err(e) = bounded(Some(limit), driver.get("about:blank"), || {
+                "Navigation to about:blank".to_owned()
+            })
+            .await
+            {
+                log::warn!("Failed to leave the page of {}: {}", session_id, e);
+            }
+        }
+        for script in self.scripts.iter() {
+            if let Err(e) = bounded(Some(limit), driver.execute_script(script), || {
+                "Teardown script".to_owned()
+            })
+            .await
+            {
+                log::warn!("Teardown script failed on {}: {}", session_id, e);
+            }
+        }
+
+        // Never reaches the grid if the quit is skipped, see `skip_quit`
+        driver.quit().await.ok();
+
+        if let Some(url) = &self.after_quit {
+            let url = url.replace("{session_id}", &session_id);
+            let result = client
+                .post(&url)
+                .timeout(limit)
+                .json(&json!({ "session_id": session_id }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                log::warn!("After-quit hook of {} failed: {}", session_id, e);
+            }
+        }
+    }
+}
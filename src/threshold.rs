@@ -0,0 +1,159 @@
+//! Policy deciding how many failed tests a run tolerates before its exit code reports a failure.
+//!
+//! Without a policy a single failed test fails the run. Large load tests usually accept some
+//! failures instead, as an absolute number, as a share of all tests, or both (in which case the
+//! run has to stay within both). The summary reports the exact numbers either way.
+
+use anyhow::{bail, Context, Result};
+
+pub struct FailureThreshold {
+    max_failures: Option<u64>,
+    /// Share of the tests allowed to fail in percent
+    max_failure_rate: Option<f64>,
+}
+
+impl FailureThreshold {
+    /// Reads the thresholds from `MAX_FAILURES` and `MAX_FAILURE_RATE` unless given
+    pub fn from_env(max_failures: Option<u64>, max_failure_rate: Option<f64>) -> Result<Self> {
+        let max_failures = match max_failures {
+            Some(max_failures) => Some(max_failures),
+            None => match std::env::var("MAX_FAILURES") {
+                Ok(value) => Some(value.parse::<u64>().context("Invalid MAX_FAILURES")?),
+                Err(_) => None,
+            },
+        };
+        let max_failure_rate = match max_failure_rate {
+            Some(rate) => Some(validate_rate(rate)?),
+            None => match std::env::var("MAX_FAILURE_RATE") {
+                Ok(value) => Some(parse_failure_rate(&value).context("Invalid MAX_FAILURE_RATE")?),
+                Err(_) => None,
+            },
+        };
+
+        Ok(Self {
+            max_failures,
+            max_failure_rate,
+        })
+    }
+
+    /// Whether the failed tests stay within the thresholds, logs the verdict if any failed
+    pub fn tolerates(&self, failed: u64, total: u64) -> bool {
+        if failed == 0 {
+            return true;
+        }
+        if self.max_failures.is_none() && self.max_failure_rate.is_none() {
+            return false;
+        }
+
+        let rate = failed as f64 * 100.0 / total.max(1) as f64;
+        let within = self.max_failures.map_or(true, |max| failed <= max)
+            && self.max_failure_rate.map_or(true, |max| rate <= max);
+
+        let mut limits = Vec::new();
+        if let Some(max) = self.max_failures {
+            limits.push(format!("{} failures", max));
+        }
+        if let Some(max) = self.max_failure_rate {
+            limits.push(format!("a failure rate of {}%", max));
+        }
+        let verdict = if within { "within" } else { "beyond" };
+        log::info!(
+            "{} of {} tests failed ({:.2}%), {} the tolerated {}.",
+            failed,
+            total,
+            rate,
+            verdict,
+            limits.join(" and ")
+        );
+
+        within
+    }
+}
+
+/// Parses a failure rate in percent, with or without a trailing `%`
+pub fn parse_failure_rate(text: &str) -> Result<f64> {
+    let rate = text
+        .trim()
+        .trim_end_matches('%')
+        .trim_end()
+        .parse::<f64>()
+        .with_context(|| format!("Expected a percentage like 2%, got '{}'", text))?;
+
+    validate_rate(rate)
+}
+
+fn validate_rate(rate: f64) -> Result<f64> {
+    if !(0.0..=100.0).contains(&rate) {
+        bail!(
+            "The failure rate has to be a percentage between 0 and 100, got {}",
+            rate
+        );
+    }
+
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threshold(max_failures: Option<u64>, max_failure_rate: Option<f64>) -> FailureThreshold {
+        FailureThreshold {
+            max_failures,
+            max_failure_rate,
+        }
+    }
+
+    #[test]
+    fn without_a_policy_any_failure_fails_the_run() {
+        let threshold = threshold(None, None);
+        assert!(threshold.tolerates(0, 100));
+        assert!(!threshold.tolerates(1, 100));
+    }
+
+    #[test]
+    fn absolute_limit_is_inclusive() {
+        let threshold = threshold(Some(3), None);
+        assert!(threshold.tolerates(3, 10));
+        assert!(!threshold.tolerates(4, 1000));
+    }
+
+    #[test]
+    fn rate_limit_is_inclusive() {
+        let threshold = threshold(None, Some(2.0));
+        assert!(threshold.tolerates(2, 100));
+        assert!(!threshold.tolerates(3, 100));
+        // Failures without any finished test count as 100%
+        assert!(!threshold.tolerates(1, 0));
+    }
+
+    #[test]
+    fn both_limits_have_to_hold() {
+        let threshold = threshold(Some(5), Some(10.0));
+        assert!(threshold.tolerates(5, 100));
+        assert!(!threshold.tolerates(6, 100));
+        assert!(!threshold.tolerates(5, 20));
+    }
+
+    #[test]
+    fn zero_tolerance_equals_no_policy() {
+        assert!(!threshold(Some(0), None).tolerates(1, 100));
+        assert!(!threshold(None, Some(0.0)).tolerates(1, 100));
+        assert!(threshold(None, Some(0.0)).tolerates(0, 100));
+    }
+
+    #[test]
+    fn parses_rates_with_and_without_percent_sign() {
+        assert_eq!(parse_failure_rate("2").unwrap(), 2.0);
+        assert_eq!(parse_failure_rate(" 2.5 % ").unwrap(), 2.5);
+        assert_eq!(parse_failure_rate("100%").unwrap(), 100.0);
+        assert_eq!(parse_failure_rate("0%").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn rejects_rates_outside_of_percentages() {
+        for text in ["", "%", "two", "-1%", "100.5", "NaN"] {
+            assert!(parse_failure_rate(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Timeouts of individual steps, navigations and element lookups.
+//!
+//! `TIMEOUT` only bounds the requests of the WebDriver client, so a hung command surfaces as a
+//! generic failure long after the fact. These timeouts are enforced on the runner's side and
+//! name the step, URL or element that hung.
+
+use anyhow::{anyhow, Context, Error, Result};
+use humantime::format_duration;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time::{sleep, sleep_until, timeout};
+
+/// Interval at which the step timeout looks for a step to begin
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepTimeouts {
+    /// Bound of every step of a scenario
+    pub step: Option<Duration>,
+    /// Bound of every navigation to a URL
+    pub navigation: Option<Duration>,
+    /// Bound of every element lookup
+    pub lookup: Option<Duration>,
+}
+
+impl StepTimeouts {
+    /// Reads the timeouts that are not given from `STEP_TIMEOUT`, `NAVIGATION_TIMEOUT` and
+    /// `LOOKUP_TIMEOUT`, all of them are unbounded by default
+    pub fn from_env(
+        step: Option<Duration>,
+        navigation: Option<Duration>,
+        lookup: Option<Duration>,
+    ) -> Result<Self> {
+        let read = |given: Option<Duration>, variable: &str| -> Result<Option<Duration>> {
+            match (given, std::env::var(variable)) {
+                (Some(duration), _) => Ok(Some(duration)),
+                (None, Ok(value)) => Ok(Some(
+                    humantime::parse_duration(&value)
+                        .with_context(|| format!("Invalid {}", variable))?,
+                )),
+                (None, Err(_)) => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            step: read(step, "STEP_TIMEOUT")?,
+            navigation: read(navigation, "NAVIGATION_TIMEOUT")?,
+            lookup: read(lookup, "LOOKUP_TIMEOUT")?,
+        })
+    }
+}
+
+/// Runs a WebDriver command within the given timeout, `describe` names the command in the error
+pub(crate) async fn bounded<T, E>(
+    limit: Option<Duration>,
+    command: impl Future<Output = Result<T, E>>,
+    describe: impl FnOnce() -> String,
+) -> Result<T>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok(command.await?),
+    };
+
+    match timeout(limit, command).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(anyhow!(
+            "{} timed out after {}",
+            describe(),
+            format_duration(limit)
+        )),
+    }
+}
+
+/// Step a test is currently in and when it began, shared with the watcher of the step timeout
+#[derive(Clone, Default)]
+pub(crate) struct StepClock(Arc<Mutex<Option<(&'static str, Instant)>>>);
+
+impl StepClock {
+    pub fn set(&self, step: Option<(&'static str, Instant)>) {
+        *self.0.lock().unwrap() = step;
+    }
+
+    /// Resolves with an error once a step ran for longer than the timeout, never without one
+    pub async fn watch(&self, limit: Option<Duration>) -> Error {
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return std::future::pending().await,
+        };
+
+        loop {
+            let current = *self.0.lock().unwrap();
+            match current {
+                Some((step, began)) if began.elapsed() >= limit => {
+                    return anyhow!("Step {} timed out after {}", step, format_duration(limit));
+                }
+                // The step may have changed in the meantime, which is checked again after waking up
+                Some((_, began)) => sleep_until((began + limit).into()).await,
+                None => sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Values captured during a session for use by later steps.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use thirtyfour::{WebDriver, WebElement};
+
+/// Named values captured by earlier steps, referenced as `${name}` by later ones
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.values.insert(name.into(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Result<&str> {
+        self.values
+            .get(name)
+            .map(String::as_str)
+            .with_context(|| format!("Variable '{}' has not been captured", name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values.iter()
+    }
+
+    /// Stores the visible text of an element
+    pub async fn capture_text(&mut self, name: &str, element: &WebElement<'_>) -> Result<String> {
+        let text = element.text().await?;
+        self.set(name, text.clone());
+        Ok(text)
+    }
+
+    /// Stores the return value of a script, strings are stored verbatim and everything else as JSON
+    pub async fn capture_script(
+        &mut self,
+        name: &str,
+        driver: &WebDriver,
+        script: &str,
+    ) -> Result<String> {
+        let result = driver.execute_script(script).await?;
+        let value = match result.value() {
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+
+        self.set(name, value.clone());
+        Ok(value)
+    }
+
+    /// Replaces every `${name}` in the template with the captured value
+    pub fn interpolate(&self, template: &str) -> Result<String> {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("${") {
+            output.push_str(&rest[..start]);
+
+            let length = rest[start..]
+                .find('}')
+                .with_context(|| format!("Unterminated variable in '{}'", template))?;
+
+            output.push_str(self.get(&rest[start + 2..start + length])?);
+            rest = &rest[start + length + 1..];
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+}
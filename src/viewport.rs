@@ -0,0 +1,207 @@
+//! Random viewport sizes and device pixel ratios per session, which turn a parallel run into a
+//! cheap sweep of the responsive layout across the grid.
+//!
+//! Every session picks a size within the bounds and one of the pixel ratios. The pick is derived
+//! from the test the session is created for and the seed of the run, which is logged, so a rerun
+//! with the same seed picks the same viewports while other runs sweep different ones. Chrome
+//! emulates both exactly through its device metrics. Other browsers get their window resized,
+//! which includes their own toolbars, and Firefox the pixel ratio through a preference. Safari
+//! keeps its native pixel ratio.
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thirtyfour::{prelude::*, Capabilities, OptionRect};
+
+/// Lower bounds of the viewport widths the results are grouped by, as `(width, label)`
+const BREAKPOINTS: [(u32, &str); 4] = [
+    (1200, "wide (>= 1200px)"),
+    (992, "desktop (992-1199px)"),
+    (768, "tablet (768-991px)"),
+    (0, "phone (< 768px)"),
+];
+
+#[derive(Debug, Clone)]
+pub struct ViewportJitter {
+    min: (u32, u32),
+    max: (u32, u32),
+    pixel_ratios: Vec<f64>,
+    seed: u64,
+}
+
+impl ViewportJitter {
+    /// Parses bounds like `360x640..1920x1080`, at a device pixel ratio of 1 and a random seed
+    pub fn parse(bounds: &str) -> Result<Self> {
+        let size = |size: &str| -> Result<(u32, u32)> {
+            let (width, height) = size
+                .split_once('x')
+                .with_context(|| format!("Expected a size like 1280x720, got '{}'", size))?;
+            Ok((width.trim().parse()?, height.trim().parse()?))
+        };
+
+        let (min, max) = bounds.split_once("..").with_context(|| {
+            format!("Expected bounds like 360x640..1920x1080, got '{}'", bounds)
+        })?;
+        let (min, max) = (size(min.trim())?, size(max.trim())?);
+        if min.0 == 0 || min.1 == 0 || min.0 > max.0 || min.1 > max.1 {
+            bail!(
+                "The lower bound of the viewport has to be non-empty and below the upper one, got '{}'",
+                bounds
+            );
+        }
+
+        Ok(Self {
+            min,
+            max,
+            pixel_ratios: vec![1.0],
+            seed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+        })
+    }
+
+    /// Repeats the viewports of the run that logged this seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Picks one of the given device pixel ratios for every session
+    pub fn pixel_ratios(mut self, pixel_ratios: Vec<f64>) -> Result<Self> {
+        if pixel_ratios.iter().any(|ratio| !(*ratio > 0.0)) {
+            bail!("Device pixel ratios have to be above zero");
+        }
+        if !pixel_ratios.is_empty() {
+            self.pixel_ratios = pixel_ratios;
+        }
+
+        Ok(self)
+    }
+
+    /// Reads the bounds from `VIEWPORT_JITTER`, the pixel ratios from `PIXEL_RATIOS` (comma
+    /// separated) and the seed from `VIEWPORT_SEED` unless given, returns `None` if no bounds are
+    /// set
+    pub fn from_env(jitter: Option<Self>) -> Result<Option<Self>> {
+        if jitter.is_some() {
+            return Ok(jitter);
+        }
+
+        let bounds = match std::env::var("VIEWPORT_JITTER") {
+            Ok(bounds) => bounds,
+            Err(_) => return Ok(None),
+        };
+        let pixel_ratios = std::env::var("PIXEL_RATIOS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|ratio| !ratio.is_empty())
+            .map(|ratio| ratio.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid PIXEL_RATIOS")?;
+
+        let mut jitter = Self::parse(&bounds)
+            .context("Invalid VIEWPORT_JITTER")?
+            .pixel_ratios(pixel_ratios)
+            .context("Invalid PIXEL_RATIOS")?;
+        if let Ok(seed) = std::env::var("VIEWPORT_SEED") {
+            jitter = jitter.seed(seed.parse().context("Invalid VIEWPORT_SEED")?);
+        }
+        Ok(Some(jitter))
+    }
+
+    pub fn log(&self) {
+        log::info!(
+            "Jittering viewports between {}x{} and {}x{} with seed {}, set VIEWPORT_SEED to repeat them",
+            self.min.0,
+            self.min.1,
+            self.max.0,
+            self.max.1,
+            self.seed
+        );
+    }
+
+    /// Viewport of the session created for the given test
+    pub fn pick(&self, test: &str) -> Viewport {
+        // FNV-1a of the test, mixed into the seed of the run
+        let key = test.bytes().fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        // SplitMix64, so neighbouring tests end up far apart
+        let mut state = self.seed ^ key;
+        let mut next = |bound: u32| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            ((z ^ (z >> 31)) % bound as u64) as u32
+        };
+
+        Viewport {
+            width: self.min.0 + next(self.max.0 - self.min.0 + 1),
+            height: self.min.1 + next(self.max.1 - self.min.1 + 1),
+            pixel_ratio: self.pixel_ratios[next(self.pixel_ratios.len() as u32) as usize],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_ratio: f64,
+}
+
+impl Viewport {
+    /// Requests the viewport through the capabilities of browsers that support it
+    pub fn apply(&self, browser: &str, caps: &mut impl Capabilities) -> Result<()> {
+        match browser {
+            "chrome" => caps.add_subkey(
+                "goog:chromeOptions",
+                "mobileEmulation",
+                json!({
+                    "deviceMetrics": {
+                        "width": self.width,
+                        "height": self.height,
+                        "pixelRatio": self.pixel_ratio,
+                        "mobile": false,
+                        "touch": false,
+                    }
+                }),
+            )?,
+            "firefox" if self.pixel_ratio != 1.0 => caps.add_subkey(
+                "moz:firefoxOptions",
+                "prefs",
+                json!({ "layout.css.devPixelsPerPx": self.pixel_ratio.to_string() }),
+            )?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Resizes the window of browsers whose viewport can not be requested up front
+    pub async fn resize(&self, browser: &str, driver: &WebDriver) -> Result<()> {
+        if browser != "chrome" {
+            driver
+                .set_window_rect(OptionRect::new().with_size(self.width as i32, self.height as i32))
+                .await
+                .with_context(|| format!("Failed to resize the window to {}", self.label()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Size and pixel ratio like `1280x720@2x`
+    pub fn label(&self) -> String {
+        format!("{}x{}@{}x", self.width, self.height, self.pixel_ratio)
+    }
+
+    /// Range of widths the viewport falls into, the results are broken down by
+    pub fn breakpoint(&self) -> &'static str {
+        BREAKPOINTS
+            .iter()
+            .find(|(width, _)| self.width >= *width)
+            .map_or("", |(_, label)| label)
+    }
+}
@@ -0,0 +1,70 @@
+//! Watchdog for uncaught JavaScript errors and unhandled promise rejections of the page under
+//! test, which can break a page for users even though every assertion still passes.
+//!
+//! Listeners are installed whenever a step begins, and the errors they caught are checked when
+//! the next step begins, so every error is attributed to the step it happened in. Errors thrown
+//! while a page is still loading, before the listeners could be installed, are not seen.
+
+use anyhow::{bail, Result};
+use thirtyfour::WebDriver;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchdog {
+    Off,
+    /// Logs a warning for every step with page errors
+    Flag,
+    /// Fails the test in the step the page errors happened in
+    Fail,
+}
+
+impl Watchdog {
+    /// Reads the mode from `JS_ERRORS` (`off`, `flag` or `fail`), defaults to `flag`
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("JS_ERRORS").as_deref() {
+            Ok("flag") | Err(_) => Ok(Watchdog::Flag),
+            Ok("fail") => Ok(Watchdog::Fail),
+            Ok("off") => Ok(Watchdog::Off),
+            Ok(other) => bail!("Unknown page error mode '{}'", other),
+        }
+    }
+
+    /// Installs the listeners on the current page unless they already are
+    pub async fn arm(&self, driver: &WebDriver) {
+        if *self == Watchdog::Off {
+            return;
+        }
+
+        let script = r#"
+            if (!window.__pageErrors) {
+                window.__pageErrors = [];
+                window.addEventListener('error', e => window.__pageErrors.push(String(e.message)));
+                window.addEventListener('unhandledrejection', e =>
+                    window.__pageErrors.push('Unhandled rejection: ' + String(e.reason)));
+            }
+        "#;
+
+        if let Err(e) = driver.execute_script(script).await {
+            log::debug!("Failed to install page error listeners: {}", e);
+        }
+    }
+
+    /// Returns and forgets the errors caught since the last check
+    pub async fn collect(&self, driver: &WebDriver) -> Vec<String> {
+        if *self == Watchdog::Off {
+            return Vec::new();
+        }
+
+        let script = r#"
+            const errors = window.__pageErrors || [];
+            if (window.__pageErrors) {
+                window.__pageErrors = [];
+            }
+            return errors;
+        "#;
+
+        match driver.execute_script(script).await {
+            Ok(result) => serde_json::from_value(result.value().clone()).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+}